@@ -1,7 +1,7 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
-use arc_swap::{ArcSwap, ArcSwapOption, Cache};
+use arc_swap::{ArcSwap, ArcSwapOption, Cache, IndependentArcSwap};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use crossbeam_utils::thread;
 use once_cell::sync::Lazy;
@@ -104,7 +104,7 @@ macro_rules! strategy {
 
             fn write() {
                 for _ in 0..ITERS {
-                    black_box(A.store(Arc::new(0)));
+                    A.store(Arc::new(0));
                 }
             }
 
@@ -121,6 +121,7 @@ macro_rules! strategy {
 }
 
 strategy!(arc_swap_b, ArcSwap::<usize>);
+strategy!(arc_swap_independent, IndependentArcSwap::<usize>);
 
 mod arc_swap_option {
     use super::{black_box, ArcSwapOption, Criterion, Lazy};
@@ -142,7 +143,7 @@ mod arc_swap_option {
 
     fn write() {
         for _ in 0..ITERS {
-            black_box(A.store(Some(Arc::new(0))));
+            A.store(Some(Arc::new(0)));
         }
     }
 
@@ -176,7 +177,7 @@ mod arc_swap_cached {
 
     fn write() {
         for _ in 0..ITERS {
-            black_box(A.store(Arc::new(0)));
+            A.store(Arc::new(0));
         }
     }
 
@@ -208,7 +209,7 @@ mod mutex {
 
     fn write() {
         for _ in 0..ITERS {
-            black_box(*M.lock().unwrap() = Arc::new(42));
+            *M.lock().unwrap() = Arc::new(42);
         }
     }
 
@@ -242,7 +243,7 @@ mod parking_mutex {
 
     fn write() {
         for _ in 0..ITERS {
-            black_box(*M.lock() = Arc::new(42));
+            *M.lock() = Arc::new(42);
         }
     }
 
@@ -276,7 +277,7 @@ mod rwlock {
 
     fn write() {
         for _ in 0..ITERS {
-            black_box(*L.write().unwrap() = Arc::new(42));
+            *L.write().unwrap() = Arc::new(42);
         }
     }
 
@@ -310,7 +311,7 @@ mod parking_rwlock {
 
     fn write() {
         for _ in 0..ITERS {
-            black_box(*L.write() = Arc::new(42));
+            *L.write() = Arc::new(42);
         }
     }
 
@@ -325,6 +326,7 @@ mod parking_rwlock {
 criterion_group!(
     benches,
     arc_swap_b::run_all,
+    arc_swap_independent::run_all,
     arc_swap_option::run_all,
     arc_swap_cached::run_all,
     mutex::run_all,
@@ -37,7 +37,7 @@ fn test_run<R, W>(
         for _ in 0..write_threads {
             scope.spawn(|_| {
                 for i in 0..iterations {
-                    black_box(w(i));
+                    w(i);
                 }
             });
         }
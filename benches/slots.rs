@@ -0,0 +1,84 @@
+//! The trade-off of the number of fast slots.
+//!
+//! More slots make holding many guards at once faster (they don't fall back to the slower path),
+//! but the writers have to check all the slots of all the threads.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier};
+
+use arc_swap::strategy::hybrid::{Config, DefaultConfig, HybridStrategy};
+use arc_swap::strategy::Strategy;
+use arc_swap::ArcSwapAny;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use crossbeam_utils::thread;
+
+const HELD: usize = 24;
+const THREADS: usize = 8;
+
+#[derive(Default)]
+struct Slots32;
+
+impl Config for Slots32 {
+    const FAST_SLOTS: usize = 32;
+}
+
+type Shared<Cfg> = ArcSwapAny<Arc<usize>, HybridStrategy<Cfg>>;
+
+fn batch<Cfg>(c: &mut Criterion, name: &str)
+where
+    Cfg: Config + Default + Send + Sync,
+    HybridStrategy<Cfg>: Strategy<Arc<usize>>,
+{
+    let mut g = c.benchmark_group(name);
+    let shared = Shared::<Cfg>::from(Arc::new(42));
+
+    g.bench_function("load_many", |b| {
+        let mut guards = Vec::with_capacity(HELD);
+        b.iter(|| {
+            guards.push(black_box(shared.load()));
+            if guards.len() == HELD {
+                guards.clear();
+            }
+        })
+    });
+    g.bench_function("store_alone", |b| {
+        b.iter(|| {
+            shared.store(Arc::new(42));
+        })
+    });
+
+    // Other threads that have used (and still hold) their slots, making the writer check them.
+    let stop = AtomicBool::new(false);
+    let ready = Barrier::new(THREADS + 1);
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|_| {
+                let other = Shared::<Cfg>::from(Arc::new(0));
+                let guards = (0..HELD).map(|_| other.load()).collect::<Vec<_>>();
+                ready.wait();
+                while !stop.load(Ordering::Relaxed) {
+                    std::thread::yield_now();
+                }
+                drop(guards);
+            });
+        }
+        ready.wait();
+        g.bench_function("store_readers", |b| {
+            b.iter(|| {
+                shared.store(Arc::new(42));
+            })
+        });
+        stop.store(true, Ordering::Relaxed);
+    })
+    .unwrap();
+
+    g.finish();
+}
+
+fn benchmarks(c: &mut Criterion) {
+    batch::<DefaultConfig>(c, "slots_8");
+    batch::<Slots32>(c, "slots_32");
+}
+
+criterion_group!(slots, benchmarks);
+criterion_main!(slots);
@@ -8,6 +8,7 @@ use std::sync::Arc;
 
 use arc_swap::access::{Access, Map};
 use arc_swap::cache::Cache;
+use arc_swap::nodes::ReaderHandle;
 use arc_swap::ArcSwap;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use crossbeam_utils::thread;
@@ -23,6 +24,12 @@ fn batch(c: &mut Criterion, name: &str, shared_number: &ArcSwap<usize>) {
             black_box(shared_number.load());
         })
     });
+    g.bench_function("load_with", |b| {
+        let handle = ReaderHandle::new();
+        b.iter(|| {
+            black_box(shared_number.load_with(&handle));
+        })
+    });
     g.bench_function("load_full", |b| {
         b.iter(|| {
             black_box(shared_number.load_full());
@@ -41,7 +48,7 @@ fn batch(c: &mut Criterion, name: &str, shared_number: &ArcSwap<usize>) {
     });
     g.bench_function("store", |b| {
         b.iter(|| {
-            black_box(shared_number.store(Arc::new(42)));
+            shared_number.store(Arc::new(42));
         })
     });
     g.bench_function("cache", |b| {
@@ -104,7 +111,7 @@ fn benchmark(c: &mut Criterion) {
         black_box(s.load());
     });
     with_background(c, "concurrent_store", 1, |s| {
-        black_box(s.store(Arc::new(42)));
+        s.store(Arc::new(42));
     });
     utilities(c);
 }
@@ -29,7 +29,8 @@
 //!
 //! Note that the [`Access`] trait is also implemented for [`ArcSwapAny`] itself. Additionally,
 //! there's the [`Constant`][crate::access::Constant] helper type, which is useful mostly for
-//! testing (it doesn't allow reloading).
+//! testing (it doesn't allow reloading), and the [`WithHandle`], loading through a
+//! [`ReaderHandle`].
 //!
 //! # Performance
 //!
@@ -84,11 +85,13 @@
 //! work_with_usize(Constant(42)).join().unwrap();
 //! ```
 
-use std::marker::PhantomData;
-use std::ops::Deref;
-use std::rc::Rc;
-use std::sync::Arc;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use core::marker::PhantomData;
+use core::ops::Deref;
 
+use super::nodes::ReaderHandle;
 use super::ref_cnt::RefCnt;
 use super::strategy::Strategy;
 use super::{ArcSwapAny, Guard};
@@ -153,7 +156,7 @@ impl<T: RefCnt, S: Strategy<T>> Access<T> for ArcSwapAny<T, S> {
 
 #[derive(Debug)]
 #[doc(hidden)]
-pub struct DirectDeref<T: RefCnt, S: Strategy<T>>(Guard<T, S>);
+pub struct DirectDeref<T: RefCnt, S: Strategy<T>>(pub(crate) Guard<T, S>);
 
 impl<T, S: Strategy<Arc<T>>> Deref for DirectDeref<Arc<T>, S> {
     type Target = T;
@@ -390,6 +393,52 @@ impl<T: Clone> Access<T> for Constant<T> {
     }
 }
 
+/// Access to an [`ArcSwapAny`] through a [`ReaderHandle`].
+///
+/// The loads go through [`load_with`][ArcSwapAny::load_with], so they don't look up the node of
+/// the current thread. Wrapping it in a [`Map`] keeps that property.
+pub struct WithHandle<'a, T: RefCnt, S: Strategy<T>> {
+    arc_swap: &'a ArcSwapAny<T, S>,
+    handle: &'a ReaderHandle,
+}
+
+impl<'a, T: RefCnt, S: Strategy<T>> WithHandle<'a, T, S> {
+    /// Creates the access.
+    pub fn new(arc_swap: &'a ArcSwapAny<T, S>, handle: &'a ReaderHandle) -> Self {
+        WithHandle { arc_swap, handle }
+    }
+}
+
+impl<T: RefCnt, S: Strategy<T>> Clone for WithHandle<'_, T, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: RefCnt, S: Strategy<T>> Copy for WithHandle<'_, T, S> {}
+
+impl<T: RefCnt, S: Strategy<T>> Access<T> for WithHandle<'_, T, S> {
+    type Guard = Guard<T, S>;
+
+    fn load(&self) -> Self::Guard {
+        self.arc_swap.load_with(self.handle)
+    }
+}
+
+impl<T, S: Strategy<Arc<T>>> Access<T> for WithHandle<'_, Arc<T>, S> {
+    type Guard = DirectDeref<Arc<T>, S>;
+    fn load(&self) -> Self::Guard {
+        DirectDeref(self.arc_swap.load_with(self.handle))
+    }
+}
+
+impl<T, S: Strategy<Rc<T>>> Access<T> for WithHandle<'_, Rc<T>, S> {
+    type Guard = DirectDeref<Rc<T>, S>;
+    fn load(&self) -> Self::Guard {
+        DirectDeref(self.arc_swap.load_with(self.handle))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::{ArcSwap, ArcSwapOption};
@@ -514,6 +563,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::arc_with_non_send_sync)]
     fn double_dyn_access_complex() {
         struct Inner {
             val: usize,
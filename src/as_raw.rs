@@ -1,3 +1,4 @@
+use super::local::LocalGuard;
 use super::{Guard, RefCnt};
 
 mod sealed {
@@ -23,7 +24,7 @@ use self::sealed::Sealed;
 /// let shared = ArcSwapOption::from(Some(Arc::clone(&a)));
 ///
 /// shared.compare_and_swap(&a, Some(Arc::clone(&a)));
-/// shared.compare_and_swap(&None::<Arc<_>>, Some(Arc::clone(&a)));
+/// shared.compare_and_swap(&None::<Arc<i32>>, Some(Arc::clone(&a)));
 /// shared.compare_and_swap(shared.load(), Some(Arc::clone(&a)));
 /// shared.compare_and_swap(&shared.load(), Some(Arc::clone(&a)));
 /// shared.compare_and_swap(ptr::null(), Some(Arc::clone(&a)));
@@ -31,20 +32,20 @@ use self::sealed::Sealed;
 ///
 /// Due to technical limitation, this is not implemented for owned `Arc`/`Option<Arc<_>>`, they
 /// need to be borrowed.
-pub trait AsRaw<T>: Sealed {
+pub trait AsRaw<T: ?Sized>: Sealed {
     /// Converts the value into a raw pointer.
     fn as_raw(&self) -> *mut T;
 }
 
-impl<'a, T: RefCnt> Sealed for &'a T {}
-impl<'a, T: RefCnt> AsRaw<T::Base> for &'a T {
+impl<T: RefCnt> Sealed for &T {}
+impl<T: RefCnt> AsRaw<T::Base> for &T {
     fn as_raw(&self) -> *mut T::Base {
         T::as_ptr(self)
     }
 }
 
-impl<'a, T: RefCnt> Sealed for &'a Guard<T> {}
-impl<'a, T: RefCnt> AsRaw<T::Base> for &'a Guard<T> {
+impl<T: RefCnt> Sealed for &Guard<T> {}
+impl<T: RefCnt> AsRaw<T::Base> for &Guard<T> {
     fn as_raw(&self) -> *mut T::Base {
         T::as_ptr(self)
     }
@@ -57,15 +58,29 @@ impl<T: RefCnt> AsRaw<T::Base> for Guard<T> {
     }
 }
 
-impl<T> Sealed for *mut T {}
-impl<T> AsRaw<T> for *mut T {
+impl<T: RefCnt> Sealed for &LocalGuard<T> {}
+impl<T: RefCnt> AsRaw<T::Base> for &LocalGuard<T> {
+    fn as_raw(&self) -> *mut T::Base {
+        T::as_ptr(self)
+    }
+}
+
+impl<T: RefCnt> Sealed for LocalGuard<T> {}
+impl<T: RefCnt> AsRaw<T::Base> for LocalGuard<T> {
+    fn as_raw(&self) -> *mut T::Base {
+        T::as_ptr(self)
+    }
+}
+
+impl<T: ?Sized> Sealed for *mut T {}
+impl<T: ?Sized> AsRaw<T> for *mut T {
     fn as_raw(&self) -> *mut T {
         *self
     }
 }
 
-impl<T> Sealed for *const T {}
-impl<T> AsRaw<T> for *const T {
+impl<T: ?Sized> Sealed for *const T {}
+impl<T: ?Sized> AsRaw<T> for *const T {
     fn as_raw(&self) -> *mut T {
         *self as *mut T
     }
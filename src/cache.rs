@@ -6,11 +6,13 @@
 //!
 //! [Arc]: std::sync::Arc
 
-use std::ops::Deref;
-use std::sync::atomic::Ordering;
+use core::ops::Deref;
+use core::sync::atomic::Ordering;
 
+use super::nodes::ReaderHandle;
 use super::ref_cnt::RefCnt;
 use super::strategy::Strategy;
+use super::thin::Owned;
 use super::ArcSwapAny;
 
 /// Generalization of caches providing access to `T`.
@@ -20,7 +22,7 @@ use super::ArcSwapAny;
 /// a part of it.
 ///
 /// See the example at [`Cache::map`].
-pub trait Access<T> {
+pub trait Access<T: ?Sized> {
     /// Loads the value from cache.
     ///
     /// This revalidates the value in the cache, then provides the access to the cached value.
@@ -109,7 +111,7 @@ pub trait Access<T> {
 #[derive(Clone, Debug)]
 pub struct Cache<A, T> {
     arc_swap: A,
-    cached: T,
+    cached: Owned<T>,
 }
 
 impl<A, T, S> Cache<A, T>
@@ -128,7 +130,7 @@ where
     /// [`ArcSwapOption`]: crate::ArcSwapOption
     /// [`ArcSwap`]: crate::ArcSwap
     pub fn new(arc_swap: A) -> Self {
-        let cached = arc_swap.load_full();
+        let cached = arc_swap.load_owned(None);
         Self { arc_swap, cached }
     }
 
@@ -146,24 +148,34 @@ where
     /// stored in the cache and returned.
     #[inline]
     pub fn load(&mut self) -> &T {
-        self.revalidate();
+        self.revalidate(None);
+        self.load_no_revalidate()
+    }
+
+    /// Loads the currently held value, refreshing it through the handle if needed.
+    ///
+    /// The same as [`load`][Cache::load], but if the underlying storage needs to be accessed, it
+    /// uses the node of the [`ReaderHandle`] instead of the current thread's one.
+    #[inline]
+    pub fn load_with(&mut self, handle: &ReaderHandle) -> &T {
+        self.revalidate(Some(handle));
         self.load_no_revalidate()
     }
 
     #[inline]
     fn load_no_revalidate(&self) -> &T {
-        &self.cached
+        self.cached.get()
     }
 
     #[inline]
-    fn revalidate(&mut self) {
-        let cached_ptr = RefCnt::as_ptr(&self.cached);
+    fn revalidate(&mut self, handle: Option<&ReaderHandle>) {
+        let cached_ptr = self.cached.word();
         // Node: Relaxed here is fine. We do not synchronize any data through this, we already have
         // it synchronized in self.cache. We just want to check if it changed, if it did, the
         // load_full will be responsible for any synchronization needed.
         let shared_ptr = self.arc_swap.ptr.load(Ordering::Relaxed);
         if cached_ptr != shared_ptr {
-            self.cached = self.arc_swap.load_full();
+            self.cached = self.arc_swap.load_owned(handle);
         }
     }
 
@@ -218,6 +230,7 @@ where
     pub fn map<F, U>(self, f: F) -> MapCache<A, T, F>
     where
         F: FnMut(&T) -> &U,
+        U: ?Sized,
     {
         MapCache {
             inner: self,
@@ -229,7 +242,7 @@ where
 impl<A, T, S> Access<T::Target> for Cache<A, T>
 where
     A: Deref<Target = ArcSwapAny<T, S>>,
-    T: Deref<Target = <T as RefCnt>::Base> + RefCnt,
+    T: Deref + RefCnt,
     S: Strategy<T>,
 {
     fn load(&mut self) -> &T::Target {
@@ -264,6 +277,7 @@ where
     T: RefCnt,
     S: Strategy<T>,
     F: FnMut(&T) -> &U,
+    U: ?Sized,
 {
     fn load(&mut self) -> &U {
         (self.projection)(self.inner.load())
@@ -272,6 +286,7 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::mem;
     use std::sync::Arc;
 
     use super::*;
@@ -310,6 +325,47 @@ mod tests {
         assert!(c.load().is_none());
     }
 
+    #[test]
+    fn cache_unsized() {
+        let a = ArcSwap::<str>::from(Arc::from("hello"));
+        let mut c = Cache::new(&a);
+
+        assert_eq!("hello", &**c.load());
+        let cached = Arc::clone(c.load());
+        a.store(Arc::clone(&cached));
+        // The same value in a different place, reloaded.
+        assert_eq!("hello", &**c.load());
+        a.store(Arc::from("world"));
+        assert_eq!("world", &**c.load());
+        assert_eq!(1, Arc::strong_count(&cached));
+    }
+
+    #[test]
+    fn cache_unsized_option() {
+        let a = ArcSwapOption::<str>::from(None);
+        let mut c = Cache::new(&a);
+
+        assert!(c.load().is_none());
+        let cloned = c.clone();
+        a.store(Some(Arc::from("hello")));
+        assert_eq!("hello", &**c.load().as_ref().unwrap());
+        let hello = c.clone();
+        a.store(None);
+        assert!(c.load().is_none());
+        // The clones hold their own values.
+        assert!(cloned.load_no_revalidate().is_none());
+        assert_eq!("hello", &**hello.load_no_revalidate().as_ref().unwrap());
+    }
+
+    /// The cache is no bigger than the handle and the value.
+    #[test]
+    fn cache_size() {
+        assert_eq!(
+            2 * mem::size_of::<usize>(),
+            mem::size_of::<Cache<&ArcSwap<usize>, Arc<usize>>>()
+        );
+    }
+
     struct Inner {
         answer: usize,
     }
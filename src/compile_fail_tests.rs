@@ -91,3 +91,45 @@
 //! let a: ArcSwapAny<Rc<usize>> = ArcSwapAny::new(Rc::new(42));
 //! std::thread::spawn(move || drop(a));
 //! ```
+//!
+//! The `LocalArcSwapAny` can be sent (if the content can), but not shared.
+//! ```rust
+//! use std::sync::Arc;
+//! use arc_swap::local::LocalArcSwapAny;
+//!
+//! let a = LocalArcSwapAny::new(Arc::new(42));
+//! std::thread::spawn(move || drop(a)).join().unwrap();
+//! ```
+//!
+//! ```rust,compile_fail
+//! use std::sync::Arc;
+//! use arc_swap::local::LocalArcSwapAny;
+//!
+//! let a = LocalArcSwapAny::new(Arc::new(42));
+//! crossbeam_utils::thread::scope(|scope| {
+//!     scope.spawn(|_| {
+//!         let _ = a.load();
+//!     });
+//! }).unwrap();
+//! ```
+//!
+//! The `ReaderHandle` can be moved to another thread, but not shared.
+//! ```rust
+//! use arc_swap::nodes::ReaderHandle;
+//!
+//! let handle = ReaderHandle::new();
+//! std::thread::spawn(move || drop(handle)).join().unwrap();
+//! ```
+//!
+//! ```rust,compile_fail
+//! use arc_swap::ArcSwap;
+//! use arc_swap::nodes::ReaderHandle;
+//!
+//! let shared = ArcSwap::from_pointee(42);
+//! let handle = ReaderHandle::new();
+//! crossbeam_utils::thread::scope(|scope| {
+//!     scope.spawn(|_| {
+//!         let _ = shared.load_with(&handle);
+//!     });
+//! }).unwrap();
+//! ```
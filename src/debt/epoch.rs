@@ -0,0 +1,174 @@
+//! Waiting for everyone who might have seen an old state.
+//!
+//! The readers [pin](Epoch::pin) the current epoch for the duration of their access. A writer
+//! first changes the state (eg. unlinks a node or replaces a pointer), then moves to the next epoch
+//! and waits until nobody is pinned in the previous one. Whoever pins after the move is guaranteed
+//! to see the changed state.
+//!
+//! There are two kinds of pins. A reader owning a [`LocalPin`] (eg. in its own debt node) just
+//! stores the epoch there, which touches no shared cache line. The writer has to check all of
+//! these (it needs to know where they are). The others share counters. Only the counters of the
+//! current and previous epoch are needed, so the odd and even ones share them. These are more
+//! expensive (two read-modify-write operations on a shared cache line for each pin), so they are
+//! meant for the rare paths.
+//!
+//! The writers must be serialized and must not move to yet another epoch until the previous one
+//! is [left](Epoch::left) (otherwise a writer could move two epochs further and wait for the new
+//! readers).
+//!
+//! Pinning is lock-free, the writer waits only for the readers that came before it. It can also
+//! just check if the previous epoch was left already and if not, try again later.
+
+use core::sync::atomic::Ordering::*;
+
+use crate::spin;
+use crate::sync::{self, AtomicUsize};
+
+pub(crate) struct Epoch {
+    epoch: AtomicUsize,
+    /// Number of readers pinned in an odd and even epoch.
+    pinned: [AtomicUsize; 2],
+}
+
+impl Epoch {
+    const_fn! {
+        pub(crate) fn new() -> Self {
+            Epoch {
+                epoch: AtomicUsize::new(0),
+                pinned: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            }
+        }
+    }
+
+    /// Pins the current epoch, until the returned value is dropped.
+    pub(crate) fn pin(&self) -> Pinned<'_> {
+        loop {
+            let epoch = self.epoch.load(SeqCst);
+            let pinned = &self.pinned[epoch % 2];
+            pinned.fetch_add(1, SeqCst);
+            sync::loom_fence();
+            // If the epoch moved in the meantime, the writer might have already checked our
+            // counter. Try again with the new one.
+            if self.epoch.load(SeqCst) == epoch {
+                return Pinned::Shared(pinned);
+            }
+            pinned.fetch_sub(1, SeqCst);
+        }
+    }
+
+    /// Pins the current epoch in the reader's own pin, until the returned value is dropped.
+    ///
+    /// If the pin is already pinned (higher up the stack), it is left as it is.
+    pub(crate) fn pin_local<'a>(&self, local: &'a LocalPin) -> Pinned<'a> {
+        // Only the owner writes into it.
+        if local.0.load(Relaxed) != LocalPin::UNPINNED {
+            return Pinned::Nested;
+        }
+        // Unlike with the counters, no need to check the epoch again. If the writer already
+        // checked the pin, we see its changes in whatever we do next. If not, it'll see the (maybe
+        // old) epoch and wait for us.
+        let epoch = self.epoch.load(SeqCst);
+        local.0.store(epoch + 1, SeqCst);
+        sync::loom_fence();
+        Pinned::Local(&local.0)
+    }
+
+    /// Is anyone pinned in the shared counters right now?
+    pub(crate) fn is_pinned(&self) -> bool {
+        self.pinned.iter().any(|pinned| pinned.load(SeqCst) != 0)
+    }
+
+    /// Forgets all the pinned readers.
+    ///
+    /// # Safety
+    ///
+    /// The readers must no longer exist (eg. the threads that are gone after a `fork`).
+    #[cfg(unix)]
+    pub(crate) unsafe fn reset(&self) {
+        for pinned in &self.pinned {
+            pinned.store(0, SeqCst);
+        }
+    }
+
+    /// Moves to the next epoch.
+    ///
+    /// Returns the previous one, to check with [`left`][Epoch::left]. The callers must not call
+    /// this concurrently, nor before the previous epoch is left.
+    pub(crate) fn advance(&self) -> usize {
+        let epoch = self.epoch.fetch_add(1, SeqCst);
+        sync::loom_fence();
+        epoch
+    }
+
+    /// Moves to the next epoch and waits for the readers pinned in the shared counters in the
+    /// previous one.
+    ///
+    /// The callers must not call this concurrently.
+    pub(crate) fn synchronize(&self) {
+        let epoch = self.advance();
+        let mut attempt = 0;
+        while !self.left(epoch) {
+            spin::relax(attempt);
+            attempt += 1;
+        }
+    }
+
+    /// Did everyone pinned in the shared counters in the given (previous) epoch leave?
+    pub(crate) fn left(&self, epoch: usize) -> bool {
+        self.pinned[epoch % 2].load(SeqCst) == 0
+    }
+
+    /// Did the owner of the local pin leave the given (previous) epoch?
+    pub(crate) fn left_local(&self, epoch: usize, local: &LocalPin) -> bool {
+        let pinned = local.0.load(SeqCst);
+        pinned == LocalPin::UNPINNED || pinned > epoch + 1
+    }
+}
+
+impl Default for Epoch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A pin owned by a single reader at a time.
+///
+/// Holds the pinned epoch + 1, so 0 means not pinned.
+pub(crate) struct LocalPin(AtomicUsize);
+
+impl LocalPin {
+    const UNPINNED: usize = 0;
+
+    pub(crate) fn new() -> Self {
+        LocalPin(AtomicUsize::new(Self::UNPINNED))
+    }
+
+    /// Forgets the pinned owner.
+    ///
+    /// # Safety
+    ///
+    /// The owner must no longer exist (eg. a thread that is gone after a `fork`).
+    #[cfg(unix)]
+    pub(crate) unsafe fn reset(&self) {
+        self.0.store(Self::UNPINNED, SeqCst);
+    }
+}
+
+/// A pinned epoch, see [`Epoch::pin`].
+pub(crate) enum Pinned<'a> {
+    Shared(&'a AtomicUsize),
+    Local(&'a AtomicUsize),
+    Nested,
+}
+
+impl Drop for Pinned<'_> {
+    fn drop(&mut self) {
+        match self {
+            Pinned::Shared(pinned) => {
+                pinned.fetch_sub(1, SeqCst);
+            }
+            Pinned::Local(pinned) => pinned.store(LocalPin::UNPINNED, SeqCst),
+            Pinned::Nested => (),
+        }
+    }
+}
@@ -14,13 +14,18 @@
 //! before the change and before any cleanup of the old pointer happened (in which case we know the
 //! writer will see our debt).
 
-use std::cell::Cell;
-use std::slice::Iter;
-use std::sync::atomic::Ordering::*;
+use alloc::boxed::Box;
+use core::cell::Cell;
+use core::slice;
+use core::sync::atomic::Ordering::*;
 
-use super::Debt;
+use super::{allocating, Debt};
+use crate::sync::{self, AtomicPtr, AtomicUsize};
 
-const DEBT_SLOT_CNT: usize = 8;
+/// The number of slots every node has (and the default number a thread uses).
+///
+/// The strategies configured to use more get them in additional chunks of the same size.
+pub(crate) const DEBT_SLOT_CNT: usize = 8;
 
 /// Thread-local information for the [`Slots`]
 #[derive(Default)]
@@ -31,46 +36,174 @@ pub(super) struct Local {
     offset: Cell<usize>,
 }
 
+impl Local {
+    pub(super) const fn new() -> Self {
+        Local {
+            offset: Cell::new(0),
+        }
+    }
+}
+
+/// Additional slots, above the basic ones.
+///
+/// Only the owner of the node adds them, on demand. They are never removed while the node lives,
+/// so they are safe to access by anyone who can access the node.
+#[derive(Default)]
+struct Chunk {
+    slots: [Debt; DEBT_SLOT_CNT],
+    next: AtomicPtr<Chunk>,
+}
+
 /// Bunch of fast debt slots.
 #[derive(Default)]
-pub(super) struct Slots([Debt; DEBT_SLOT_CNT]);
+pub(super) struct Slots {
+    base: [Debt; DEBT_SLOT_CNT],
+    more: AtomicPtr<Chunk>,
+    /// Number of the chunks in `more`.
+    ///
+    /// Only the owner of the node uses it, so it doesn't have to walk the chunks to find out.
+    chunks: AtomicUsize,
+}
 
 impl Slots {
     /// Try to allocate one slot and get the pointer in it.
     ///
-    /// Fails if there are no free slots.
+    /// Only the first `limit` slots are considered (more are added if needed). Fails if there are
+    /// no free slots.
     #[inline]
-    pub(super) fn get_debt(&self, ptr: usize, local: &Local) -> Option<&Debt> {
+    pub(super) fn get_debt(&self, ptr: usize, local: &Local, limit: usize) -> Option<&Debt> {
+        if limit > DEBT_SLOT_CNT {
+            return self.get_debt_more(ptr, local, limit);
+        }
         // Trick with offsets: we rotate through the slots (save the value from last time)
         // so successive leases are likely to succeed on the first attempt (or soon after)
         // instead of going through the list of already held ones.
         let offset = local.offset.get();
-        let len = self.0.len();
+        let len = limit;
         for i in 0..len {
             let i = (i + offset) % len;
             // Note: the indexing check is almost certainly optimised out because the len
             // is used above. And using .get_unchecked was actually *slower*.
-            let slot = &self.0[i];
-            if slot.0.load(Relaxed) == Debt::NONE {
-                // We are allowed to split into the check and acquiring the debt. That's because we
-                // are the only ones allowed to change NONE to something else. But we still need a
-                // read-write operation wit SeqCst on it :-(
-                let old = slot.0.swap(ptr, SeqCst);
-                debug_assert_eq!(Debt::NONE, old);
+            let slot = &self.base[i];
+            if Self::acquire(slot, ptr) {
                 local.offset.set(i + 1);
-                return Some(&self.0[i]);
+                return Some(slot);
             }
         }
         None
     }
+
+    /// The [`get_debt`][Slots::get_debt] for configurations with more slots than the basic ones.
+    #[inline]
+    fn get_debt_more(&self, ptr: usize, local: &Local, limit: usize) -> Option<&Debt> {
+        // Relaxed: only we (the owner) change it.
+        let available = (self.chunks.load(Relaxed) + 1) * DEBT_SLOT_CNT;
+        let len = available.min(limit);
+        // The same rotation as above, but without the random access into the chunks (the skip
+        // jumps over whole chunks).
+        let offset = local.offset.get() % len;
+        let slots = self.iter().take(len).enumerate();
+        let rotated = slots.clone().skip(offset).chain(slots.take(offset));
+        for (i, slot) in rotated {
+            if Self::acquire(slot, ptr) {
+                local.offset.set(i + 1);
+                return Some(slot);
+            }
+        }
+        if len == limit || !allocating() {
+            return None;
+        }
+        let slot = &self.grow().slots[0];
+        let acquired = Self::acquire(slot, ptr);
+        debug_assert!(acquired);
+        local.offset.set(len + 1);
+        Some(slot)
+    }
+
+    #[inline]
+    fn acquire(slot: &Debt, ptr: usize) -> bool {
+        if slot.0.load(Relaxed) == Debt::NONE {
+            // We are allowed to split into the check and acquiring the debt. That's because we
+            // are the only ones allowed to change NONE to something else. But we still need a
+            // read-write operation wit SeqCst on it :-(
+            let old = slot.0.swap(ptr, SeqCst);
+            debug_assert_eq!(Debt::NONE, old);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Appends another chunk of slots.
+    ///
+    /// Only the owner of the node may call this.
+    fn grow(&self) -> &Chunk {
+        let mut last = &self.more;
+        // The owner is the only one changing these, so it sees the current values.
+        while let Some(chunk) = unsafe { last.load(Relaxed).as_ref() } {
+            last = &chunk.next;
+        }
+        let chunk = Box::into_raw(Box::<Chunk>::default());
+        // SeqCst: the writers must see the chunk if they see the storage change after we put a
+        // debt into it (the debt itself is put in with SeqCst after this).
+        last.store(chunk, SeqCst);
+        self.chunks.fetch_add(1, Relaxed);
+        unsafe { &*chunk }
+    }
+
+    /// Iterates through all the slots, including the additional ones.
+    pub(super) fn iter(&self) -> Iter<'_> {
+        Iter {
+            slots: self.base.iter(),
+            next: &self.more,
+        }
+    }
 }
 
-impl<'a> IntoIterator for &'a Slots {
+impl Drop for Slots {
+    fn drop(&mut self) {
+        let mut chunk = sync::load_mut(&mut self.more);
+        while !chunk.is_null() {
+            let mut boxed = unsafe { Box::from_raw(chunk) };
+            chunk = sync::load_mut(&mut boxed.next);
+        }
+    }
+}
+
+/// An iterator through the fast slots of a node.
+#[derive(Clone)]
+pub(crate) struct Iter<'a> {
+    slots: slice::Iter<'a, Debt>,
+    next: &'a AtomicPtr<Chunk>,
+}
+
+impl<'a> Iterator for Iter<'a> {
     type Item = &'a Debt;
 
-    type IntoIter = Iter<'a, Debt>;
+    fn next(&mut self) -> Option<&'a Debt> {
+        loop {
+            if let Some(slot) = self.slots.next() {
+                return Some(slot);
+            }
+            // SeqCst: see the grow.
+            let chunk = unsafe { self.next.load(SeqCst).as_ref() }?;
+            self.slots = chunk.slots.iter();
+            self.next = &chunk.next;
+        }
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.iter()
+    fn nth(&mut self, mut n: usize) -> Option<&'a Debt> {
+        loop {
+            let remaining = self.slots.len();
+            // This also exhausts the current chunk if the slot is not in there.
+            if let Some(slot) = self.slots.nth(n) {
+                return Some(slot);
+            }
+            n -= remaining;
+            // SeqCst: see the grow.
+            let chunk = unsafe { self.next.load(SeqCst).as_ref() }?;
+            self.slots = chunk.slots.iter();
+            self.next = &chunk.next;
+        }
     }
 }
@@ -14,17 +14,15 @@
 //! * Loads the pointer and puts it to the debt slot.
 //! * Confirms by CaS-replacing the generation back to idle state.
 //!
-//! * Later, we pay it back by CaS-replacing it with the NO_DEPT (like any other slot).
+//! * Later, we release it by CaS-replacing it with the NO_DEPT (like any other slot).
 //!
 //! ## Writer, the non-colliding path
 //!
 //! * Replaces the pointer in the storage.
 //! * The writer walks over all debts. It pays each debt that it is concerned with by bumping the
-//!   reference and replacing the dept with NO_DEPT. The relevant reader will fail in the CaS
-//!   (either because it finds NO_DEPT or other pointer in there) and knows the reference was
-//!   bumped, so it needs to decrement it. Note that it is possible that someone also reuses the
-//!   slot for the _same_ pointer. In that case that reader will set it to NO_DEPT and the newer
-//!   reader will have a pre-paid debt, which is fine.
+//!   reference and replacing the dept with PAID. The relevant reader will fail in the CaS
+//!   (because it finds PAID in there) and knows the reference was bumped, so it needs to decrement
+//!   it. It then sets the slot to NO_DEPT, so it can be reused.
 //!
 //! ## The collision path
 //!
@@ -106,12 +104,14 @@
 //!   writer and that change is the destruction ‒ by that time, the destroying thread has exclusive
 //!   ownership and therefore there can be no new readers.
 
-use std::cell::Cell;
-use std::ptr;
-use std::sync::atomic::Ordering::*;
-use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use alloc::boxed::Box;
+use core::cell::Cell;
+use core::ptr;
+use core::sync::atomic::Ordering::*;
 
 use super::Debt;
+use crate::sync::{self, AtomicPtr, AtomicUsize};
+use crate::thin;
 use crate::RefCnt;
 
 pub const REPLACEMENT_TAG: usize = 0b01;
@@ -126,6 +126,14 @@ pub(super) struct Local {
     generation: Cell<usize>,
 }
 
+impl Local {
+    pub(super) const fn new() -> Self {
+        Local {
+            generation: Cell::new(0),
+        }
+    }
+}
+
 // Make sure the pointers have 2 empty bits. Always.
 #[derive(Default)]
 #[repr(align(4))]
@@ -152,15 +160,13 @@ pub(super) struct Slots {
     /// If there's a generation in control, this signifies what address the reader is trying to
     /// load from.
     active_addr: AtomicUsize,
-    /// A place where a writer can put a replacement value.
-    ///
-    /// Note that this is simply an allocation, and every participating slot contributes one, but
-    /// they may be passed around through the lifetime of the program. It is not accessed directly,
-    /// but through the space_offer thing.
-    ///
-    handover: Handover,
     /// A pointer to a handover envelope this node currently owns.
     ///
+    /// The envelope is a place where a writer can put a replacement value. It is simply an
+    /// allocation, and every participating slot contributes one, but they may be passed around
+    /// through the lifetime of the program. Whichever one the slots own at the end is freed
+    /// together with them.
+    ///
     /// A writer makes a switch of its and readers handover when successfully storing a replacement
     /// in the control.
     space_offer: AtomicPtr<Handover>,
@@ -173,21 +179,33 @@ impl Default for Slots {
             slot: Debt::default(),
             // Doesn't matter yet
             active_addr: AtomicUsize::new(0),
-            // Also doesn't matter
-            handover: Handover::default(),
-            // Here we would like it to point to our handover. But for that we need to be in place
-            // in RAM (effectively pinned, though we use older Rust than Pin, possibly?), so not
-            // yet. See init().
+            // Allocated only in init(), not all slots take part in the helping.
             space_offer: AtomicPtr::new(ptr::null_mut()),
         }
     }
 }
 
+impl Drop for Slots {
+    fn drop(&mut self) {
+        // Nobody is helping us or being helped by us (we are being freed), so the envelope we have
+        // now is ours only.
+        let space = sync::load_mut(&mut self.space_offer);
+        if !space.is_null() {
+            drop(unsafe { Box::from_raw(space) });
+        }
+    }
+}
+
 impl Slots {
     pub(super) fn slot(&self) -> &Debt {
         &self.slot
     }
 
+    /// Nobody is using the slots right now (no debt, no transaction in progress).
+    pub(super) fn is_idle(&self) -> bool {
+        self.control.load(Acquire) == IDLE && self.slot.0.load(Acquire) == Debt::NONE
+    }
+
     pub(super) fn get_debt(&self, ptr: usize, local: &Local) -> (usize, bool) {
         // Incrementing by 4 ensures we always have enough space for 2 bit of tags.
         let gen = local.generation.get().wrapping_add(4);
@@ -215,7 +233,7 @@ impl Slots {
     pub(super) fn help<R, T>(&self, who: &Self, storage_addr: usize, replacement: &R)
     where
         T: RefCnt,
-        R: Fn() -> T,
+        R: Fn() -> *const (),
     {
         debug_assert_eq!(IDLE, self.control.load(Relaxed));
         // Also acquires the auxiliary data in other variables.
@@ -256,7 +274,7 @@ impl Slots {
                     // This actually does a full-featured load under the hood, but we are currently
                     // idle and the load doesn't re-enter write, so that's all fine.
                     let replacement = replacement();
-                    let replace_addr = T::as_ptr(&replacement) as usize;
+                    let replace_addr = replacement as usize;
                     // If we succeed in helping the other thread, we take their empty space in
                     // return for us that we pass to them. It's already there, the value is synced
                     // to us by Acquire on control.
@@ -283,15 +301,16 @@ impl Slots {
                             // We have successfully sent our replacement out (Release) and got
                             // their space in return (Acquire on that load above).
                             self.space_offer.store(their_space, SeqCst);
-                            // The ref count went with it, so forget about it here.
-                            T::into_ptr(replacement);
+                            metric!(helped);
+                            // The ref count went with it, so we keep it there.
                             // We have successfully helped out, so we are done.
                             break;
                         }
                         Err(new_control) => {
                             // Something has changed in between. Let's try again, nothing changed
-                            // (the replacement will get dropped at the end of scope, we didn't do
-                            // anything with the spaces, etc.
+                            // (we didn't do anything with the spaces, etc.), we just release the
+                            // replacement.
+                            unsafe { thin::dec::<T>(replacement) };
                             control = new_control;
                         }
                     }
@@ -301,8 +320,24 @@ impl Slots {
         }
     }
 
+    /// Forgets the transaction of a reader that no longer exists (after a `fork`).
+    ///
+    /// The debt in the slot is dropped too, it only ever protects a load in progress. The envelope
+    /// is replaced, as a writer might have been in the middle of passing it to someone else (the
+    /// old one is leaked).
+    #[cfg(unix)]
+    pub(super) fn abandon(&self) {
+        self.control.store(IDLE, SeqCst);
+        self.slot.0.store(Debt::NONE, SeqCst);
+        self.space_offer
+            .store(Box::into_raw(Box::new(Handover::default())), SeqCst);
+    }
+
     pub(super) fn init(&mut self) {
-        *self.space_offer.get_mut() = &mut self.handover;
+        sync::store_mut(
+            &mut self.space_offer,
+            Box::into_raw(Box::new(Handover::default())),
+        );
     }
 
     pub(super) fn confirm(&self, gen: usize, ptr: usize) -> Result<(), usize> {
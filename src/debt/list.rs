@@ -3,9 +3,31 @@
 //! A node may or may not be owned by a thread. Reader debts are allocated in its owned node,
 //! writer walks everything (but may also use some owned values).
 //!
-//! The list is prepend-only ‒ if thread dies, the node lives on (and can be claimed by another
-//! thread later on). This makes the implementation much simpler, since everything here is
-//! `'static` and we don't have to care about knowing when to free stuff.
+//! If a thread dies, its node lives on and can be claimed by another thread later on. Once there
+//! are too many unused nodes (eg. after a spike in the number of threads), they get freed, so the
+//! writers don't have to walk them forever. A writer that comes across too many of them on its
+//! walk does that, so nothing is done on thread termination. A node can be freed only if nothing
+//! points into it ‒ it is not owned by any thread, no [`Guard`] has a debt in it and no writer is
+//! walking it. The first two are checked on the node itself (a paid debt stays in the slot until
+//! its owner notices, see [`Debt::PAID`]), for the last one the node is first unlinked from the
+//! list and freed only after everyone walking the list since before that is gone (see the
+//! [Synchronization](#synchronization)). The writers don't wait for that, the next one to come
+//! around frees the nodes.
+//!
+//! The number of nodes in the global list can be limited. A thread that can't get a node then
+//! uses the [overflow] fallback.
+//!
+//! [overflow]: super::overflow
+//!
+//! Usually, there's just the one global list (the global [`Domain`]), with a node cached for each
+//! thread. A strategy may own a private domain. Each thread keeps a node for a few recently used
+//! private domains in a small cache (without it, each operation claims a node just for its own
+//! duration). Once the domain is dropped, its nodes are freed or, if some [`Guard`]s still point
+//! into them, put into a global pool, to be reused by some other private domain. A node that is
+//! still in some thread's cache at that point is abandoned and that thread disposes of it once it
+//! gives it up.
+//!
+//! [`Guard`]: crate::Guard
 //!
 //! The nodes contain both the fast primary slots and a secondary fallback ones.
 //!
@@ -18,6 +40,14 @@
 //! atomic) and other things that do change take care of themselves (the debt slots have their own
 //! synchronization, etc).
 //!
+//! The removal of nodes is done by only one thread at a time. It unlinks the nodes, but leaves
+//! their own `next` pointers intact, so anyone currently standing on them can still continue
+//! walking. Each walk over the list pins the current [epoch][super::epoch]. A writer pins it in its
+//! own node (which stays in the list, as it is claimed), only the walks without one (claiming a
+//! node, writers without a node) use the shared counters. The remover then moves to the next epoch
+//! and frees the nodes once nobody is pinned in the previous one. Whoever pins after that already
+//! starts from the updated list.
+//!
 //! The ownership is acquire-release lock pattern.
 //!
 //! Similar, the counting of active writers is an acquire-release lock pattern.
@@ -26,23 +56,620 @@
 //! at least as up to date value of the writers as when the cooldown started. That we if we see 0,
 //! we know it must have happened since then.
 
-use std::cell::Cell;
-use std::ptr;
-use std::slice::Iter;
-use std::sync::atomic::Ordering::*;
-use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::Cell;
+#[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+use core::cell::RefCell;
+use core::ptr;
+use core::sync::atomic::Ordering::*;
 
-use super::fast::{Local as FastLocal, Slots as FastSlots};
+use super::epoch::{Epoch, LocalPin};
+use super::fast::{Iter as FastIter, Local as FastLocal, Slots as FastSlots};
 use super::helping::{Local as HelpingLocal, Slots as HelpingSlots};
+use super::overflow::Overflow;
 use super::Debt;
+use crate::diagnostics::NodeState;
+use crate::nodes;
+use crate::spin::{self, Mutex};
+use crate::sync::{self, AtomicBool, AtomicPtr, AtomicUsize};
 use crate::RefCnt;
 
 const NODE_UNUSED: usize = 0;
 const NODE_USED: usize = 1;
 const NODE_COOLDOWN: usize = 2;
+const NODE_RECLAIMING: usize = 3;
+/// Still cached by a thread, but its domain is gone.
+const NODE_ABANDONED: usize = 4;
+
+/// How many unused nodes are kept around when reclaiming lazily.
+///
+/// Threads are often started in batches, we don't want to free the nodes just to allocate them
+/// again right away.
+const SPARE_NODES: usize = 4;
+
+/// The global debt linked list.
+#[cfg(not(loom))]
+static GLOBAL: Domain = Domain::new();
+
+#[cfg(loom)]
+loom::lazy_static! {
+    // Not dropped at the end of the execution, the nodes of the thread locals might still be in
+    // the list.
+    static ref GLOBAL: core::mem::ManuallyDrop<Domain> = core::mem::ManuallyDrop::new(Domain::new());
+}
+
+global! {
+    /// Maximum number of nodes in the global list.
+    static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+}
+
+/// Sets the maximum number of nodes in the global list.
+pub(crate) fn set_limit(limit: usize) {
+    LIMIT.store(limit, Relaxed);
+}
+
+/// The maximum number of nodes in the global list.
+pub(crate) fn limit() -> usize {
+    LIMIT.load(Relaxed)
+}
+
+global! {
+    /// May new nodes (and additional fast slots) be allocated on demand?
+    static ALLOCATING: AtomicBool = AtomicBool::new(true);
+}
+
+global! {
+    /// The number of nodes asked for by [`preallocate`], kept when reclaiming lazily.
+    static PREALLOCATED: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Allows or forbids allocating new nodes in the global list and additional fast slots on demand.
+pub(crate) fn set_allocating(allocating: bool) {
+    ALLOCATING.store(allocating, Relaxed);
+}
+
+/// Are new nodes and slots allocated on demand?
+pub(crate) fn allocating() -> bool {
+    ALLOCATING.load(Relaxed)
+}
+
+/// Creates unused nodes in the global list until there are at least `n` of them (if the limit
+/// allows).
+///
+/// Returns the number of created nodes.
+pub(crate) fn preallocate(n: usize) -> usize {
+    PREALLOCATED.fetch_max(n, Relaxed);
+    let mut created = 0;
+    while GLOBAL.count() < n && GLOBAL.grow() {
+        let node = Node::alloc();
+        // Nobody else sees it yet.
+        unsafe { (*node).in_use.store(NODE_UNUSED, Relaxed) };
+        unsafe { GLOBAL.prepend(node) };
+        created += 1;
+    }
+    created
+}
+
+/// Takes the nodes away from the threads that no longer exist.
+///
+/// Only the nodes that belong to a thread (not to a [`ThreadNode`][crate::nodes::ThreadNode] or a
+/// [`ReaderHandle`][crate::nodes::ReaderHandle], which may have survived) are released. Their fast
+/// debts are kept, as the [`Guard`][crate::Guard]s might have been sent to the current thread.
+///
+/// # Safety
+///
+/// Only the current thread exists and it is not in the middle of any operation (eg. after a
+/// `fork`).
+#[cfg(unix)]
+pub(crate) unsafe fn after_fork_child() {
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    let own = THREAD_HEAD
+        .try_with(|head| head.node.get())
+        .ok()
+        .flatten()
+        .map_or(ptr::null(), |own| own as *const Node);
+    #[cfg(not(any(feature = "std", not(feature = "no-std"))))]
+    let own: *const Node = ptr::null();
+
+    POOL.force_unlock();
+    // The remover might have been in the middle of something, forget its nodes (they leak).
+    GLOBAL.reclaiming.force_unlock();
+    core::mem::forget(core::mem::take(&mut GLOBAL.reclaiming.lock().nodes));
+    GLOBAL.retired.store(false, Relaxed);
+    GLOBAL.epoch.reset();
+    GLOBAL.overflow.after_fork_child();
+    let mut count = 0;
+    GLOBAL.traverse::<(), _>(|node| {
+        count += 1;
+        // No writer is walking the list any more.
+        node.active_writers.store(0, Relaxed);
+        node.pin.reset();
+        let orphaned = node.thread_bound.load(Relaxed) && !ptr::eq(own, node);
+        match node.in_use.load(Relaxed) {
+            NODE_USED if orphaned => {
+                node.helping.abandon();
+                node.thread_bound.store(false, Relaxed);
+                node.in_use.store(NODE_UNUSED, SeqCst);
+            }
+            // The reclaim didn't finish, but the node is still in the list.
+            NODE_RECLAIMING => node.in_use.store(NODE_UNUSED, SeqCst),
+            _ => (),
+        }
+        None
+    });
+    // Some nodes might have been lost in the middle of reclaiming.
+    GLOBAL.count.store(count, Relaxed);
+}
+
+#[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+global! {
+    /// The id for the next private domain that asks for one.
+    static NEXT_DOMAIN_ID: AtomicUsize = AtomicUsize::new(1);
+}
+
+/// Nodes of the dropped private domains, waiting to be reused.
+struct Pool(Vec<*mut Node>);
+
+// The nodes are shared between threads anyway, we just need to move the pointers around.
+unsafe impl Send for Pool {}
+
+global! {
+    static POOL: Mutex<Pool> = Mutex::new(Pool(Vec::new()));
+}
+
+/// Nodes unlinked from a list, waiting until nobody can be walking through them.
+#[derive(Default)]
+struct Retired {
+    nodes: Vec<*mut Node>,
+    /// The epoch they were unlinked in.
+    epoch: usize,
+}
+
+// Only the remover touches the nodes.
+unsafe impl Send for Retired {}
 
-/// The head of the debt linked list.
-static LIST_HEAD: AtomicPtr<Node> = AtomicPtr::new(ptr::null_mut());
+/// A linked list of debt nodes.
+///
+/// Writers pay the debts only in the domain of their strategy.
+pub(crate) struct Domain {
+    head: AtomicPtr<Node>,
+    /// Number of the nodes in the list.
+    count: AtomicUsize,
+    /// For freeing the unlinked nodes.
+    epoch: Epoch,
+    /// Someone is currently removing nodes, the unlinked ones wait here to be freed.
+    reclaiming: Mutex<Retired>,
+    /// There are some unlinked nodes to be freed.
+    retired: AtomicBool,
+    /// The unused nodes the last lazy reclamation had to keep.
+    ///
+    /// The writers don't try again until they see more of them.
+    kept: AtomicUsize,
+    /// The fallback for the threads that didn't get a node.
+    overflow: Overflow,
+    /// Identifies the private domain in the per-thread caches, assigned on the first use.
+    ///
+    /// Unlike the address, this is not reused by another domain once this one is dropped (and
+    /// stays the same if the domain is moved).
+    #[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+    id: AtomicUsize,
+}
+
+impl Domain {
+    const_fn! {
+        /// Creates a new empty private domain.
+        pub(crate) fn new() -> Self {
+            Domain {
+                head: AtomicPtr::new(ptr::null_mut()),
+                count: AtomicUsize::new(0),
+                epoch: Epoch::new(),
+                reclaiming: Mutex::new(Retired {
+                    nodes: Vec::new(),
+                    epoch: 0,
+                }),
+                retired: AtomicBool::new(false),
+                kept: AtomicUsize::new(0),
+                overflow: Overflow::new(),
+                #[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+                id: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    /// The domain shared by all the instances not having a private one.
+    pub(crate) fn global() -> &'static Self {
+        &GLOBAL
+    }
+
+    /// The fallback for the threads without a node.
+    pub(crate) fn overflow(&self) -> &Overflow {
+        &self.overflow
+    }
+
+    /// Number of the nodes in the list.
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Relaxed)
+    }
+
+    /// The unique id of the domain.
+    #[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+    fn id(&self) -> usize {
+        let id = self.id.load(Relaxed);
+        if id != 0 {
+            return id;
+        }
+        let new = NEXT_DOMAIN_ID.fetch_add(1, Relaxed);
+        match self.id.compare_exchange(0, new, Relaxed, Relaxed) {
+            Ok(_) => new,
+            // Someone else was faster, the lost id is simply skipped.
+            Err(id) => id,
+        }
+    }
+
+    /// Goes through the debt linked list.
+    ///
+    /// This traverses the linked list, calling the closure on each node. If the closure returns
+    /// `Some`, it terminates with that value early, otherwise it runs to the end.
+    ///
+    /// The nodes are not freed during the traversal (but they may be right after that, unless
+    /// something else keeps them alive, like owning them).
+    pub(crate) fn traverse<R, F: FnMut(&Node) -> Option<R>>(&self, f: F) -> Option<R> {
+        // Makes sure no node we can reach is freed until we are done.
+        let _pinned = self.epoch.pin();
+        unsafe { self.walk(f) }
+    }
+
+    /// Goes through the debt linked list, from the given local node of this domain.
+    ///
+    /// The same as [`traverse`][Domain::traverse], but if the local node has a node, the walk is
+    /// pinned in it instead of the shared counters.
+    pub(crate) fn traverse_as<R, F: FnMut(&Node) -> Option<R>>(
+        &self,
+        local: &LocalNode,
+        f: F,
+    ) -> Option<R> {
+        let _pinned = match local.node.get() {
+            Some(node) => self.epoch.pin_local(&node.pin),
+            None => self.epoch.pin(),
+        };
+        unsafe { self.walk(f) }
+    }
+
+    /// Goes through the debt linked list, without making sure the nodes are not freed.
+    ///
+    /// # Safety
+    ///
+    /// The caller must either pin the epoch or hold the `reclaiming` lock (then nobody else frees
+    /// the nodes).
+    unsafe fn walk<R, F: FnMut(&Node) -> Option<R>>(&self, mut f: F) -> Option<R> {
+        // Acquire ‒ we want to make sure we read the correct version of data at the end of the
+        // pointer. Any write to the DEBT_HEAD is with Release.
+        //
+        // Furthermore, we need to see the newest version of the list in case we examine the debts
+        // - if a new one is added recently, we don't want a stale read -> SeqCst.
+        //
+        // The other pointers in the chain change only when unlinking a node. We've synchronized
+        // with that through the pin (and unlinked nodes still point to the rest of the list).
+        let mut current = unsafe { self.head.load(SeqCst).as_ref() };
+        while let Some(node) = current {
+            let result = f(node);
+            if result.is_some() {
+                return result;
+            }
+            current = unsafe { node.next.load(Acquire).as_ref() };
+        }
+        None
+    }
+
+    /// Prepends a node we have exclusive access to into the list.
+    ///
+    /// # Safety
+    ///
+    /// The node must not be part of any list and must not be accessed by anyone through the
+    /// `next` field.
+    unsafe fn prepend(&self, node: *mut Node) -> &'static Node {
+        // We don't want to read any data in addition to the head, Relaxed is fine
+        // here.
+        //
+        // We do need to release the data to others, but for that, we acquire in the
+        // compare_exchange below.
+        let mut head = self.head.load(Relaxed);
+        loop {
+            // Only that field, others may still be accessed through stale debts.
+            (*node).next.store(head, Relaxed);
+            if let Err(old) = self.head.compare_exchange_weak(
+                head, node,
+                // We need to release *the whole chain* here. For that, we need to
+                // acquire it first.
+                //
+                // SeqCst because we need to make sure it is properly set "before" we do
+                // anything to the debts.
+                SeqCst, Relaxed, // Nothing changed, go next round of the loop.
+            ) {
+                head = old;
+            } else {
+                return &*node;
+            }
+        }
+    }
+
+    /// Reserves a place for a new node in the list, if the limit allows.
+    fn grow(&self) -> bool {
+        let limit = if ptr::eq(self, Self::global()) {
+            limit()
+        } else {
+            usize::MAX
+        };
+        let mut count = self.count.load(Relaxed);
+        loop {
+            if count >= limit {
+                return false;
+            }
+            match self
+                .count
+                .compare_exchange_weak(count, count + 1, Relaxed, Relaxed)
+            {
+                Ok(_) => return true,
+                Err(actual) => count = actual,
+            }
+        }
+    }
+
+    /// "Allocate" a node.
+    ///
+    /// Either a new one is created, or previous one is reused. The node is claimed to become
+    /// in_use.
+    ///
+    /// Returns `None` if there's no unused node and the limit doesn't allow creating a new one (or
+    /// the allocation is turned off). That never happens for a private domain.
+    ///
+    /// The `thread_bound` marks the node as used by the current thread only (as opposed to an
+    /// object that might be passed around), see [`after_fork_child`].
+    fn claim(&self, thread_bound: bool) -> Option<&'static Node> {
+        #[cfg(all(unix, feature = "fork-hook"))]
+        crate::fork::register_hook();
+        let node = self.claim_node()?;
+        node.thread_bound.store(thread_bound, Relaxed);
+        Some(node)
+    }
+
+    fn claim_node(&self) -> Option<&'static Node> {
+        // Try to find an unused one in the chain and reuse it.
+        let found = self.traverse(|node| {
+            node.check_cooldown();
+            if node
+                .in_use
+                // We claim a unique control over the generation and the right to write to slots if
+                // they are NO_DEPT
+                .compare_exchange(NODE_UNUSED, NODE_USED, SeqCst, Relaxed)
+                .is_ok()
+            {
+                // A claimed node is not freed until we release it.
+                Some(node as *const Node)
+            } else {
+                None
+            }
+        });
+        if let Some(node) = found {
+            return Some(unsafe { &*node });
+        }
+        // If that didn't work, take one from a dead domain or create a new one and prepend it to
+        // the list.
+        let global = ptr::eq(self, Self::global());
+        if (global && !allocating()) || !self.grow() {
+            return None;
+        }
+        let pooled = if global {
+            // Keep the pool for the private domains, the global one would just eat it up.
+            None
+        } else {
+            POOL.lock().0.pop()
+        };
+        let node = match pooled {
+            Some(node) => {
+                // The pool's lock synchronized the rest of the node.
+                unsafe { (*node).in_use.store(NODE_USED, Relaxed) };
+                node
+            }
+            None => Node::alloc(),
+        };
+        Some(unsafe { self.prepend(node) })
+    }
+
+    /// Frees the unused nodes, except for `spare` of them.
+    ///
+    /// Unlike the [lazy reclamation][Domain::reclaim_lazily], this waits for everyone who might be
+    /// walking the unlinked nodes.
+    ///
+    /// Returns the number of freed nodes. If someone else is already reclaiming, this gives up
+    /// right away.
+    pub(crate) fn reclaim(&self, spare: usize) -> usize {
+        let mut retired = match self.reclaiming.try_lock() {
+            Some(retired) => retired,
+            None => return 0,
+        };
+        // The ones unlinked by someone before, then the ones unused now.
+        let mut freed = self.free(&mut retired, true);
+        self.retire(&mut retired, spare);
+        freed += self.free(&mut retired, true);
+        freed
+    }
+
+    /// Frees some unused nodes if there are too many of them, without waiting for anyone.
+    ///
+    /// The `unused` is the number of the unused nodes the caller came across on its walk. The
+    /// nodes are only unlinked from the list, a later call frees them once nobody can be walking
+    /// through them.
+    pub(crate) fn reclaim_lazily(&self, unused: usize) {
+        let spare = if ptr::eq(self, Self::global()) {
+            SPARE_NODES.max(PREALLOCATED.load(Relaxed))
+        } else {
+            SPARE_NODES
+        };
+        if !self.retired.load(Relaxed) && unused <= spare.max(self.kept.load(Relaxed)) {
+            return;
+        }
+        let mut retired = match self.reclaiming.try_lock() {
+            Some(retired) => retired,
+            None => return,
+        };
+        if !retired.nodes.is_empty() && self.free(&mut retired, false) == 0 {
+            // Someone might still be walking them, the next writer tries again.
+            return;
+        }
+        let kept = self.retire(&mut retired, spare);
+        self.kept.store(kept, Relaxed);
+    }
+
+    /// Frees the retired nodes once nobody can be walking through them.
+    ///
+    /// If `wait` is not set and someone still can, it gives up and returns 0. Otherwise, returns
+    /// the number of the freed nodes.
+    fn free(&self, retired: &mut Retired, wait: bool) -> usize {
+        if retired.nodes.is_empty() {
+            return 0;
+        }
+        let mut attempt = 0;
+        while !self.left(retired.epoch) {
+            if !wait {
+                return 0;
+            }
+            spin::relax(attempt);
+            attempt += 1;
+        }
+        let freed = retired.nodes.len();
+        self.count.fetch_sub(freed, Relaxed);
+        for node in retired.nodes.drain(..) {
+            drop(unsafe { Box::from_raw(node) });
+        }
+        self.retired.store(false, Relaxed);
+        freed
+    }
+
+    /// Did everyone walking the list in the given epoch leave?
+    fn left(&self, epoch: usize) -> bool {
+        // We hold the reclaiming lock.
+        self.epoch.left(epoch)
+            && unsafe {
+                self.walk(|node| {
+                    if self.epoch.left_local(epoch, &node.pin) {
+                        None
+                    } else {
+                        Some(())
+                    }
+                })
+            }
+            .is_none()
+    }
+
+    /// Unlinks the unused nodes, except for `spare` of them, and puts them into the `retired`.
+    ///
+    /// The `retired` must be empty. Returns the number of unused nodes left in the list (the
+    /// spare ones and the ones still having some debts).
+    fn retire(&self, retired: &mut Retired, spare: usize) -> usize {
+        debug_assert!(retired.nodes.is_empty());
+
+        // Mark the ones to remove, so nobody claims them. We hold the reclaiming lock, so we
+        // don't have to pin.
+        let mut spare = spare;
+        let mut kept = 0;
+        unsafe {
+            self.walk::<(), _>(|node| {
+                node.check_cooldown();
+                if node.in_use.load(Relaxed) != NODE_UNUSED {
+                    return None;
+                }
+                if spare > 0 {
+                    spare -= 1;
+                    kept += 1;
+                    return None;
+                }
+                if node
+                    .in_use
+                    .compare_exchange(NODE_UNUSED, NODE_RECLAIMING, SeqCst, Relaxed)
+                    .is_ok()
+                    // Nobody can put a new debt in there now, the existing ones may only go away.
+                    && !node.is_idle()
+                {
+                    node.in_use.store(NODE_UNUSED, SeqCst);
+                    kept += 1;
+                }
+                None
+            })
+        };
+
+        // Unlink them. We are the only ones removing nodes, so the ones we see can't disappear
+        // under our hands. Others may be prepending, though.
+        let unlinked = &mut retired.nodes;
+        'restart: loop {
+            let mut prev: Option<&Node> = None;
+            let mut current = self.head.load(SeqCst);
+            while let Some(node) = unsafe { current.as_ref() } {
+                let next = node.next.load(Acquire);
+                if node.in_use.load(Relaxed) == NODE_RECLAIMING {
+                    match prev {
+                        Some(prev) => prev.next.store(next, SeqCst),
+                        None => {
+                            if self
+                                .head
+                                .compare_exchange(current, next, SeqCst, SeqCst)
+                                .is_err()
+                            {
+                                // Someone prepended, so it's no longer the head.
+                                continue 'restart;
+                            }
+                        }
+                    }
+                    unlinked.push(current);
+                } else {
+                    prev = Some(node);
+                }
+                current = next;
+            }
+            break;
+        }
+        if !unlinked.is_empty() {
+            // Free them once everyone who might have seen them leaves.
+            retired.epoch = self.epoch.advance();
+            self.retired.store(true, Relaxed);
+        }
+        kept
+    }
+}
+
+impl Default for Domain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Domain {
+    fn drop(&mut self) {
+        // Nobody uses the domain any more, so no writer walks the list and the nodes are claimed
+        // only by the per-thread caches. These are left to their threads, the rest may still hold
+        // debts of some Guards, so these can't be freed. Keep them for the next private domain.
+        for node in self.reclaiming.lock().nodes.drain(..) {
+            drop(unsafe { Box::from_raw(node) });
+        }
+        let mut current = sync::load_mut(&mut self.head);
+        while !current.is_null() {
+            let node = current;
+            unsafe {
+                current = (*node).next.load(Relaxed);
+                // AcqRel ‒ the thread may get rid of the node right away, it must not miss
+                // anything we did with it (and we must not miss what it did in case we do).
+                if (*node)
+                    .in_use
+                    .compare_exchange(NODE_USED, NODE_ABANDONED, AcqRel, Acquire)
+                    .is_err()
+                {
+                    Node::dispose(node);
+                }
+            }
+        }
+    }
+}
 
 pub struct NodeReservation<'a>(&'a Node);
 
@@ -63,8 +690,12 @@ pub(crate) struct Node {
     // It is a pointer because we touch it before synchronization (we don't _dereference_ it before
     // synchronization, only manipulate the pointer itself). That is illegal according to strict
     // interpretation of the rules by MIRI on references.
-    next: *const Node,
+    next: AtomicPtr<Node>,
     active_writers: AtomicUsize,
+    /// Owned by a thread (or its single operation), not by an object that may outlive it.
+    thread_bound: AtomicBool,
+    /// The owner walking the list pins here.
+    pin: LocalPin,
 }
 
 impl Default for Node {
@@ -73,43 +704,56 @@ impl Default for Node {
             fast: FastSlots::default(),
             helping: HelpingSlots::default(),
             in_use: AtomicUsize::new(NODE_USED),
-            next: ptr::null(),
+            next: AtomicPtr::new(ptr::null_mut()),
             active_writers: AtomicUsize::new(0),
+            thread_bound: AtomicBool::new(false),
+            pin: LocalPin::new(),
         }
     }
 }
 
 impl Node {
-    /// Goes through the debt linked list.
+    /// Allocates a new node, claimed (in use) and outside of any list.
+    fn alloc() -> *mut Node {
+        let mut node = Box::<Node>::default();
+        node.helping.init();
+        Box::into_raw(node)
+    }
+
+    /// Frees a node that is no longer in any list or, if it still has some debts, puts it into
+    /// the pool.
     ///
-    /// This traverses the linked list, calling the closure on each node. If the closure returns
-    /// `Some`, it terminates with that value early, otherwise it runs to the end.
-    pub(crate) fn traverse<R, F: FnMut(&'static Node) -> Option<R>>(mut f: F) -> Option<R> {
-        // Acquire ‒ we want to make sure we read the correct version of data at the end of the
-        // pointer. Any write to the DEBT_HEAD is with Release.
-        //
-        // Furthermore, we need to see the newest version of the list in case we examine the debts
-        // - if a new one is added recently, we don't want a stale read -> SeqCst.
-        //
-        // Note that the other pointers in the chain never change and are *ordinary* pointers. The
-        // whole linked list is synchronized through the head.
-        let mut current = unsafe { LIST_HEAD.load(SeqCst).as_ref() };
-        while let Some(node) = current {
-            let result = f(node);
-            if result.is_some() {
-                return result;
-            }
-            current = unsafe { node.next.as_ref() };
+    /// # Safety
+    ///
+    /// The node must be exclusively owned by the caller (nobody claims it and it is not
+    /// reachable through any list).
+    unsafe fn dispose(node: *mut Node) {
+        if (*node).is_idle() {
+            drop(Box::from_raw(node));
+        } else {
+            (*node).in_use.store(NODE_UNUSED, Relaxed);
+            POOL.lock().0.push(node);
         }
-        None
     }
 
     /// Put the current thread node into cooldown
-    fn start_cooldown(&self) {
+    ///
+    /// Returns `false` if the node can't go into cooldown, because its domain is gone.
+    fn start_cooldown(&self) -> bool {
         // Trick: Make sure we have an up to date value of the active_writers in this thread, so we
-        // can properly release it below.
-        let _reservation = self.reserve_writer();
-        assert_eq!(NODE_USED, self.in_use.swap(NODE_COOLDOWN, Release));
+        // can properly release it below. Given up before the node is, the node may be freed right
+        // after that.
+        drop(self.reserve_writer());
+        match self
+            .in_use
+            .compare_exchange(NODE_USED, NODE_COOLDOWN, Release, Acquire)
+        {
+            Ok(_) => true,
+            Err(state) => {
+                assert_eq!(NODE_ABANDONED, state);
+                false
+            }
+        }
     }
 
     /// Perform a cooldown if the node is ready.
@@ -133,64 +777,41 @@ impl Node {
         }
     }
 
+    /// Checks that nothing points into the node from the outside.
+    ///
+    /// Meaningful only if the node is not owned by anyone.
+    fn is_idle(&self) -> bool {
+        // Acquire ‒ the owners release the slots with Release, we need to see everything they did
+        // with it before we free it.
+        self.fast_slots()
+            .all(|slot| slot.0.load(Acquire) == Debt::NONE)
+            && self.helping.is_idle()
+    }
+
+    /// Is the node not claimed by anyone (maybe still in a cooldown)?
+    pub(crate) fn is_unclaimed(&self) -> bool {
+        self.in_use.load(Relaxed) != NODE_USED
+    }
+
     /// Mark this node that a writer is currently playing with it.
-    pub fn reserve_writer(&self) -> NodeReservation {
+    pub fn reserve_writer(&self) -> NodeReservation<'_> {
         self.active_writers.fetch_add(1, Acquire);
         NodeReservation(self)
     }
 
-    /// "Allocate" a node.
-    ///
-    /// Either a new one is created, or previous one is reused. The node is claimed to become
-    /// in_use.
-    fn get() -> &'static Self {
-        // Try to find an unused one in the chain and reuse it.
-        Self::traverse(|node| {
-            node.check_cooldown();
-            if node
-                .in_use
-                // We claim a unique control over the generation and the right to write to slots if
-                // they are NO_DEPT
-                .compare_exchange(NODE_UNUSED, NODE_USED, SeqCst, Relaxed)
-                .is_ok()
-            {
-                Some(node)
-            } else {
-                None
-            }
-        })
-        // If that didn't work, create a new one and prepend to the list.
-        .unwrap_or_else(|| {
-            let node = Box::leak(Box::<Node>::default());
-            node.helping.init();
-            // We don't want to read any data in addition to the head, Relaxed is fine
-            // here.
-            //
-            // We do need to release the data to others, but for that, we acquire in the
-            // compare_exchange below.
-            let mut head = LIST_HEAD.load(Relaxed);
-            loop {
-                node.next = head;
-                if let Err(old) = LIST_HEAD.compare_exchange_weak(
-                    head, node,
-                    // We need to release *the whole chain* here. For that, we need to
-                    // acquire it first.
-                    //
-                    // SeqCst because we need to make sure it is properly set "before" we do
-                    // anything to the debts.
-                    SeqCst, Relaxed, // Nothing changed, go next round of the loop.
-                ) {
-                    head = old;
-                } else {
-                    return node;
-                }
-            }
-        })
+    /// The state of the node, for the diagnostics.
+    pub(crate) fn state(&self) -> NodeState {
+        match self.in_use.load(Relaxed) {
+            NODE_USED => NodeState::Used,
+            NODE_COOLDOWN => NodeState::Cooldown,
+            // About to be freed, but nobody uses it anyway.
+            _ => NodeState::Unused,
+        }
     }
 
     /// Iterate over the fast slots.
-    pub(crate) fn fast_slots(&self) -> Iter<Debt> {
-        self.fast.into_iter()
+    pub(crate) fn fast_slots(&self) -> FastIter<'_> {
+        self.fast.iter()
     }
 
     /// Access the helping slot.
@@ -206,6 +827,9 @@ pub(crate) struct LocalNode {
     /// We don't necessarily have to own one, but if we don't, we'll get one before the first use.
     node: Cell<Option<&'static Node>>,
 
+    /// Is this used only by one thread (the thread local variable or a temporary one)?
+    thread_bound: bool,
+
     /// Thread-local data for the fast slots.
     fast: FastLocal,
 
@@ -214,56 +838,217 @@ pub(crate) struct LocalNode {
 }
 
 impl LocalNode {
+    /// A node to be kept for the thread (or CPU), claiming a node on the first use.
+    pub(crate) const fn cached() -> Self {
+        LocalNode {
+            node: Cell::new(None),
+            thread_bound: false,
+            fast: FastLocal::new(),
+            helping: HelpingLocal::new(),
+        }
+    }
+
+    /// The node kept in the thread local variable.
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    const fn thread_local() -> Self {
+        LocalNode {
+            node: Cell::new(None),
+            thread_bound: true,
+            fast: FastLocal::new(),
+            helping: HelpingLocal::new(),
+        }
+    }
+
+    /// A node for just one operation, claimed from the given domain right away.
+    fn temporary(domain: &Domain) -> Self {
+        LocalNode {
+            node: Cell::new(domain.claim(true)),
+            thread_bound: true,
+            fast: FastLocal::new(),
+            helping: HelpingLocal::new(),
+        }
+    }
+
+    /// Runs the closure, claiming a node first if we don't have one yet.
+    pub(crate) fn run<R, F: FnOnce(&LocalNode) -> R>(&self, f: F) -> R {
+        if self.node.get().is_none() {
+            // If we don't get one, we'll try again next time.
+            self.node.set(GLOBAL.claim(self.thread_bound));
+        }
+        f(self)
+    }
+
     pub(crate) fn with<R, F: FnOnce(&LocalNode) -> R>(f: F) -> R {
-        let f = Cell::new(Some(f));
-        THREAD_HEAD
-            .try_with(|head| {
-                if head.node.get().is_none() {
-                    head.node.set(Some(Node::get()));
+        let mut f = Some(f);
+        if let Some(provider) = nodes::provider() {
+            let mut result = None;
+            provider.with_node(&mut |node| {
+                let f = f
+                    .take()
+                    .expect("The node provider called the closure twice");
+                result = Some(node.0.run(f));
+            });
+            if let Some(result) = result {
+                return result;
+            }
+        }
+        #[cfg(any(feature = "std", not(feature = "no-std")))]
+        {
+            if let Ok(result) = THREAD_HEAD.try_with(|head| head.run(f.take().unwrap())) {
+                return result;
+            }
+        }
+        // There's no node for the current thread. This happens during the application shutdown,
+        // when the thread local storage may be already deallocated, or if the node provider
+        // doesn't have one. In that case we still need something. So we just find or allocate a
+        // node and use it just once.
+        //
+        // Note that the situation should be very very rare and not happen often, so the slower
+        // performance doesn't matter that much.
+        let tmp_node = Self::temporary(Domain::global());
+        let f = f.take().unwrap();
+        f(&tmp_node)
+        // Drop of tmp_node -> sends the node we just used into cooldown.
+    }
+
+    /// Runs the closure with a node of the given domain.
+    ///
+    /// For the global domain, this is the same as [`with`][LocalNode::with]. For a private one,
+    /// the node cached for the domain in the current thread is used. If there's none (or it is
+    /// already in use higher up the stack), a node is claimed just for the duration of the
+    /// closure.
+    pub(crate) fn with_domain<R, F: FnOnce(&LocalNode) -> R>(domain: &Domain, f: F) -> R {
+        if ptr::eq(domain, Domain::global()) {
+            return Self::with(f);
+        }
+        let mut f = Some(f);
+        #[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+        {
+            let id = domain.id();
+            let result = DOMAIN_NODES.try_with(|nodes| {
+                let mut cached = nodes[id % DOMAIN_NODES_CNT].try_borrow_mut().ok()?;
+                match &mut *cached {
+                    Some(cached) if cached.domain == id => {
+                        if cached.local.node.get().is_none() {
+                            cached.local.node.set(domain.claim(true));
+                        }
+                    }
+                    // Evict whatever was there, dropping a LocalNode gives up its node.
+                    other => {
+                        *other = Some(DomainNode {
+                            domain: id,
+                            local: Self::temporary(domain),
+                        })
+                    }
                 }
-                let f = f.take().unwrap();
-                f(head)
-            })
-            // During the application shutdown, the thread local storage may be already
-            // deallocated. In that case, the above fails but we still need something. So we just
-            // find or allocate a node and use it just once.
-            //
-            // Note that the situation should be very very rare and not happen often, so the slower
-            // performance doesn't matter that much.
-            .unwrap_or_else(|_| {
-                let tmp_node = LocalNode {
-                    node: Cell::new(Some(Node::get())),
-                    fast: FastLocal::default(),
-                    helping: HelpingLocal::default(),
-                };
-                let f = f.take().unwrap();
-                f(&tmp_node)
-                // Drop of tmp_node -> sends the node we just used into cooldown.
-            })
+                let local = &cached.as_ref().unwrap().local;
+                Some(f.take().unwrap()(local))
+            });
+            if let Ok(Some(result)) = result {
+                return result;
+            }
+        }
+        let tmp_node = Self::temporary(domain);
+        f.take().unwrap()(&tmp_node)
+        // Drop of tmp_node -> sends the node into cooldown, so it can be claimed again.
+    }
+
+    /// Does the thread own a node?
+    ///
+    /// If not, it needs to use the [overflow][super::overflow] fallback instead of debts.
+    #[inline]
+    pub(crate) fn is_claimed(&self) -> bool {
+        self.node.get().is_some()
+    }
+
+    /// Runs the closure with the place for the current thread's node, if there's one.
+    ///
+    /// Unlike [`with`][LocalNode::with], this doesn't claim a node nor falls back to a temporary
+    /// one.
+    fn with_thread<R, F: FnOnce(&LocalNode) -> R>(f: F) -> Option<R> {
+        let mut f = Some(f);
+        if let Some(provider) = nodes::provider() {
+            let mut result = None;
+            provider.with_node(&mut |node| {
+                let f = f
+                    .take()
+                    .expect("The node provider called the closure twice");
+                result = Some(f(&node.0));
+            });
+            return result;
+        }
+        #[cfg(any(feature = "std", not(feature = "no-std")))]
+        {
+            THREAD_HEAD.try_with(|head| f.take().unwrap()(head)).ok()
+        }
+        #[cfg(not(any(feature = "std", not(feature = "no-std"))))]
+        {
+            None
+        }
+    }
+
+    /// Does the current thread have a node cached?
+    ///
+    /// Unlike [`with`][LocalNode::with], this doesn't try to claim one.
+    pub(crate) fn thread_has_node() -> bool {
+        Self::with_thread(LocalNode::is_claimed).unwrap_or(false)
+    }
+
+    /// Claims a node for the current thread, if it doesn't have one yet.
+    ///
+    /// Returns if the thread has a node now.
+    pub(crate) fn register_thread() -> bool {
+        Self::with_thread(|local| local.run(LocalNode::is_claimed)).unwrap_or(false)
+    }
+
+    /// Gives up the node of the current thread, if it has one.
+    pub(crate) fn unregister_thread() {
+        Self::with_thread(LocalNode::release);
+    }
+
+    /// Gives up the node, sending it into cooldown.
+    ///
+    /// Returns if there was one.
+    fn release(&self) -> bool {
+        match self.node.take() {
+            Some(node) => {
+                // Release - syncing writes/ownership of this Node
+                if !node.start_cooldown() {
+                    // The domain is gone and left the node to us.
+                    unsafe { Node::dispose(node as *const Node as *mut Node) };
+                }
+                true
+            }
+            None => false,
+        }
     }
 
     /// Creates a new debt.
     ///
-    /// This stores the debt of the given pointer (untyped, casted into an usize) and returns a
-    /// reference to that slot, or gives up with `None` if all the slots are currently full.
+    /// This stores the debt of the given pointer (untyped, casted into an usize) in one of the
+    /// first `limit` fast slots and returns a reference to that slot, or gives up with `None` if
+    /// all these slots are currently full.
     #[inline]
-    pub(crate) fn new_fast(&self, ptr: usize) -> Option<&'static Debt> {
-        let node = &self.node.get().expect("LocalNode::with ensures it is set");
+    pub(crate) fn new_fast(&self, ptr: usize, limit: usize) -> Option<&'static Debt> {
+        let node = &self.node.get().expect("Checked by is_claimed");
         debug_assert_eq!(node.in_use.load(Relaxed), NODE_USED);
-        node.fast.get_debt(ptr, &self.fast)
+        node.fast.get_debt(ptr, &self.fast, limit)
     }
 
     /// Initializes a helping slot transaction.
     ///
     /// Returns the generation (with tag).
     pub(crate) fn new_helping(&self, ptr: usize) -> usize {
-        let node = &self.node.get().expect("LocalNode::with ensures it is set");
+        let node = &self.node.get().expect("Checked by is_claimed");
         debug_assert_eq!(node.in_use.load(Relaxed), NODE_USED);
         let (gen, discard) = node.helping.get_debt(ptr, &self.helping);
         if discard {
             // Too many generations happened, make sure the writers give the poor node a break for
             // a while so they don't observe the generation wrapping around.
-            node.start_cooldown();
+            //
+            // We are in the middle of an operation on the domain, so it can't be gone.
+            let cooling = node.start_cooldown();
+            debug_assert!(cooling);
             self.node.take();
         }
         gen
@@ -280,7 +1065,7 @@ impl LocalNode {
         gen: usize,
         ptr: usize,
     ) -> Result<&'static Debt, (&'static Debt, usize)> {
-        let node = &self.node.get().expect("LocalNode::with ensures it is set");
+        let node = &self.node.get().expect("Checked by is_claimed");
         debug_assert_eq!(node.in_use.load(Relaxed), NODE_USED);
         let slot = node.helping_slot();
         node.helping
@@ -293,46 +1078,76 @@ impl LocalNode {
     ///
     /// This potentially helps the `who` node (uses self as the local node, which must be
     /// different) by loading the address that one is trying to load.
-    pub(super) fn help<R, T>(&self, who: &Node, storage_addr: usize, replacement: &R)
-    where
+    pub(super) fn help<R, T>(
+        &self,
+        domain: &Domain,
+        who: &Node,
+        storage_addr: usize,
+        replacement: &R,
+    ) where
         T: RefCnt,
-        R: Fn() -> T,
+        R: Fn() -> *const (),
     {
-        let node = &self.node.get().expect("LocalNode::with ensures it is set");
-        debug_assert_eq!(node.in_use.load(Relaxed), NODE_USED);
-        node.helping.help(&who.helping, storage_addr, replacement)
+        match self.node.get() {
+            Some(node) => {
+                debug_assert_eq!(node.in_use.load(Relaxed), NODE_USED);
+                node.helping
+                    .help::<R, T>(&who.helping, storage_addr, replacement)
+            }
+            // We don't have our own slots to help from, borrow the shared ones.
+            None => domain
+                .overflow
+                .reserve()
+                .help::<R, T>(&who.helping, storage_addr, replacement),
+        }
     }
 }
 
 impl Drop for LocalNode {
     fn drop(&mut self) {
-        if let Some(node) = self.node.get() {
-            // Release - syncing writes/ownership of this Node
-            node.start_cooldown();
-        }
+        // The unused nodes are reclaimed by the writers later on, not here (the thread may be
+        // terminating and we don't want to make it walk the list).
+        self.release();
     }
 }
 
+#[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
 thread_local! {
     /// A debt node assigned to this thread.
-    static THREAD_HEAD: LocalNode = LocalNode {
-        node: Cell::new(None),
-        fast: FastLocal::default(),
-        helping: HelpingLocal::default(),
-    };
+    static THREAD_HEAD: LocalNode = const { LocalNode::thread_local() };
+}
+
+#[cfg(all(any(feature = "std", not(feature = "no-std")), loom))]
+loom::thread_local! {
+    static THREAD_HEAD: LocalNode = LocalNode::thread_local();
+}
+
+/// How many private domains a thread keeps a node for.
+#[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+const DOMAIN_NODES_CNT: usize = 8;
+
+/// A node of a private domain, cached by a thread.
+#[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+struct DomainNode {
+    /// The [id][Domain::id] of the domain.
+    domain: usize,
+    local: LocalNode,
+}
+
+#[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+thread_local! {
+    /// The nodes of the private domains this thread used recently, indexed by the domain id.
+    ///
+    /// Borrowed for the whole operation, a nested one on the same domain gets a temporary node.
+    static DOMAIN_NODES: [RefCell<Option<DomainNode>>; DOMAIN_NODES_CNT] = Default::default();
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::fast::DEBT_SLOT_CNT;
     use super::*;
 
     impl Node {
-        fn is_empty(&self) -> bool {
-            self.fast_slots()
-                .chain(std::iter::once(self.helping_slot()))
-                .all(|d| d.0.load(Relaxed) == Debt::NONE)
-        }
-
         fn get_thread() -> &'static Self {
             LocalNode::with(|h| h.node.get().unwrap())
         }
@@ -341,6 +1156,198 @@ mod tests {
     /// A freshly acquired thread local node is empty.
     #[test]
     fn new_empty() {
-        assert!(Node::get_thread().is_empty());
+        assert!(Node::get_thread().is_idle());
+    }
+
+    /// The node of a private domain is cached for the thread, a nested operation gets a temporary
+    /// one.
+    #[test]
+    fn private_domain() {
+        let domain = Domain::new();
+        let node = |local: &LocalNode| local.node.get().unwrap() as *const Node;
+        let first = LocalNode::with_domain(&domain, node);
+        let (second, nested) = LocalNode::with_domain(&domain, |local| {
+            (node(local), LocalNode::with_domain(&domain, node))
+        });
+        assert_eq!(first, second);
+        assert_ne!(first, nested);
+        let mut cnt = 0;
+        domain.traverse::<(), _>(|_| {
+            cnt += 1;
+            None
+        });
+        assert_eq!(2, cnt);
+        // Not the global one
+        assert_ne!(first, Node::get_thread() as *const _);
+        // Still kept for the next operation
+        #[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+        assert_eq!(NodeState::Used, unsafe { (*first).state() });
+    }
+
+    /// A node cached by a thread outlives its domain, the thread disposes of it once it gives it
+    /// up.
+    #[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+    #[test]
+    fn abandoned() {
+        let value = 42;
+        let ptr = &value as *const i32 as usize;
+        let debt = std::thread::spawn(move || {
+            let domain = Domain::new();
+            let (node, debt) = LocalNode::with_domain(&domain, |local| {
+                let node = local.node.get().unwrap();
+                (node, local.new_fast(ptr, DEBT_SLOT_CNT).unwrap())
+            });
+            drop(domain);
+            assert_eq!(NODE_ABANDONED, node.in_use.load(Relaxed));
+            // Still usable, the thread didn't give it up yet.
+            assert!(debt.pay(ptr as *const ()));
+            debt
+        })
+        .join()
+        .unwrap();
+        // The debt kept it alive (in the pool).
+        assert!(!debt.release(ptr as *const ()));
+    }
+
+    /// Unused nodes are freed, except for the requested spare ones.
+    #[test]
+    fn reclaim_unused() {
+        let domain = Domain::new();
+        {
+            let _first = LocalNode::temporary(&domain);
+            let _second = LocalNode::temporary(&domain);
+            let _third = LocalNode::temporary(&domain);
+        }
+        assert_eq!(3, domain.count());
+        assert_eq!(1, domain.reclaim(2));
+        assert_eq!(2, domain.reclaim(0));
+        assert_eq!(0, domain.count());
+        // New ones are created as needed
+        drop(LocalNode::temporary(&domain));
+        assert_eq!(1, domain.count());
+    }
+
+    /// The lazy reclamation doesn't start below the threshold and frees the nodes on the next
+    /// attempt after unlinking them.
+    #[test]
+    fn reclaim_lazily() {
+        let domain = Domain::new();
+        {
+            let _nodes = (0..SPARE_NODES + 2)
+                .map(|_| LocalNode::temporary(&domain))
+                .collect::<Vec<_>>();
+        }
+        domain.reclaim_lazily(SPARE_NODES);
+        assert!(!domain.retired.load(Relaxed));
+        domain.reclaim_lazily(SPARE_NODES + 2);
+        assert!(domain.retired.load(Relaxed));
+        // Unlinked, but not freed yet.
+        assert_eq!(SPARE_NODES + 2, domain.count());
+        let mut cnt = 0;
+        domain.traverse::<(), _>(|_| {
+            cnt += 1;
+            None
+        });
+        assert_eq!(SPARE_NODES, cnt);
+        domain.reclaim_lazily(0);
+        assert!(!domain.retired.load(Relaxed));
+        assert_eq!(SPARE_NODES, domain.count());
+    }
+
+    /// The nodes are not freed while someone pinned in its own node might walk them.
+    #[test]
+    fn reclaim_pinned() {
+        let domain = Domain::new();
+        let walker = LocalNode::temporary(&domain);
+        {
+            let _nodes = (0..SPARE_NODES + 1)
+                .map(|_| LocalNode::temporary(&domain))
+                .collect::<Vec<_>>();
+        }
+        domain.traverse_as(&walker, |_| {
+            domain.reclaim_lazily(usize::MAX);
+            assert!(domain.retired.load(Relaxed));
+            // We might still be standing on it.
+            domain.reclaim_lazily(usize::MAX);
+            assert!(domain.retired.load(Relaxed));
+            assert_eq!(SPARE_NODES + 2, domain.count());
+            Some(())
+        });
+        domain.reclaim_lazily(0);
+        assert!(!domain.retired.load(Relaxed));
+        assert_eq!(SPARE_NODES + 1, domain.count());
+    }
+
+    /// Claimed nodes are not freed.
+    #[test]
+    fn reclaim_claimed() {
+        let domain = Domain::new();
+        let _claimed = LocalNode::temporary(&domain);
+        drop(LocalNode::temporary(&domain));
+        assert_eq!(1, domain.reclaim(0));
+        assert_eq!(1, domain.count());
+    }
+
+    /// Nodes with a debt are kept until the owner releases the debt, even if paid.
+    #[test]
+    fn reclaim_debt() {
+        let domain = Domain::new();
+        let value = 42;
+        let ptr = &value as *const i32 as *const ();
+        let debt = LocalNode::temporary(&domain)
+            .new_fast(ptr as usize, DEBT_SLOT_CNT)
+            .unwrap();
+        assert_eq!(0, domain.reclaim(0));
+        assert!(debt.pay(ptr));
+        assert_eq!(0, domain.reclaim(0));
+        assert!(!debt.release(ptr));
+        assert_eq!(1, domain.reclaim(0));
+    }
+
+    /// More slots than the basic ones are added on demand and the writers see them too.
+    #[test]
+    fn more_slots() {
+        const LIMIT: usize = DEBT_SLOT_CNT * 2 + 1;
+        let domain = Domain::new();
+        let value = 42;
+        let ptr = &value as *const i32 as *const ();
+        let debts = LocalNode::with_domain(&domain, |local| {
+            let debts = (0..LIMIT)
+                .map(|_| local.new_fast(ptr as usize, LIMIT).unwrap())
+                .collect::<Vec<_>>();
+            assert!(local.new_fast(ptr as usize, LIMIT).is_none());
+            debts
+        });
+        domain.traverse::<(), _>(|node| {
+            assert_eq!(DEBT_SLOT_CNT * 3, node.fast_slots().count());
+            assert_eq!(LIMIT, node.fast_slots().filter(|d| d.pay(ptr)).count());
+            // Skipping over the chunks finds the same slots as walking through them.
+            let walked = node.fast_slots().collect::<Vec<_>>();
+            for (n, slot) in walked.iter().enumerate() {
+                assert!(core::ptr::eq(*slot, node.fast_slots().nth(n).unwrap()));
+            }
+            assert!(node.fast_slots().nth(DEBT_SLOT_CNT * 3).is_none());
+            None
+        });
+        for debt in &debts {
+            assert!(!debt.release(ptr));
+        }
+        // The added slots are reused and the basic limit still holds.
+        LocalNode::with_domain(&domain, |local| {
+            let debt = local.new_fast(ptr as usize, LIMIT).unwrap();
+            assert!(debt.release(ptr));
+            let debts = (0..DEBT_SLOT_CNT)
+                .map(|_| local.new_fast(ptr as usize, DEBT_SLOT_CNT).unwrap())
+                .collect::<Vec<_>>();
+            assert!(local.new_fast(ptr as usize, DEBT_SLOT_CNT).is_none());
+            // Don't leave debts in the node, it would end up in the pool for the other tests.
+            for debt in debts {
+                assert!(debt.release(ptr));
+            }
+        });
+        domain.traverse::<(), _>(|node| {
+            assert_eq!(DEBT_SLOT_CNT * 3, node.fast_slots().count());
+            None
+        });
     }
 }
@@ -5,24 +5,44 @@
 //!
 //! Each thread has its own node with bunch of slots. Only that thread can allocate debts in there,
 //! but others are allowed to inspect and pay them. The nodes form a linked list for the reason of
-//! inspection. The nodes are never removed (even after the thread terminates), but if the thread
-//! gives it up, another (new) thread can claim it.
+//! inspection. If the thread gives its node up, another (new) thread can claim it. Unused nodes
+//! are eventually removed, if nothing points into them.
 //!
 //! The writers walk the whole chain and pay the debts (by bumping the ref counts) of the just
 //! removed pointer.
 //!
+//! The chain is usually the global one, but some strategies have their own private chain (a
+//! [`Domain`]). Then the writers walk only that one.
+//!
 //! Each node has some fast (but fallible) nodes and a fallback node, with different algorithms to
 //! claim them (see the relevant submodules).
 
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering::*;
+use core::sync::atomic::Ordering::*;
 
-pub(crate) use self::list::{LocalNode, Node};
+pub(crate) use self::fast::DEBT_SLOT_CNT;
+pub(crate) use self::list::{
+    allocating, limit, preallocate, set_allocating, set_limit, Domain, LocalNode,
+};
 use super::RefCnt;
+use crate::sync::{self, AtomicUsize};
+use crate::thin;
 
+mod epoch;
 mod fast;
 mod helping;
 mod list;
+pub(crate) mod overflow;
+
+/// Resets the bookkeeping left behind by the threads that no longer exist.
+///
+/// # Safety
+///
+/// Only the current thread exists and it is not in the middle of any operation (eg. after a
+/// `fork`).
+#[cfg(unix)]
+pub(crate) unsafe fn after_fork_child() {
+    list::after_fork_child();
+}
 
 /// One debt slot.
 ///
@@ -37,6 +57,15 @@ impl Debt {
     ///   because the data at the end of the `Arc` has the counters.
     /// * It's in the very first page where NULL lives, so it's not mapped.
     pub(crate) const NONE: usize = 0b11;
+
+    /// A debt that was paid by a writer, but its owner didn't notice yet.
+    ///
+    /// The slot can't be reused until the owner (the one who put the debt in) acknowledges it by
+    /// [releasing][Debt::release] it. Therefore an occupied slot (anything other than [`NONE`])
+    /// means someone still has a reference to it. Safe for the same reasons as the [`NONE`].
+    ///
+    /// [`NONE`]: Debt::NONE
+    pub(crate) const PAID: usize = 0b111;
 }
 
 impl Default for Debt {
@@ -52,17 +81,18 @@ impl Debt {
     /// is empty or if there's some other pointer, it is not paid and `false` is returned, meaning
     /// the debt was paid previously by someone else.
     ///
+    /// This is for the writers. The slot is left in the [`PAID`][Debt::PAID] state, for the owner
+    /// to [`release`][Debt::release].
+    ///
     /// # Notes
     ///
-    /// * It is possible that someone paid the debt and then someone else put a debt for the same
-    ///   pointer in there. This is fine, as we'll just pay the debt for that someone else.
     /// * This relies on the fact that the same pointer must point to the same object and
     ///   specifically to the same type ‒ the caller provides the type, it's destructor, etc.
     /// * It also relies on the fact the same thing is not stuffed both inside an `Arc` and `Rc` or
     ///   something like that, but that sounds like a reasonable assumption. Someone storing it
     ///   through `ArcSwap<T>` and someone else with `ArcSwapOption<T>` will work.
     #[inline]
-    pub(crate) fn pay<T: RefCnt>(&self, ptr: *const T::Base) -> bool {
+    pub(crate) fn pay(&self, ptr: *const ()) -> bool {
         self.0
             // If we don't change anything because there's something else, Relaxed is fine.
             //
@@ -73,43 +103,89 @@ impl Debt {
             // necessarily observe that increment, but whoever destroys the pointer *must* see the
             // up to date value, with all increments already counted in (the Arc takes care of that
             // part).
-            .compare_exchange(ptr as usize, Self::NONE, Release, Relaxed)
+            .compare_exchange(ptr as usize, Self::PAID, Release, Relaxed)
             .is_ok()
     }
 
-    /// Pays all the debts on the given pointer and the storage.
-    pub(crate) fn pay_all<T, R>(ptr: *const T::Base, storage_addr: usize, replacement: R)
-    where
+    /// Gives up the debt on the given pointer, by its owner.
+    ///
+    /// Returns `true` if the debt was still there (and now it's not). Returns `false` if some
+    /// writer paid it in the meantime ‒ then the caller owns a reference to the pointer. In both
+    /// cases, the slot is free after this.
+    #[inline]
+    pub(crate) fn release(&self, ptr: *const ()) -> bool {
+        // The Release works as kind of Mutex, same as with pay.
+        match self
+            .0
+            .compare_exchange(ptr as usize, Self::NONE, Release, Relaxed)
+        {
+            Ok(_) => true,
+            Err(actual) => {
+                // Nobody else touches the slot once it is paid.
+                debug_assert_eq!(Self::PAID, actual);
+                self.0.store(Self::NONE, Release);
+                false
+            }
+        }
+    }
+
+    /// Pays all the debts on the given pointer and the storage, in the given domain.
+    ///
+    /// The `replacement` provides words with an owned reference.
+    ///
+    /// # Safety
+    ///
+    /// The caller must own a reference to the `ptr` word for the whole duration of the call.
+    pub(crate) unsafe fn pay_all<T, R>(
+        domain: &Domain,
+        ptr: *const (),
+        storage_addr: usize,
+        replacement: R,
+    ) where
         T: RefCnt,
-        R: Fn() -> T,
+        R: Fn() -> *const (),
     {
-        LocalNode::with(|local| {
-            let val = unsafe { T::from_ptr(ptr) };
+        // Ordered after the change of the storage.
+        sync::loom_fence();
+
+        // The readers without a node don't use debts.
+        domain.overflow().wait_for_readers();
+
+        LocalNode::with_domain(domain, |local| {
             // Pre-pay one ref count that can be safely put into a debt slot to pay it.
-            T::inc(&val);
+            thin::inc::<T>(ptr);
 
-            Node::traverse::<(), _>(|node| {
+            // The nodes nobody owns, maybe left behind by terminated threads.
+            let mut unused = 0;
+            domain.traverse_as::<(), _>(local, |node| {
+                if node.is_unclaimed() {
+                    unused += 1;
+                }
                 // Make the cooldown trick know we are poking into this node.
                 let _reservation = node.reserve_writer();
 
-                local.help(node, storage_addr, &replacement);
+                local.help::<R, T>(domain, node, storage_addr, &replacement);
 
                 let all_slots = node
                     .fast_slots()
-                    .chain(std::iter::once(node.helping_slot()));
+                    .chain(core::iter::once(node.helping_slot()));
                 for slot in all_slots {
                     // Note: Release is enough even here. That makes sure the increment is
                     // visible to whoever might acquire on this slot and can't leak below this.
                     // And we are the ones doing decrements anyway.
-                    if slot.pay::<T>(ptr) {
+                    if slot.pay(ptr) {
+                        metric!(debts_paid);
                         // Pre-pay one more, for another future slot
-                        T::inc(&val);
+                        thin::inc::<T>(ptr);
                     }
                 }
 
                 None
             });
-            // Implicit dec by dropping val in here, pair for the above
+            // Pair for the pre-paid one above
+            thin::dec::<T>(ptr);
+
+            domain.reclaim_lazily(unused);
         })
     }
 }
@@ -0,0 +1,119 @@
+//! The fallback for threads without a debt node.
+//!
+//! If the number of nodes is limited and all of them are taken, a thread has no slots to put its
+//! debts in. Its reads then [pin](super::epoch) an epoch of the domain, load the pointer and
+//! increment the reference count before unpinning. The writers wait for such readers after the
+//! pointer is replaced ‒ but only if there's any such reader at all, otherwise the cost for them is
+//! a load of two counters.
+//!
+//! The ordering argument is similar to the one in the `wait` module. The reader first pins and
+//! then loads the pointer, the writer first replaces the pointer and then looks at the pins (all
+//! SeqCst). Therefore either the writer sees the reader (and waits for it) or the reader gets the
+//! new pointer.
+//!
+//! Writers without a node still need a place to help the readers in the helping slots from, so
+//! there's a reserve, used by one such writer at a time.
+//!
+//! Each [`Domain`][super::Domain] has its own fallback. But all the instances using the same
+//! domain (all the ones with the default strategy share the global one) share it, the readers
+//! don't know which instance the writer changes.
+
+use alloc::boxed::Box;
+use core::ptr;
+use core::sync::atomic::Ordering::*;
+
+use super::epoch::Epoch;
+use super::helping::Slots as HelpingSlots;
+use crate::spin::{Mutex, MutexGuard};
+use crate::sync::{self, AtomicPtr};
+
+/// The fallback of one domain.
+pub(crate) struct Overflow {
+    /// The readers currently in the fallback.
+    readers: Epoch,
+    /// Serializes the writers waiting for the readers (required by the [`Epoch`]).
+    writers: Mutex<()>,
+    /// The helping slots for the writers without a node, created on first use.
+    reserve: AtomicPtr<Mutex<HelpingSlots>>,
+}
+
+impl Overflow {
+    const_fn! {
+        pub(crate) fn new() -> Self {
+            Overflow {
+                readers: Epoch::new(),
+                writers: Mutex::new(()),
+                reserve: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+    }
+
+    /// Runs the `load` so that no writer finishes in the meantime.
+    ///
+    /// The `load` is expected to load the pointer and increment its reference count.
+    pub(crate) fn protect<R, F: FnOnce() -> R>(&self, load: F) -> R {
+        let _pinned = self.readers.pin();
+        load()
+    }
+
+    /// Waits for the readers that might have loaded the replaced pointer.
+    ///
+    /// To be called after the pointer is replaced.
+    #[inline]
+    pub(crate) fn wait_for_readers(&self) {
+        if self.readers.is_pinned() {
+            let _writers = self.writers.lock();
+            self.readers.synchronize();
+        }
+    }
+
+    /// Forgets the readers and writers that no longer exist.
+    ///
+    /// # Safety
+    ///
+    /// Only the current thread exists and it is not in the middle of any operation (eg. after a
+    /// `fork`).
+    #[cfg(unix)]
+    pub(super) unsafe fn after_fork_child(&self) {
+        self.readers.reset();
+        self.writers.force_unlock();
+        // A writer might have been in the middle of helping with the old one, start with a new one
+        // (the old one is leaked).
+        self.reserve.store(ptr::null_mut(), SeqCst);
+    }
+
+    /// The helping slots for the writers without a node.
+    pub(super) fn reserve(&self) -> MutexGuard<'_, HelpingSlots> {
+        let current = self.reserve.load(Acquire);
+        let reserve = if let Some(reserve) = unsafe { current.as_ref() } {
+            reserve
+        } else {
+            let mut slots = HelpingSlots::default();
+            slots.init();
+            let new = Box::into_raw(Box::new(Mutex::new(slots)));
+            match self
+                .reserve
+                .compare_exchange(ptr::null_mut(), new, AcqRel, Acquire)
+            {
+                // Freed together with the domain.
+                Ok(_) => unsafe { &*new },
+                Err(other) => {
+                    // Someone was faster, use theirs.
+                    drop(unsafe { Box::from_raw(new) });
+                    unsafe { &*other }
+                }
+            }
+        };
+        reserve.lock()
+    }
+}
+
+impl Drop for Overflow {
+    fn drop(&mut self) {
+        let reserve = sync::load_mut(&mut self.reserve);
+        if !reserve.is_null() {
+            // Nobody uses the domain any more.
+            drop(unsafe { Box::from_raw(reserve) });
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Looking into the internal bookkeeping.
+//!
+//! Each thread loading from an [`ArcSwapAny`][crate::ArcSwapAny] (with the default strategy) owns
+//! a node with a few slots for its [`Guard`][crate::Guard]s (see the
+//! [`nodes`][crate::nodes] module). This reports their current state, which can help to explain
+//! some performance problems:
+//!
+//! * Every write walks all the nodes. Many nodes make the writes slow.
+//! * If a thread holds too many guards at once, its fast slots are all occupied and further loads
+//!   use the slower fallback (the helping slot). See the
+//!   [limitations](crate::docs::limitations#too-many-guards).
+//!
+//! Only the shared nodes are reported, not the private ones of the instances with an
+//! [`IndependentStrategy`][crate::IndependentStrategy].
+//!
+//! The report is taken without stopping the other threads. It is not an atomic snapshot, the
+//! individual nodes may be looked at in slightly different times.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use arc_swap::ArcSwap;
+//! use arc_swap::diagnostics;
+//!
+//! let shared = ArcSwap::from_pointee(42);
+//! let guards = (0..3).map(|_| shared.load()).collect::<Vec<_>>();
+//!
+//! assert!(diagnostics::thread_has_node());
+//! let report = diagnostics::report();
+//! assert!(report.used() >= 1);
+//! let busiest = report.nodes().iter().map(|n| n.fast_occupied()).max().unwrap();
+//! assert!(busiest >= 3);
+//! # drop(guards);
+//! ```
+
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering::*;
+
+use crate::debt::{Debt, Domain, LocalNode};
+
+/// What a node is being used for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum NodeState {
+    /// The node is owned by a thread.
+    Used,
+    /// The node was given up by a thread recently and can't be reused for a while yet.
+    Cooldown,
+    /// The node can be claimed by a thread (or freed).
+    Unused,
+}
+
+/// State of a single node.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct NodeReport {
+    state: NodeState,
+    fast_slots: usize,
+    fast_occupied: usize,
+    helping_occupied: bool,
+}
+
+impl NodeReport {
+    /// What the node is being used for.
+    pub fn state(&self) -> NodeState {
+        self.state
+    }
+
+    /// Total number of fast slots in the node.
+    ///
+    /// It may be larger than the default if a [strategy][crate::strategy::hybrid::Config] with
+    /// more slots was used in the thread owning it.
+    pub fn fast_slots(&self) -> usize {
+        self.fast_slots
+    }
+
+    /// Number of fast slots currently holding a debt.
+    ///
+    /// Each occupied slot belongs to some live [`Guard`][crate::Guard] (or one that was just
+    /// dropped).
+    pub fn fast_occupied(&self) -> usize {
+        self.fast_occupied
+    }
+
+    /// Is the helping (fallback) slot currently holding a debt?
+    pub fn helping_occupied(&self) -> bool {
+        self.helping_occupied
+    }
+}
+
+/// State of all the nodes.
+///
+/// Created by [`report`].
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    nodes: Vec<NodeReport>,
+}
+
+impl Report {
+    /// The individual nodes, in the order the writers walk them.
+    pub fn nodes(&self) -> &[NodeReport] {
+        &self.nodes
+    }
+
+    fn count(&self, state: NodeState) -> usize {
+        self.nodes.iter().filter(|node| node.state == state).count()
+    }
+
+    /// Number of nodes owned by some thread.
+    pub fn used(&self) -> usize {
+        self.count(NodeState::Used)
+    }
+
+    /// Number of nodes in the cooldown.
+    pub fn cooldown(&self) -> usize {
+        self.count(NodeState::Cooldown)
+    }
+
+    /// Number of nodes available to be claimed.
+    pub fn unused(&self) -> usize {
+        self.count(NodeState::Unused)
+    }
+
+    /// Total number of fast slots holding a debt, in all the nodes.
+    pub fn fast_occupied(&self) -> usize {
+        self.nodes.iter().map(NodeReport::fast_occupied).sum()
+    }
+
+    /// Number of nodes with an occupied helping slot.
+    pub fn helping_occupied(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| node.helping_occupied)
+            .count()
+    }
+}
+
+fn occupied(slot: &Debt) -> bool {
+    slot.0.load(Relaxed) != Debt::NONE
+}
+
+/// Looks at the current state of the nodes.
+pub fn report() -> Report {
+    let mut nodes = Vec::new();
+    Domain::global().traverse::<(), _>(|node| {
+        nodes.push(NodeReport {
+            state: node.state(),
+            fast_slots: node.fast_slots().count(),
+            fast_occupied: node.fast_slots().filter(|slot| occupied(slot)).count(),
+            helping_occupied: occupied(node.helping_slot()),
+        });
+        None
+    });
+    Report { nodes }
+}
+
+/// Does the calling thread currently own a node?
+///
+/// A thread gets one on its first load. It may be left without one if the
+/// [limit][crate::nodes::set_limit] is reached.
+pub fn thread_has_node() -> bool {
+    LocalNode::thread_has_node()
+}
+
+// Without std, the nodes are only borrowed for the duration of each operation.
+#[cfg(all(test, any(feature = "std", not(feature = "no-std"))))]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::ArcSwap;
+
+    #[test]
+    fn thread_node() {
+        thread::spawn(|| {
+            assert!(!thread_has_node());
+            let shared = ArcSwap::from_pointee(1);
+            let _guard = shared.load();
+            assert!(thread_has_node());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn occupied_slots() {
+        let shared = ArcSwap::from_pointee(1);
+        let guards = (0..2).map(|_| shared.load()).collect::<Vec<_>>();
+        let report = report();
+        assert!(report.used() >= 1);
+        assert_eq!(
+            report.nodes().len(),
+            report.used() + report.cooldown() + report.unused()
+        );
+        // Other tests may hold some guards too, at least ours are there.
+        assert!(report
+            .nodes()
+            .iter()
+            .any(|node| node.fast_occupied() >= guards.len()));
+    }
+}
@@ -13,7 +13,13 @@
 //! implements it for [`Rc`] and [`Weak`], though the actual usefulness of these is a bit
 //! questionable).
 //!
-//! The raw pointer is stored inside an [`AtomicPtr`].
+//! The raw pointer is stored inside an [`AtomicPtr`]. If the pointer is fat (the pointee is
+//! unsized), it doesn't fit. In such case the whole smart pointer is moved into a small heap cell
+//! with its own reference count and the pointer to the cell is stored instead. The rest of the
+//! machinery (debts, paying them) then works with the cell as if it was the reference counted
+//! thing. Each reference of the cell also owns a reference of the smart pointer inside, so the
+//! value can be taken out of the cell without touching the smart pointer's count. The dead cells
+//! are cached per thread and reused.
 //!
 //! # Protection of reference counts
 //!
@@ -83,8 +89,11 @@
 //!
 //! # Memory orders around debts
 //!
-//! The linked list of debt nodes only grows. The shape of the list (existence of nodes) is
-//! synchronized through Release on creation and Acquire on load on the head pointer.
+//! New debt nodes are prepended to the linked list. The shape of the list (existence of nodes) is
+//! synchronized through Release on creation and Acquire on load on the head pointer. Nodes are
+//! removed only when nothing points into them ‒ a paid debt stays in its slot (marked as paid)
+//! until the reader notices, so a node with an occupied slot is still in use. The removed nodes
+//! are freed once all the writers that might have been walking them are done.
 //!
 //! The debts work similar to locks ‒ Acquire and Release make all the pointer manipulation at the
 //! interval where it is written down. However, we use the SeqCst on the allocation of the debt
@@ -1,26 +1,50 @@
 //! Limitations and common pitfalls.
 //!
-//! # Sized types
+//! # Unsized types
 //!
-//! This currently works only for `Sized` types. Unsized types have „fat pointers“, which are twice
-//! as large as the normal ones. The [`AtomicPtr`] doesn't support them. One could use something
-//! like `AtomicU128` for them. The catch is this doesn't exist and the difference would make it
-//! really hard to implement the debt storage/stripped down hazard pointers.
-//!
-//! A workaround is to use double indirection:
+//! Unsized types (`str`, `[T]`, `dyn Trait`) have „fat pointers“, which are twice as large as the
+//! normal ones. The [`AtomicPtr`] doesn't support them and there's no double-word atomic to use
+//! instead. Therefore, when the pointee is unsized, each stored value is put into a small
+//! reference-counted heap cell and the pointer to that cell is stored instead.
 //!
 //! ```rust
+//! # use std::sync::Arc;
 //! # use arc_swap::ArcSwap;
-//! // This doesn't work:
-//! // let data: ArcSwap<[u8]> = ArcSwap::new(Arc::from([1, 2, 3]));
-//!
-//! // But this does:
-//! let data: ArcSwap<Box<[u8]>> = ArcSwap::from_pointee(Box::new([1, 2, 3]));
-//! # drop(data);
+//! let data: ArcSwap<[u8]> = ArcSwap::new(Arc::from(vec![1, 2, 3]));
+//! assert_eq!(&[1, 2, 3], &**data.load());
 //! ```
 //!
-//! It also may be possible to use `ArcSwap` with the [`triomphe::ThinArc`] (that crate needs
-//! enabling a feature flag to cooperate with `ArcSwap`).
+//! Trait objects of other traits need the [`Pointee`] trait implemented (by the crate that
+//! defines the trait), see there.
+//!
+//! Compared to the double indirection that one would use otherwise (eg. `ArcSwap<Box<[u8]>>`,
+//! which is `Arc<Box<[u8]>>` inside), the data are not behind an additional pointer:
+//!
+//! * The loads read the cell once, to copy the fat pointer out of it into the [`Guard`]. The
+//!   accesses through the [`Guard`] then go straight to the data. The same goes for the
+//!   [`Cache`][crate::Cache], which does the copy only when the value changes.
+//! * Each store/swap/successful compare-and-swap of an unsized value needs a cell. The dead cells
+//!   are kept in a small per-thread cache and reused (for any unsized type with a two-word
+//!   pointer), so the steady state doesn't allocate. The first stores of a thread, threads that
+//!   store more than they release and `no_std` builds do allocate.
+//! * Each writer that needs to hand a value to a reader bumps both the cell's count and the one
+//!   of the `Arc` inside (one more atomic increment than with sized types).
+//! * The cell is freed (or cached) only after the last [`Guard`] pointing into it goes away.
+//!
+//! Sized types are not affected in any way. If the read of the cell matters, store a thin pointer
+//! (see below).
+//!
+//! Other consequences:
+//!
+//! * Only the address part of the pointer is used to decide identity (eg. in
+//!   [`compare_and_swap`][crate::ArcSwapAny::compare_and_swap]), the metadata is ignored. The raw
+//!   pointers of unsized values are pointers to their [`Base`][crate::Pointee::Base] (eg. `*const u8`
+//!   for `str`).
+//! * `ArcSwapOption` of unsized types stores the `None` directly, without a cell.
+//!
+//! It is also possible to use `ArcSwapAny` with the [`triomphe::ThinArc`] (with the `triomphe`
+//! feature of this crate), which avoids the cell. It keeps the length of the slice inside the
+//! allocation, so the pointer is thin.
 //!
 //! # Too many [`Guard`]s
 //!
@@ -31,9 +55,14 @@
 //! If too many [`Guard`]s are kept around, the performance might be poor. These are not intended
 //! to be stored in data structures or used across async yield points.
 //!
+//! The [`diagnostics`] module can show how many slots are currently occupied. If many guards are
+//! needed, a strategy with more slots can be [configured][crate::strategy::hybrid::Config].
+//!
 //! [`ArcSwap`]: crate::ArcSwap
+//! [`diagnostics`]: crate::diagnostics
 //! [`Guard`]: crate::Guard
 //! [`AtomicPtr`]: std::sync::atomic::AtomicPtr
+//! [`Pointee`]: crate::Pointee
 //!
 //! # No `Clone` implementation
 //!
@@ -50,4 +79,14 @@
 //! # let _ = new;
 //! ```
 //!
+//! # Observed instances live in an Arc
+//!
+//! The [observers][crate::observe] are kept in a global registry, found by the address of the
+//! instance. To make sure the address doesn't change (or gets reused by another instance) while
+//! observed, [`observe`] can be called only on an instance inside an [`Arc`]. The writes into an
+//! observed instance take a lock and clone the new value, to report the changes in order.
+//!
+//! [`observe`]: crate::ArcSwapAny::observe
+//! [`Arc`]: std::sync::Arc
+//!
 //! [`triomphe::ThinArc`]: https://docs.rs/triomphe/latest/triomphe/struct.ThinArc.html
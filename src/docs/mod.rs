@@ -14,8 +14,7 @@
 //! # Features
 //!
 //! The `weak` feature adds the ability to use arc-swap with the [`Weak`] pointer too,
-//! through the [`ArcSwapWeak`] type. The needed std support is stabilized in rust version 1.45 (as
-//! of now in beta).
+//! through the [`ArcSwapWeak`] type.
 //!
 //! The `experimental-strategies` enables few more strategies that can be used. Note that these
 //! **are not** part of the API stability guarantees and they may be changed, renamed or removed at
@@ -23,9 +22,9 @@
 //!
 //! # Minimal compiler version
 //!
-//! The `1` versions will compile on all compilers supporting the 2018 edition. Note that this
-//! applies only if no additional feature flags are enabled and does not apply to compiling or
-//! running tests.
+//! The minimal supported Rust version is 1.65 (earlier `1` versions compiled on all compilers
+//! supporting the 2018 edition). Note that this applies only to the features that don't pull in
+//! other crates and does not apply to compiling or running tests.
 //!
 //! [`ArcSwapAny`]: crate::ArcSwapAny
 //! [`ArcSwapWeak`]: crate::ArcSwapWeak
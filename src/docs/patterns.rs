@@ -127,6 +127,34 @@
 //! work.phase_2(&config.load());
 //! ```
 //!
+//! The same problem appears when the configuration is split into several independent
+//! [`ArcSwap`]s (maybe because parts of it are updated by different threads or at different
+//! rates). Loading them one after another can produce a combination that never existed ‒ one of
+//! them could have been replaced in between the loads. The [`load_consistent`] loads several of
+//! them so that the values all were there at the same moment:
+//!
+//! ```rust
+//! # use arc_swap::ArcSwap;
+//! # use arc_swap::snapshot::load_consistent;
+//! # struct Routes;
+//! # struct Limits;
+//! # struct Work;
+//! # impl Work {
+//! #     fn fetch() -> Self { Work }
+//! #     fn process(&self, _: &Routes, _: &Limits) {}
+//! # }
+//! # let routes = ArcSwap::from_pointee(Routes);
+//! # let limits = ArcSwap::from_pointee(Limits);
+//! let work = Work::fetch();
+//! let (routes, limits) = load_consistent((&routes, &limits));
+//! work.process(&routes, &limits);
+//! ```
+//!
+//! Note that this only prevents reading in the middle of a write to one of them. If the writer
+//! changes them one by one, the reader can still get a snapshot in between the two writes. If the
+//! instances must change together, the writer can use a
+//! [`Transaction`][crate::transaction::Transaction].
+//!
 //! # Caching of the configuration
 //!
 //! Let's say that the work chunks are really small, but there's *a lot* of them to work on. Maybe
@@ -266,6 +294,7 @@
 //! [`load`]: crate::ArcSwapAny::load
 //! [`ArcSwap`]: crate::ArcSwap
 //! [`Cache`]: crate::cache::Cache
+//! [`load_consistent`]: crate::snapshot::load_consistent
 //! [`Access`]: crate::access::Access
 //! [`DynAccess`]: crate::access::DynAccess
 //! [`Constant`]: crate::access::Constant
@@ -0,0 +1,116 @@
+//! Support for `fork`.
+//!
+//! After `fork`, the child process has only the thread that called it. But the bookkeeping of the
+//! library still remembers the other threads ‒ their nodes (see the [`nodes`][crate::nodes]
+//! module) stay taken forever, every writer keeps walking them and some of them may have been in
+//! the middle of an operation, holding a lock the child would wait on.
+//!
+//! The [`after_fork_child`] resets these. It needs to be called in the child, before it does
+//! anything else with the library (eg. from a `pthread_atfork` handler or right after the `fork`
+//! returns).
+//!
+//! With the `fork-hook` feature, the library registers such a `pthread_atfork` handler itself (the
+//! first time a thread claims a debt node). The requirements of [`after_fork_child`] still apply,
+//! only the call is done automatically: the thread calling `fork` must not be in the middle of an
+//! operation of this library (eg. forking from inside an `rcu` closure is not allowed).
+//!
+//! Some things can't be reset:
+//!
+//! * The [`Guard`][crate::Guard]s held by the vanished threads are never dropped. The values they
+//!   point to are leaked (once a writer replaces them).
+//! * The nodes of [`ThreadNode`][crate::nodes::ThreadNode]s and
+//!   [`ReaderHandle`][crate::nodes::ReaderHandle]s are kept, these objects may have survived. A
+//!   [`NodeProvider`][crate::nodes::NodeProvider] that keeps its nodes in thread locals should
+//!   drop the ones of the vanished threads itself.
+//! * Instances with a [private domain][crate::IndependentStrategy] may keep some nodes taken.
+//! * Writes or [transactions][crate::transaction] in progress in the vanished threads are left
+//!   as they are (possibly only partially done).
+//! * A vanished thread might have been in the middle of a load, with a writer handing a value
+//!   over to it. The envelopes for these handovers (in the released nodes and in the fallback for
+//!   threads without a node) are replaced by new ones and the old ones are leaked, together with
+//!   the value possibly inside.
+//! * Background threads (eg. of the [reclaim][crate::reclaim] module) don't exist in the child.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! # #[cfg(unix)] {
+//! use arc_swap::ArcSwap;
+//!
+//! extern "C" {
+//!     fn pthread_atfork(
+//!         prepare: Option<unsafe extern "C" fn()>,
+//!         parent: Option<unsafe extern "C" fn()>,
+//!         child: Option<unsafe extern "C" fn()>,
+//!     ) -> i32;
+//! }
+//!
+//! unsafe extern "C" fn child() {
+//!     // The child has just the one thread and it's not doing anything else right now.
+//!     unsafe { arc_swap::after_fork_child() };
+//! }
+//!
+//! assert_eq!(0, unsafe { pthread_atfork(None, None, Some(child)) });
+//! let shared = ArcSwap::from_pointee(42);
+//! // Fork here and use it in the child.
+//! assert_eq!(42, **shared.load());
+//! # }
+//! ```
+
+/// Resets the bookkeeping left behind by the threads that don't exist after `fork`.
+///
+/// The nodes of the vanished threads are released, for other threads to use, and locks they might
+/// have held are unlocked. See the [module documentation](self) for details.
+///
+/// # Safety
+///
+/// It must be called in the child process after `fork`, while it has only the one thread (the
+/// one calling this). The thread must not be in the middle of any operation of this library (eg.
+/// inside an `rcu` closure or a [`NodeProvider`][crate::nodes::NodeProvider]).
+///
+/// Calling it in a process with other threads (which are still alive) is undefined behaviour.
+///
+/// # Leaks
+///
+/// The envelopes the writers use to hand values over to the vanished threads in the middle of a
+/// load are abandoned, not freed, as it can't be known if a writer was just using them. This
+/// leaks a small allocation for each released node and one for the fallback, together with the
+/// value inside if there was a handover in progress.
+pub unsafe fn after_fork_child() {
+    crate::debt::after_fork_child();
+    crate::seq::after_fork_child();
+    crate::transaction::after_fork_child();
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    crate::wait::after_fork_child();
+    #[cfg(feature = "metrics")]
+    crate::metrics::after_fork_child();
+}
+
+/// Registers [`after_fork_child`] as the `pthread_atfork` child handler, the first time it's
+/// called.
+#[cfg(feature = "fork-hook")]
+pub(crate) fn register_hook() {
+    use core::ffi::c_int;
+    use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+    extern "C" {
+        fn pthread_atfork(
+            prepare: Option<unsafe extern "C" fn()>,
+            parent: Option<unsafe extern "C" fn()>,
+            child: Option<unsafe extern "C" fn()>,
+        ) -> c_int;
+    }
+
+    unsafe extern "C" fn child() {
+        after_fork_child();
+    }
+
+    static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+    if REGISTERED.load(Relaxed) || REGISTERED.swap(true, Relaxed) {
+        return;
+    }
+    // It can fail only on lack of memory. There's nothing better to do than go on without it, like
+    // without the feature.
+    unsafe { pthread_atfork(None, None, Some(child)) };
+}
@@ -2,6 +2,7 @@
 #![warn(missing_docs)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 #![allow(deprecated)]
+#![cfg_attr(all(feature = "no-std", not(any(feature = "std", test))), no_std)]
 
 //! Making [`Arc`][Arc] itself atomic
 //!
@@ -82,6 +83,17 @@
 //! of application from each other (eg. giving a component access to only its own part of
 //! configuration while still having it reloaded as a whole).
 //!
+//! For single-threaded code, the [`local`] module offers the same API over a plain cell.
+//!
+//! The `triomphe` and `servo_arc` features add support for the `Arc`s from these crates. These
+//! don't have the weak count and the `triomphe::ThinArc` is stored without the indirection needed
+//! for other slices (see [`limitations`][crate::docs::limitations]).
+//!
+//! With the `no-std` feature (and without the default `std` one), the crate is `no_std` (it still
+//! needs `alloc`). The parts that need the OS (blocking waits, notifications, background
+//! reclamation) are not available then and the per-thread bookkeeping can be provided through the
+//! [`nodes`] module. Disabling just the default features keeps using the standard library.
+//!
 //! # Before using
 //!
 //! The data structure is a bit niche. Before using, please check the
@@ -124,36 +136,112 @@
 //!
 //! [RwLock]: https://doc.rust-lang.org/std/sync/struct.RwLock.html
 
+extern crate alloc;
+
+/// Counts the event in the current thread, if the `metrics` feature is on.
+///
+/// The argument is the name of the counter.
+macro_rules! metric {
+    ($counter: ident) => {
+        #[cfg(feature = "metrics")]
+        $crate::metrics::record(|counters| &counters.$counter);
+    };
+}
+
+/// A `const fn`, except under loom (its primitives can't be created in a const context).
+macro_rules! const_fn {
+    ($(#[$attr: meta])* $vis: vis fn $name: ident($($args: tt)*) -> $ret: ty $body: block) => {
+        #[cfg(not(loom))]
+        $(#[$attr])*
+        $vis const fn $name($($args)*) -> $ret $body
+
+        #[cfg(loom)]
+        $(#[$attr])*
+        $vis fn $name($($args)*) -> $ret $body
+    };
+}
+
+/// A `static`, except under loom it's created anew for each explored execution.
+macro_rules! global {
+    ($(#[$attr: meta])* static $name: ident: $ty: ty = $init: expr;) => {
+        #[cfg(not(loom))]
+        $(#[$attr])*
+        static $name: $ty = $init;
+
+        #[cfg(loom)]
+        loom::lazy_static! {
+            $(#[$attr])*
+            static ref $name: $ty = $init;
+        }
+    };
+}
+
 pub mod access;
 mod as_raw;
 pub mod cache;
 mod compile_fail_tests;
 mod debt;
+pub mod diagnostics;
 pub mod docs;
+#[cfg(unix)]
+pub mod fork;
+pub mod local;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod nodes;
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+pub mod notify;
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+pub mod observe;
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+pub mod reclaim;
 mod ref_cnt;
+pub mod retry;
+mod seq;
 #[cfg(feature = "serde")]
 mod serde;
+#[cfg(feature = "servo_arc")]
+mod servo_arc;
+pub mod snapshot;
+mod spin;
 pub mod strategy;
+mod sync;
+mod thin;
+pub mod thread;
+pub mod transaction;
+#[cfg(feature = "triomphe")]
+mod triomphe;
+pub mod versioned;
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+mod wait;
 #[cfg(feature = "weak")]
 mod weak;
 
-use std::borrow::Borrow;
-use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
-use std::marker::PhantomData;
-use std::mem;
-use std::ops::Deref;
-use std::ptr;
-use std::sync::atomic::{AtomicPtr, Ordering};
-use std::sync::Arc;
+use alloc::sync::Arc;
+use core::borrow::Borrow;
+use core::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::Deref;
+#[cfg(not(loom))] // Only for the const_empty
+use core::ptr;
+use core::sync::atomic::Ordering;
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+use std::time::Duration;
 
 use crate::access::{Access, Map};
 pub use crate::as_raw::AsRaw;
 pub use crate::cache::Cache;
-pub use crate::ref_cnt::RefCnt;
+#[cfg(unix)]
+pub use crate::fork::after_fork_child;
+pub use crate::nodes::preallocate;
+pub use crate::ref_cnt::{Pointee, RefCnt};
+#[cfg(not(loom))] // Only for the const_empty
 use crate::strategy::hybrid::{DefaultConfig, HybridStrategy};
 use crate::strategy::sealed::Protected;
 use crate::strategy::{CaS, Strategy};
 pub use crate::strategy::{DefaultStrategy, IndependentStrategy};
+use crate::sync::AtomicPtr;
 
 /// A temporary storage of the pointer.
 ///
@@ -197,6 +285,56 @@ impl<T: RefCnt, S: Strategy<T>> Guard<T, S> {
             inner: S::Protected::from_inner(inner),
         }
     }
+
+    /// Projects the guard to a part of the loaded value.
+    ///
+    /// The resulting guard keeps the original one (and whatever protection it has) alive, so it's
+    /// as cheap as the [`load`][ArcSwapAny::load] itself ‒ nothing is cloned and the storage is
+    /// not accessed again (unlike with [`map`][ArcSwapAny::map]).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use arc_swap::{ArcSwap, Guard, MappedGuard};
+    ///
+    /// struct Config {
+    ///     database: String,
+    /// }
+    ///
+    /// fn database(config: &ArcSwap<Config>) -> MappedGuard<std::sync::Arc<Config>, String> {
+    ///     Guard::map(config.load(), |c| &c.database)
+    /// }
+    ///
+    /// let config = ArcSwap::from_pointee(Config {
+    ///     database: "db.example.com".to_owned(),
+    /// });
+    /// assert_eq!("db.example.com", *database(&config));
+    /// ```
+    // Associated function on purpose, because of deref
+    pub fn map<U, F>(guard: Self, f: F) -> MappedGuard<T, U, S>
+    where
+        T: Deref,
+        U: ?Sized,
+        F: FnOnce(&T::Target) -> &U,
+    {
+        // The reference points into the pointee, which doesn't move with the guard (the RefCnt is
+        // required to be "pinned"), so it lives as long as the guard.
+        let ptr = f(&**guard) as *const U;
+        MappedGuard { guard, ptr }
+    }
+
+    /// A fallible version of [`map`][Guard::map].
+    ///
+    /// Returns `None` (and drops the guard) if the closure does.
+    pub fn try_map<U, F>(guard: Self, f: F) -> Option<MappedGuard<T, U, S>>
+    where
+        T: Deref,
+        U: ?Sized,
+        F: FnOnce(&T::Target) -> Option<&U>,
+    {
+        let ptr = f(&**guard)? as *const U;
+        Some(MappedGuard { guard, ptr })
+    }
 }
 
 impl<T: RefCnt, S: Strategy<T>> Deref for Guard<T, S> {
@@ -231,18 +369,104 @@ impl<T: Display + RefCnt, S: Strategy<T>> Display for Guard<T, S> {
     }
 }
 
+/// A [`Guard`] projected to a part of the loaded value.
+///
+/// Created by [`Guard::map`] and [`Guard::try_map`]. It dereferences directly to the part.
+pub struct MappedGuard<T: RefCnt, U: ?Sized, S: Strategy<T> = DefaultStrategy> {
+    guard: Guard<T, S>,
+    // Points into the value protected by the guard.
+    ptr: *const U,
+}
+
+impl<T: RefCnt, U: ?Sized, S: Strategy<T>> MappedGuard<T, U, S> {
+    /// Projects the guard further.
+    pub fn map<V, F>(mapped: Self, f: F) -> MappedGuard<T, V, S>
+    where
+        V: ?Sized,
+        F: FnOnce(&U) -> &V,
+    {
+        let ptr = f(&*mapped) as *const V;
+        MappedGuard {
+            guard: mapped.guard,
+            ptr,
+        }
+    }
+
+    /// A fallible version of [`map`][MappedGuard::map].
+    pub fn try_map<V, F>(mapped: Self, f: F) -> Option<MappedGuard<T, V, S>>
+    where
+        V: ?Sized,
+        F: FnOnce(&U) -> Option<&V>,
+    {
+        let ptr = f(&*mapped)? as *const V;
+        Some(MappedGuard {
+            guard: mapped.guard,
+            ptr,
+        })
+    }
+
+    /// Returns the original guard, for the whole value.
+    pub fn into_guard(mapped: Self) -> Guard<T, S> {
+        mapped.guard
+    }
+}
+
+impl<T: RefCnt, U: ?Sized, S: Strategy<T>> Deref for MappedGuard<T, U, S> {
+    type Target = U;
+    #[inline]
+    fn deref(&self) -> &U {
+        // Safety: the guard keeps the value alive and the value doesn't move.
+        unsafe { &*self.ptr }
+    }
+}
+
+// The raw pointer is really a shared reference to U.
+unsafe impl<T, U, S> Send for MappedGuard<T, U, S>
+where
+    T: RefCnt,
+    U: ?Sized + Sync,
+    S: Strategy<T>,
+    Guard<T, S>: Send,
+{
+}
+
+unsafe impl<T, U, S> Sync for MappedGuard<T, U, S>
+where
+    T: RefCnt,
+    U: ?Sized + Sync,
+    S: Strategy<T>,
+    Guard<T, S>: Sync,
+{
+}
+
+impl<T: RefCnt, U: Debug + ?Sized, S: Strategy<T>> Debug for MappedGuard<T, U, S> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        self.deref().fmt(formatter)
+    }
+}
+
+impl<T: RefCnt, U: Display + ?Sized, S: Strategy<T>> Display for MappedGuard<T, U, S> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        self.deref().fmt(formatter)
+    }
+}
+
 /// Comparison of two pointer-like things.
+///
+/// Only the addresses are compared (fat pointers to the same object may carry different
+/// metadata, eg. vtables from different codegen units).
 // A and B are likely to *be* references, or thin wrappers around that. Calling that with extra
 // reference is just annoying.
 #[allow(clippy::needless_pass_by_value)]
-fn ptr_eq<Base, A, B>(a: A, b: B) -> bool
+pub(crate) fn ptr_eq<Base, A, B>(a: A, b: B) -> bool
 where
+    Base: ?Sized,
     A: AsRaw<Base>,
     B: AsRaw<Base>,
 {
-    let a = a.as_raw();
-    let b = b.as_raw();
-    ptr::eq(a, b)
+    let a = thin::addr(a.as_raw());
+    let b = thin::addr(b.as_raw());
+    a == b
 }
 
 /// An atomic storage for a reference counted smart pointer like [`Arc`] or `Option<Arc>`.
@@ -267,8 +491,11 @@ where
 /// # Type parameters
 ///
 /// * `T`: The smart pointer to be kept inside. This crate provides implementation for `Arc<_>` and
-///   `Option<Arc<_>>` (`Rc` too, but that one is not practically useful). But third party could
-///   provide implementations of the [`RefCnt`] trait and plug in others.
+///   `Option<Arc<_>>` (`Rc` too, but that one is not practically useful), and for the `Arc`s from
+///   `triomphe` and `servo_arc` behind the feature flags of the same name. But third party could
+///   provide implementations of the [`RefCnt`] trait and plug in others. The `Arc` may point to
+///   an unsized type, like `Arc<str>` or `Arc<dyn Trait>` (see the
+///   [limitations][crate::docs::limitations] for the costs).
 /// * `S`: Chooses the [strategy] used to protect the data inside. They come with various
 ///   performance trade offs, the default [`DefaultStrategy`] is good rule of thumb for most use
 ///   cases.
@@ -300,9 +527,11 @@ where
 /// [`from`]: https://doc.rust-lang.org/nightly/std/convert/trait.From.html#tymethod.from
 /// [`RefCnt`]: trait.RefCnt.html
 pub struct ArcSwapAny<T: RefCnt, S: Strategy<T> = DefaultStrategy> {
-    // Notes: AtomicPtr needs Sized
     /// The actual pointer, extracted from the Arc.
-    ptr: AtomicPtr<T::Base>,
+    ///
+    /// This is a word as produced by the [thin] module ‒ either the pointer itself or a pointer
+    /// to a cell holding the fat one.
+    ptr: AtomicPtr<()>,
 
     /// We are basically an Arc in disguise. Inherit parameters from Arc by pretending to contain
     /// it.
@@ -320,12 +549,14 @@ impl<T: RefCnt, S: Default + Strategy<T>> From<T> for ArcSwapAny<T, S> {
 
 impl<T: RefCnt, S: Strategy<T>> Drop for ArcSwapAny<T, S> {
     fn drop(&mut self) {
-        let ptr = *self.ptr.get_mut();
+        #[cfg(any(feature = "std", not(feature = "no-std")))]
+        observe::forget(self.addr());
+        let ptr = sync::load_mut(&mut self.ptr);
         unsafe {
             // To pay any possible debts
             self.strategy.wait_for_readers(ptr, &self.ptr);
             // We are getting rid of the one stored ref count
-            T::dec(ptr);
+            thin::dec::<T>(ptr);
         }
     }
 }
@@ -371,7 +602,7 @@ impl<T: RefCnt, S: Strategy<T>> ArcSwapAny<T, S> {
         // The AtomicPtr requires *mut in its interface. We are more like *const, so we cast it.
         // However, we always go back to *const right away when we get the pointer on the other
         // side, so it should be fine.
-        let ptr = T::into_ptr(val);
+        let ptr = thin::into_word(val);
         Self {
             ptr: AtomicPtr::new(ptr),
             _phantom_arc: PhantomData,
@@ -381,11 +612,13 @@ impl<T: RefCnt, S: Strategy<T>> ArcSwapAny<T, S> {
 
     /// Extracts the value inside.
     pub fn into_inner(mut self) -> T {
-        let ptr = *self.ptr.get_mut();
+        #[cfg(any(feature = "std", not(feature = "no-std")))]
+        observe::forget(self.addr());
+        let ptr = sync::load_mut(&mut self.ptr);
         // To pay all the debts
         unsafe { self.strategy.wait_for_readers(ptr, &self.ptr) };
         mem::forget(self);
-        unsafe { T::from_ptr(ptr) }
+        unsafe { thin::from_word(ptr) }
     }
 
     /// Loads the value.
@@ -399,6 +632,22 @@ impl<T: RefCnt, S: Strategy<T>> ArcSwapAny<T, S> {
         Guard::into_inner(self.load())
     }
 
+    /// Loads the value together with the word it was stored as.
+    ///
+    /// The word can be compared against the storage later (used by the [`Cache`]).
+    fn load_owned(&self, handle: Option<&nodes::ReaderHandle>) -> thin::Owned<T> {
+        let guard = match handle {
+            Some(handle) => self.load_with(handle),
+            None => self.load(),
+        };
+        let word = guard.inner.word();
+        // The guard protects the word while we get our own reference.
+        unsafe {
+            thin::inc::<T>(word);
+            thin::Owned::from_word(word)
+        }
+    }
+
     /// Provides a temporary borrow of the object inside.
     ///
     /// This returns a proxy object allowing access to the thing held inside. However, there's
@@ -450,6 +699,16 @@ impl<T: RefCnt, S: Strategy<T>> ArcSwapAny<T, S> {
         Guard { inner: protected }
     }
 
+    /// Loads the value, using the node of the given handle.
+    ///
+    /// This is the same as [`load`](#method.load), but it skips looking up the node of the
+    /// current thread. See the [`ReaderHandle`][nodes::ReaderHandle].
+    #[inline]
+    pub fn load_with(&self, handle: &nodes::ReaderHandle) -> Guard<T, S> {
+        let protected = unsafe { self.strategy.load_with(&self.ptr, handle) };
+        Guard { inner: protected }
+    }
+
     /// Replaces the value inside this instance.
     ///
     /// Further loads will yield the new value. Uses [`swap`](#method.swap) internally.
@@ -457,17 +716,60 @@ impl<T: RefCnt, S: Strategy<T>> ArcSwapAny<T, S> {
         drop(self.swap(val));
     }
 
+    /// Replaces the value inside this instance and hands the old one to the `reclaimer`.
+    ///
+    /// This is like [`store`](#method.store), but the previous value is dropped by the
+    /// [`Reclaimer`][reclaim::Reclaimer] (eg. in its background thread) instead of the current
+    /// thread.
+    ///
+    /// Only the reference held by this instance is moved to the reclaimer. If a reader still holds
+    /// a [`Guard`] of the old value, it does the final drop when it releases it. To move that drop
+    /// away too, store the values wrapped in [`Deferred`][reclaim::Deferred].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use std::sync::Arc;
+    /// use arc_swap::ArcSwap;
+    /// use arc_swap::reclaim::Reclaimer;
+    ///
+    /// let reclaimer = Reclaimer::background();
+    /// let routes = ArcSwap::from_pointee(vec![0; 1000]);
+    /// routes.store_reclaimed(Arc::new(vec![1; 1000]), &reclaimer);
+    /// reclaimer.flush();
+    /// ```
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    pub fn store_reclaimed(&self, val: T, reclaimer: &reclaim::Reclaimer)
+    where
+        T: Send + 'static,
+    {
+        reclaimer.retire(self.swap(val));
+    }
+
     /// Exchanges the value inside this instance.
     pub fn swap(&self, new: T) -> T {
-        let new = T::into_ptr(new);
+        #[cfg(any(feature = "std", not(feature = "no-std")))]
+        if let Some(observers) = observe::observed(self.addr()) {
+            return observers.write(new, |new| self.swap_unobserved(new), |old| Some(old));
+        }
+        self.swap_unobserved(new)
+    }
+
+    fn swap_unobserved(&self, new: T) -> T {
+        let new = thin::into_word(new);
         // AcqRel needed to publish the target of the new pointer and get the target of the old
         // one.
         //
         // SeqCst to synchronize the time lines with the group counters.
-        let old = self.ptr.swap(new, Ordering::SeqCst);
+        let old = {
+            let _writing = self.writing();
+            self.ptr.swap(new, Ordering::SeqCst)
+        };
+        #[cfg(any(feature = "std", not(feature = "no-std")))]
+        wait::notify(self.addr());
         unsafe {
             self.strategy.wait_for_readers(old, &self.ptr);
-            T::from_ptr(old)
+            thin::from_word(old)
         }
     }
 
@@ -487,13 +789,192 @@ impl<T: RefCnt, S: Strategy<T>> ArcSwapAny<T, S> {
     /// The `current` can be specified as `&Arc`, [`Guard`](struct.Guard.html),
     /// [`&Guards`](struct.Guards.html) or as a raw pointer (but _not_ owned `Arc`). See the
     /// [`AsRaw`] trait.
+    ///
+    /// See [`compare_exchange`](#method.compare_exchange) for a variant telling directly if the
+    /// swap happened.
     pub fn compare_and_swap<C>(&self, current: C, new: T) -> Guard<T, S>
     where
         C: AsRaw<T::Base>,
         S: CaS<T>,
     {
-        let protected = unsafe { self.strategy.compare_and_swap(&self.ptr, current, new) };
-        Guard { inner: protected }
+        match self.compare_exchange(current, new) {
+            Ok(prev) => prev,
+            Err((cur, _)) => cur,
+        }
+    }
+
+    /// Stores the `new` value if the current value is the same as `current`.
+    ///
+    /// This is similar to [`compare_and_swap`](#method.compare_and_swap), but the result tells if
+    /// the swap happened (in the style of [`AtomicPtr::compare_exchange`]):
+    ///
+    /// * `Ok` with the previous value (the one equal to `current`) if the `new` was stored.
+    /// * `Err` with the actual current value and the rejected `new` otherwise, so it is not lost.
+    ///
+    /// Only the pointers are compared, not the values they point to.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use std::sync::Arc;
+    /// # use arc_swap::ArcSwap;
+    /// let shared = ArcSwap::from_pointee(1);
+    /// let orig = shared.load_full();
+    /// let other = Arc::new(2);
+    ///
+    /// // Guessing wrong, we get the new one back
+    /// let (cur, rejected) = shared.compare_exchange(&other, Arc::new(3)).unwrap_err();
+    /// assert!(Arc::ptr_eq(&orig, &cur));
+    /// assert_eq!(3, *rejected);
+    ///
+    /// let prev = shared.compare_exchange(&orig, rejected).unwrap();
+    /// assert!(Arc::ptr_eq(&orig, &prev));
+    /// assert_eq!(3, **shared.load());
+    /// ```
+    ///
+    /// [`AtomicPtr::compare_exchange`]: std::sync::atomic::AtomicPtr::compare_exchange
+    pub fn compare_exchange<C>(&self, current: C, new: T) -> Result<Guard<T, S>, (Guard<T, S>, T)>
+    where
+        C: AsRaw<T::Base>,
+        S: CaS<T>,
+    {
+        self.exchange(new, |new| unsafe {
+            self.strategy
+                .compare_exchange(&self.ptr, current, new, false)
+        })
+    }
+
+    /// Stores the `new` value if the current value is the same as `current`, possibly failing
+    /// spuriously.
+    ///
+    /// This is the same as [`compare_exchange`](#method.compare_exchange), except it is allowed to
+    /// fail even if the current value is equal to `current` (eg. if another thread changes the
+    /// value concurrently and changes it back). In such case, the returned current value may be
+    /// equal to `current`. This may be slightly cheaper when used in a retry loop.
+    ///
+    /// Some strategies may never fail spuriously.
+    pub fn compare_exchange_weak<C>(
+        &self,
+        current: C,
+        new: T,
+    ) -> Result<Guard<T, S>, (Guard<T, S>, T)>
+    where
+        C: AsRaw<T::Base>,
+        S: CaS<T>,
+    {
+        self.exchange(new, |new| unsafe {
+            self.strategy
+                .compare_exchange(&self.ptr, current, new, true)
+        })
+    }
+
+    /// Address of the storage, identifying the instance for reads of multiple instances at once.
+    #[inline]
+    pub(crate) fn addr(&self) -> usize {
+        &self.ptr as *const _ as usize
+    }
+
+    /// Does the `exchange`, reporting and notifying about it if it succeeds.
+    #[inline]
+    fn exchange<F>(&self, new: T, exchange: F) -> Result<Guard<T, S>, (Guard<T, S>, T)>
+    where
+        F: FnOnce(T) -> Result<S::Protected, (S::Protected, T)>,
+    {
+        let exchange = |new| {
+            let result = {
+                let _writing = self.writing();
+                exchange(new)
+            };
+            self.wrap_exchange(result)
+        };
+        #[cfg(any(feature = "std", not(feature = "no-std")))]
+        if let Some(observers) = observe::observed(self.addr()) {
+            return observers.write(new, exchange, |result| {
+                result.as_ref().ok().map(|prev| &**prev)
+            });
+        }
+        exchange(new)
+    }
+
+    /// Marks a write to the storage, for the readers of multiple instances at once.
+    #[inline]
+    fn writing(&self) -> seq::Writing {
+        seq::Writing::begin(self.addr())
+    }
+
+    #[inline]
+    fn wrap_exchange(
+        &self,
+        result: Result<S::Protected, (S::Protected, T)>,
+    ) -> Result<Guard<T, S>, (Guard<T, S>, T)> {
+        match result {
+            Ok(prev) => {
+                #[cfg(any(feature = "std", not(feature = "no-std")))]
+                wait::notify(self.addr());
+                Ok(Guard { inner: prev })
+            }
+            Err((cur, new)) => Err((Guard { inner: cur }, new)),
+        }
+    }
+
+    /// Blocks the current thread until the value is different from `seen`.
+    ///
+    /// Returns the new value, or `None` if the `timeout` passed without the value changing. Only
+    /// the pointers are compared, so storing the same pointer again doesn't count as a change.
+    ///
+    /// The waiting thread is woken up by the writing methods ([`store`](#method.store),
+    /// [`swap`](#method.swap), [`compare_and_swap`](#method.compare_and_swap),
+    /// [`rcu`](#method.rcu), ...) of this instance. As long as nobody waits on this instance, this
+    /// costs the writers close to nothing.
+    ///
+    /// The `seen` can be specified the same way as the `current` of
+    /// [`compare_and_swap`](#method.compare_and_swap).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// # use arc_swap::ArcSwap;
+    /// let config = Arc::new(ArcSwap::from_pointee(1));
+    /// let seen = config.load();
+    /// let config_cp = Arc::clone(&config);
+    /// std::thread::spawn(move || config_cp.store(Arc::new(2)));
+    /// let new = config.wait_until_changed(&*seen, Duration::from_secs(60)).unwrap();
+    /// assert_eq!(2, **new);
+    /// ```
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    pub fn wait_until_changed<C>(&self, seen: C, timeout: Duration) -> Option<Guard<T, S>>
+    where
+        C: AsRaw<T::Base>,
+    {
+        let seen = thin::addr(seen.as_raw());
+        self.wait_until(|cur| thin::addr(T::as_ptr(cur)) != seen, timeout)
+    }
+
+    /// Blocks the current thread until the value satisfies the `predicate`.
+    ///
+    /// Returns the satisfying value, or `None` if the `timeout` passed before that. The
+    /// `predicate` is checked right away and then after changes (possibly also at other times).
+    ///
+    /// See [`wait_until_changed`](#method.wait_until_changed) for details about the wake ups.
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    pub fn wait_until<F>(&self, mut predicate: F, timeout: Duration) -> Option<Guard<T, S>>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        wait::wait(
+            self.addr(),
+            || {
+                let cur = self.load();
+                if predicate(&cur) {
+                    Some(cur)
+                } else {
+                    None
+                }
+            },
+            timeout,
+        )
     }
 
     /// Read-Copy-Update of the pointer inside.
@@ -595,23 +1076,102 @@ impl<T: RefCnt, S: Strategy<T>> ArcSwapAny<T, S> {
     /// inside to share the unchanged values). Something like
     /// [`rpds`](https://crates.io/crates/rpds) or [`im`](https://crates.io/crates/im) might do
     /// what you need.
-    pub fn rcu<R, F>(&self, mut f: F) -> T
+    pub fn rcu<R, F>(&self, f: F) -> T
     where
         F: FnMut(&T) -> R,
         R: Into<T>,
         S: CaS<T>,
     {
-        let mut cur = self.load();
-        loop {
-            let new = f(&cur).into();
-            let prev = self.compare_and_swap(&*cur, new);
-            let swapped = ptr_eq(&*cur, &*prev);
-            if swapped {
-                return Guard::into_inner(prev);
-            } else {
-                cur = prev;
-            }
-        }
+        retry::rcu(self, f)
+    }
+
+    /// [`rcu`](#method.rcu) with a limited number of retries.
+    ///
+    /// This works like [`rcu`](#method.rcu), but gives up once the `budget` is exhausted (at least
+    /// one attempt is always made). Between the attempts, the `backoff` policy is used to wait a
+    /// bit, to lower the contention, and the value is reloaded.
+    ///
+    /// On success, the previous value and the number of attempts are returned. On exhaustion, the
+    /// last observed value is returned inside the error.
+    ///
+    /// See the [`retry`] module for an example.
+    pub fn rcu_bounded<R, F, B>(
+        &self,
+        budget: retry::Budget,
+        backoff: B,
+        f: F,
+    ) -> Result<(T, usize), retry::Exhausted<T, S>>
+    where
+        F: FnMut(&T) -> R,
+        R: Into<T>,
+        B: retry::Backoff,
+        S: CaS<T>,
+    {
+        retry::rcu_bounded(self, budget, backoff, f)
+    }
+
+    /// Fallible [`rcu`](#method.rcu) with a side result.
+    ///
+    /// The closure is called with the current value. It returns either a `Result` or an `Option`
+    /// (see [`TryRcu`][crate::retry::TryRcu]) and can:
+    ///
+    /// * Return `Ok` (or `Some`) with the new value and some payload. The new value is stored
+    ///   (with retries, like with [`rcu`](#method.rcu)) and the previous value and the payload from
+    ///   the successful attempt are returned. Like with [`rcu`](#method.rcu), the new value is
+    ///   handed over to the storage without cloning it. If the caller needs it too, a clone of it
+    ///   can be put into the payload.
+    /// * Return `Err` (or `None`) to abort the update. The error (or `None`) is returned right
+    ///   away and the storage is left untouched (this is as cheap as a [`load`](#method.load)).
+    ///
+    /// As with [`rcu`](#method.rcu), the closure may be called multiple times.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use arc_swap::ArcSwap;
+    /// let cnt = ArcSwap::from_pointee(0);
+    /// let increment = |limit| {
+    ///     cnt.try_rcu(|cur| {
+    ///         if **cur < limit {
+    ///             Ok((**cur + 1, "incremented"))
+    ///         } else {
+    ///             Err("limit reached")
+    ///         }
+    ///     })
+    /// };
+    ///
+    /// let (old, msg) = increment(1).unwrap();
+    /// assert_eq!((0, "incremented"), (*old, msg));
+    /// assert_eq!("limit reached", increment(1).unwrap_err());
+    /// assert_eq!(1, **cnt.load());
+    /// ```
+    ///
+    /// Something like `fetch_update`, with an `Option`:
+    ///
+    /// ```rust
+    /// # use std::sync::Arc;
+    /// # use arc_swap::ArcSwapOption;
+    /// let shared = ArcSwapOption::<usize>::empty();
+    /// let fill = || shared.try_rcu(|cur| match cur {
+    ///     None => {
+    ///         let new = Arc::new(42);
+    ///         // Getting the new value out too
+    ///         Some((Some(Arc::clone(&new)), new))
+    ///     }
+    ///     Some(_) => None,
+    /// });
+    /// let (old, new) = fill().unwrap();
+    /// assert!(old.is_none());
+    /// assert_eq!(42, *new);
+    /// assert!(fill().is_none());
+    /// ```
+    pub fn try_rcu<O, F>(&self, f: F) -> O::Output
+    where
+        F: FnMut(&T) -> O,
+        O: retry::TryRcu<T>,
+        S: CaS<T>,
+    {
+        retry::try_rcu(self, f)
     }
 
     /// Provides an access to an up to date projection of the carried data.
@@ -673,6 +1233,18 @@ impl<T: RefCnt, S: Strategy<T>> ArcSwapAny<T, S> {
     }
 }
 
+impl<T: RefCnt, S: CaS<T>> retry::Exchange<T, S> for ArcSwapAny<T, S> {
+    #[inline]
+    fn load(&self) -> Guard<T, S> {
+        ArcSwapAny::load(self)
+    }
+
+    #[inline]
+    fn compare_and_swap(&self, current: &T, new: T) -> Guard<T, S> {
+        ArcSwapAny::compare_and_swap(self, current, new)
+    }
+}
+
 /// An atomic storage for `Arc`.
 ///
 /// This is a type alias only. Most of its methods are described on
@@ -766,6 +1338,8 @@ impl<T> ArcSwapOption<T> {
     /// GLOBAL_DATA.store(Some(Arc::new(42)));
     /// assert_eq!(42, **GLOBAL_DATA.load().as_ref().unwrap());
     /// ```
+    // Loom's atomics can't be created in const context.
+    #[cfg(not(loom))]
     pub const fn const_empty() -> Self {
         Self {
             ptr: AtomicPtr::new(ptr::null_mut()),
@@ -777,14 +1351,12 @@ impl<T> ArcSwapOption<T> {
     }
 }
 
-/// An atomic storage that doesn't share the internal generation locks with others.
+/// An atomic storage that doesn't share the internal debt slots with others.
 ///
-/// This makes it bigger and it also might suffer contention (on the HW level) if used from many
-/// threads at once. On the other hand, it can't block writes in other instances.
+/// This makes it bigger. On the other hand, it is not influenced by other instances and writes to
+/// it don't need to check readers of the others.
 ///
 /// See the [`IndependentStrategy`] for further details.
-// Being phased out. Will deprecate once we verify in production that the new strategy works fine.
-#[doc(hidden)]
 pub type IndependentArcSwap<T> = ArcSwapAny<Arc<T>, IndependentStrategy>;
 
 /// Arc swap for the [Weak] pointer.
@@ -799,7 +1371,7 @@ pub type IndependentArcSwap<T> = ArcSwapAny<Arc<T>, IndependentStrategy>;
 ///
 /// [Weak]: std::sync::Weak
 #[cfg(feature = "weak")]
-pub type ArcSwapWeak<T> = ArcSwapAny<std::sync::Weak<T>>;
+pub type ArcSwapWeak<T> = ArcSwapAny<alloc::sync::Weak<T>>;
 
 macro_rules! t {
     ($name: ident, $strategy: ty) => {
@@ -1084,6 +1656,28 @@ macro_rules! t {
                 assert_eq!("42", &format!("{}", shared.load()));
             }
 
+            /// The mapped guards hold onto the original value, even after it is replaced and even if
+            /// they no longer fit into the fast slots.
+            #[test]
+            fn guard_map() {
+                let shared = As::<(usize, String)>::from_pointee((1, "one".to_owned()));
+                let mapped = (0..20)
+                    .map(|_| Guard::map(shared.load(), |v| &v.1))
+                    .collect::<Vec<_>>();
+                let num = Guard::try_map(shared.load(), |v| Some(&v.0)).unwrap();
+                assert!(Guard::try_map(shared.load(), |_| None::<&usize>).is_none());
+                shared.store(Arc::new((2, "two".to_owned())));
+                for m in &mapped {
+                    assert_eq!("one", &**m);
+                }
+                let chars = MappedGuard::map(mapped.into_iter().next().unwrap(), |s| s.as_str());
+                assert_eq!("one", &*chars);
+                assert_eq!("1", format!("{}", num));
+                let whole = MappedGuard::into_guard(num);
+                assert_eq!(1, whole.0);
+                assert_eq!(2, shared.load().0);
+            }
+
             // The following "tests" are not run, only compiled. They check that things that should be
             // Send/Sync actually are.
             fn _check_stuff_is_send_sync() {
@@ -1093,12 +1687,17 @@ macro_rules! t {
                 let lease = shared.load();
                 let lease_ref = &lease;
                 let lease = shared.load();
+                let mapped = Guard::map(shared.load(), |v| v);
+                let mapped_ref = &mapped;
+                let mapped = Guard::map(shared.load(), |v| v);
                 thread::scope(|s| {
                     s.spawn(move |_| {
                         let _ = lease;
+                        let _ = mapped_ref;
                         let _ = lease_ref;
                         let _ = shared_ref;
                         let _ = moved;
+                        let _ = mapped;
                     });
                 })
                 .unwrap();
@@ -1139,7 +1738,7 @@ macro_rules! t {
                 let orig = shared.compare_and_swap(ptr::null(), Some(Arc::clone(&a)));
                 assert!(orig.is_none());
                 assert_eq!(2, Arc::strong_count(&a));
-                let orig = Guard::into_inner(shared.compare_and_swap(&None::<Arc<_>>, None));
+                let orig = Guard::into_inner(shared.compare_and_swap(&None::<Arc<usize>>, None));
                 assert_eq!(3, Arc::strong_count(&a));
                 assert!(ptr_eq(&a, &orig));
             }
@@ -1163,6 +1762,162 @@ macro_rules! t {
                 assert_eq!(THREADS * ITERATIONS, **shared.load());
             }
 
+            #[test]
+            /// Multiple try_rcus interacting, some of them aborting.
+            fn try_rcu() {
+                const ITERATIONS: usize = 50;
+                const THREADS: usize = 10;
+                const LIMIT: usize = THREADS * ITERATIONS / 2;
+                let shared = As::<usize>::from(Arc::new(0));
+                let updated = AtomicUsize::new(0);
+                thread::scope(|scope| {
+                    for _ in 0..THREADS {
+                        scope.spawn(|_| {
+                            for _ in 0..ITERATIONS {
+                                let result = shared.try_rcu(|old| {
+                                    if **old < LIMIT {
+                                        Ok((**old + 1, **old))
+                                    } else {
+                                        Err(())
+                                    }
+                                });
+                                if let Ok((old, payload)) = result {
+                                    assert_eq!(*old, payload);
+                                    updated.fetch_add(1, Ordering::Relaxed);
+                                }
+                            }
+                        });
+                    }
+                })
+                .unwrap();
+                assert_eq!(LIMIT, **shared.load());
+                assert_eq!(LIMIT, updated.load(Ordering::Relaxed));
+            }
+
+            #[test]
+            /// Aborting try_rcu leaves everything in place.
+            fn try_rcu_abort() {
+                let orig = Arc::new(0);
+                let shared = As::<usize>::from(Arc::clone(&orig));
+                let result = shared.try_rcu(|_| Err::<(Arc<usize>, ()), _>(42));
+                assert_eq!(42, result.unwrap_err());
+                assert!(ptr_eq(&orig, &*shared.load()));
+                // One in orig, one in shared
+                assert_eq!(2, Arc::strong_count(&orig));
+            }
+
+            #[test]
+            /// The Option form of try_rcu.
+            fn try_rcu_option() {
+                let shared = As::<usize>::from(Arc::new(0));
+                let bump = || {
+                    shared.try_rcu(|old| {
+                        if **old < 1 {
+                            Some((**old + 1, ()))
+                        } else {
+                            None
+                        }
+                    })
+                };
+                let (old, ()) = bump().unwrap();
+                assert_eq!(0, *old);
+                assert!(bump().is_none());
+                assert_eq!(1, **shared.load());
+            }
+
+            #[cfg(any(feature = "std", not(feature = "no-std")))]
+            #[test]
+            /// Waiting for a change done in another thread.
+            fn wait_until_changed() {
+                let shared = As::<usize>::from(Arc::new(0));
+                let seen = shared.load();
+                // Nothing changes, times out
+                assert!(shared
+                    .wait_until_changed(&*seen, Duration::from_millis(10))
+                    .is_none());
+                // Storing the same pointer is not a change
+                shared.store(Arc::clone(&seen));
+                assert!(shared
+                    .wait_until_changed(&*seen, Duration::from_millis(0))
+                    .is_none());
+                thread::scope(|scope| {
+                    scope.spawn(|_| shared.store(Arc::new(1)));
+                    let new = shared
+                        .wait_until_changed(&*seen, Duration::from_secs(600))
+                        .unwrap();
+                    assert_eq!(1, **new);
+                })
+                .unwrap();
+            }
+
+            #[cfg(any(feature = "std", not(feature = "no-std")))]
+            #[test]
+            /// Waiting for a value satisfying a predicate, written by rcu.
+            fn wait_until() {
+                const ITERATIONS: usize = 50;
+                const THREADS: usize = 4;
+                let shared = As::<usize>::from(Arc::new(0));
+                thread::scope(|scope| {
+                    for _ in 0..THREADS {
+                        scope.spawn(|_| {
+                            for _ in 0..ITERATIONS {
+                                shared.rcu(|old| **old + 1);
+                            }
+                        });
+                    }
+                    let waiters = (0..THREADS)
+                        .map(|_| {
+                            scope.spawn(|_| {
+                                **shared
+                                    .wait_until(
+                                        |cur| **cur == THREADS * ITERATIONS,
+                                        Duration::from_secs(600),
+                                    )
+                                    .unwrap()
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    for waiter in waiters {
+                        assert_eq!(THREADS * ITERATIONS, waiter.join().unwrap());
+                    }
+                })
+                .unwrap();
+            }
+
+            #[cfg(any(feature = "std", not(feature = "no-std")))]
+            #[test]
+            /// Writes to other instances don't wake the waiting thread up.
+            fn wait_other_instance() {
+                const ITERATIONS: usize = 100;
+                let waited = As::<usize>::from(Arc::new(0));
+                let other = As::<usize>::from(Arc::new(0));
+                let checks = AtomicUsize::new(0);
+                thread::scope(|scope| {
+                    scope.spawn(|_| {
+                        while checks.load(Ordering::SeqCst) == 0 {
+                            std::thread::yield_now();
+                        }
+                        for i in 0..ITERATIONS {
+                            other.store(Arc::new(i));
+                        }
+                        waited.store(Arc::new(1));
+                    });
+                    let new = waited
+                        .wait_until(
+                            |cur| {
+                                checks.fetch_add(1, Ordering::SeqCst);
+                                **cur == 1
+                            },
+                            Duration::from_secs(600),
+                        )
+                        .unwrap();
+                    assert_eq!(1, **new);
+                })
+                .unwrap();
+                // Allow for some spurious wake ups, but not one for each write.
+                assert!(checks.load(Ordering::SeqCst) < ITERATIONS / 2);
+            }
+
             #[test]
             /// Make sure the reference count and compare_and_swap works as expected.
             fn cas_ref_cnt() {
@@ -1220,11 +1975,190 @@ macro_rules! t {
                 // Only a now
                 assert_eq!(1, Arc::strong_count(&a));
             }
+
+            #[test]
+            /// The compare_exchange tells the result and hands the rejected value back.
+            fn compare_exchange() {
+                let orig = Arc::new(0);
+                let shared = As::<usize>::from(Arc::clone(&orig));
+                let other = Arc::new(1);
+                let new = Arc::new(2);
+
+                let (cur, rejected) = shared
+                    .compare_exchange(&other, Arc::clone(&new))
+                    .unwrap_err();
+                assert!(ptr_eq(&orig, &*cur));
+                assert!(ptr_eq(&new, &rejected));
+                drop(cur);
+                drop(rejected);
+                // One in orig, one in shared
+                assert_eq!(2, Arc::strong_count(&orig));
+                assert_eq!(1, Arc::strong_count(&new));
+
+                let prev = shared.compare_exchange(&orig, Arc::clone(&new)).unwrap();
+                assert!(ptr_eq(&orig, &*prev));
+                drop(prev);
+                assert_eq!(1, Arc::strong_count(&orig));
+                assert_eq!(2, Arc::strong_count(&new));
+                assert_eq!(2, **shared.load());
+
+                let shared = Aso::<usize>::empty();
+                assert!(shared.compare_exchange(&Some(orig), None).is_err());
+                let prev = shared
+                    .compare_exchange(&None::<Arc<usize>>, Some(Arc::clone(&new)))
+                    .unwrap();
+                assert!(prev.is_none());
+                assert_eq!(3, Arc::strong_count(&new));
+            }
+
+            #[test]
+            /// Counting with compare_exchange_weak in a retry loop.
+            fn compare_exchange_weak() {
+                const ITERATIONS: usize = 50;
+                const THREADS: usize = 10;
+                let shared = As::<usize>::from(Arc::new(0));
+                thread::scope(|scope| {
+                    for _ in 0..THREADS {
+                        scope.spawn(|_| {
+                            for _ in 0..ITERATIONS {
+                                let mut cur = shared.load();
+                                let mut new = Arc::new(**cur + 1);
+                                loop {
+                                    match shared.compare_exchange_weak(&*cur, new) {
+                                        Ok(_) => break,
+                                        Err((actual, rejected)) => {
+                                            cur = actual;
+                                            new = rejected;
+                                            *Arc::get_mut(&mut new).unwrap() = **cur + 1;
+                                        }
+                                    }
+                                }
+                            }
+                        });
+                    }
+                })
+                .unwrap();
+                assert_eq!(THREADS * ITERATIONS, **shared.load());
+            }
+
+            /// Storing, loading and swapping unsized values.
+            #[test]
+            fn unsized_load_store() {
+                let a: Arc<str> = Arc::from("hello");
+                let shared = As::<str>::from(Arc::clone(&a));
+                assert_eq!("hello", &**shared.load());
+                assert!(ptr_eq(&a, &shared.load_full()));
+                // One in a, one in the shared
+                assert_eq!(2, Arc::strong_count(&a));
+
+                let slice: Arc<[usize]> = Arc::from(vec![1, 2, 3]);
+                let shared_slice = As::<[usize]>::from(Arc::clone(&slice));
+                assert_eq!(&[1, 2, 3], &**shared_slice.load());
+                let prev = shared_slice.swap(Arc::from(vec![4]));
+                assert!(ptr_eq(&slice, &prev));
+                assert_eq!(&[4], &**shared_slice.load());
+                drop(prev);
+                assert_eq!(1, Arc::strong_count(&slice));
+
+                let guards = (0..ITERATIONS).map(|_| shared.load()).collect::<Vec<_>>();
+                shared.store(Arc::from("world"));
+                assert!(guards.iter().all(|g| ptr_eq(&a, &**g)));
+                assert_eq!("world", &**shared.load());
+                drop(guards);
+                assert_eq!(1, Arc::strong_count(&a));
+
+                let val = shared.into_inner();
+                assert_eq!("world", &*val);
+                assert_eq!(1, Arc::strong_count(&val));
+            }
+
+            /// Compare and swap on unsized values compares the pointee, not the metadata.
+            #[test]
+            fn unsized_cas() {
+                let orig: Arc<dyn Fn() -> usize + Send + Sync> = Arc::new(|| 1);
+                let shared = As::<dyn Fn() -> usize + Send + Sync>::from(Arc::clone(&orig));
+                let other: Arc<dyn Fn() -> usize + Send + Sync> = Arc::new(|| 2);
+
+                // Failure
+                let prev = shared.compare_and_swap(&other, Arc::new(|| 3));
+                assert!(ptr_eq(&orig, &*prev));
+                assert_eq!(1, shared.load()());
+                drop(prev);
+
+                // Success
+                let prev = shared.compare_and_swap(&orig, Arc::clone(&other));
+                assert!(ptr_eq(&orig, &*prev));
+                assert_eq!(2, shared.load()());
+                drop(prev);
+                assert_eq!(1, Arc::strong_count(&orig));
+                assert_eq!(2, Arc::strong_count(&other));
+                drop(shared);
+                assert_eq!(1, Arc::strong_count(&other));
+            }
+
+            /// Unsized values inside an Option, the None is stored without the cell.
+            #[test]
+            fn unsized_option() {
+                let a: Arc<str> = Arc::from("hello");
+                let shared = Aso::<str>::from(Some(Arc::clone(&a)));
+                assert_eq!("hello", &**shared.load().as_ref().unwrap());
+                let prev = shared.swap(None);
+                assert!(ptr_eq(&a, &prev.unwrap()));
+                assert!(shared.load().is_none());
+                let prev = shared.compare_and_swap(ptr::null::<u8>(), Some(Arc::clone(&a)));
+                assert!(prev.is_none());
+                drop(prev);
+                assert_eq!(2, Arc::strong_count(&a));
+                let val = shared.into_inner().unwrap();
+                assert!(ptr_eq(&a, &val));
+                drop(val);
+                assert_eq!(1, Arc::strong_count(&a));
+            }
+
+            #[test]
+            /// Multiple RCUs interacting on an unsized value.
+            fn unsized_rcu() {
+                const ITERATIONS: usize = 50;
+                const THREADS: usize = 10;
+                let shared = As::<[usize]>::from(Arc::from(Vec::new()));
+                thread::scope(|scope| {
+                    for _ in 0..THREADS {
+                        scope.spawn(|_| {
+                            for _ in 0..ITERATIONS {
+                                shared.rcu(|old| {
+                                    let mut new = old.to_vec();
+                                    new.push(new.len());
+                                    Arc::<[usize]>::from(new)
+                                });
+                            }
+                        });
+                    }
+                })
+                .unwrap();
+                let result = shared.load_full();
+                assert_eq!(THREADS * ITERATIONS, result.len());
+                assert!(result.iter().enumerate().all(|(i, v)| i == *v));
+            }
         }
     };
 }
 
 t!(tests_default, DefaultStrategy);
+t!(tests_independent, IndependentStrategy);
+#[cfg(test)]
+mod many_slots {
+    use super::*;
+    use crate::strategy::hybrid::Config;
+
+    #[derive(Default)]
+    struct ManySlots;
+
+    impl Config for ManySlots {
+        const FAST_SLOTS: usize = 20;
+    }
+
+    t!(tests_many_slots, HybridStrategy<ManySlots>);
+}
 #[cfg(all(feature = "internal-test-strategies", test))]
 #[allow(deprecated)]
 mod internal_strategies {
@@ -0,0 +1,332 @@
+//! A single-threaded variant, for [`Rc`].
+//!
+//! The [`ArcSwapAny`][crate::ArcSwapAny] can hold an [`Rc`], but it still pays for the machinery
+//! needed to protect the value from the other threads. The [`LocalArcSwapAny`] can't be shared
+//! between threads (it's not `Sync`), so it can just hold the value in a cell. The loads and stores
+//! are then only a matter of adjusting the reference count.
+//!
+//! This is useful for code that wants the same API in single-threaded contexts (GUI event loops,
+//! single-threaded async runtimes, ...), eg. because it's generic over [`Access`].
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::rc::Rc;
+//!
+//! use arc_swap::local::LocalArcSwap;
+//!
+//! let config = LocalArcSwap::from_pointee(1);
+//! let old = config.load();
+//! config.rcu(|cfg| **cfg + 1);
+//! assert_eq!(1, **old);
+//! assert_eq!(2, **config.load());
+//! config.store(Rc::new(3));
+//! assert_eq!(3, **config.load());
+//! ```
+
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use core::mem;
+use core::ops::Deref;
+
+use crate::access::{Access, Map};
+use crate::as_raw::AsRaw;
+use crate::{thin, RefCnt};
+
+/// A loaded value of [`LocalArcSwapAny`].
+///
+/// It's the counterpart of [`Guard`][crate::Guard], for code that wants to be interchangeable.
+/// Unlike that one, it always holds a full reference.
+pub struct LocalGuard<T: RefCnt>(T);
+
+impl<T: RefCnt> LocalGuard<T> {
+    /// Converts it into the held value.
+    #[inline]
+    pub fn into_inner(guard: Self) -> T {
+        guard.0
+    }
+
+    /// Creates a guard from the value.
+    #[inline]
+    pub fn from_inner(inner: T) -> Self {
+        LocalGuard(inner)
+    }
+}
+
+impl<T: RefCnt> Deref for LocalGuard<T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: RefCnt> From<T> for LocalGuard<T> {
+    fn from(inner: T) -> Self {
+        LocalGuard(inner)
+    }
+}
+
+impl<T: Debug + RefCnt> Debug for LocalGuard<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        self.0.fmt(formatter)
+    }
+}
+
+impl<T: Display + RefCnt> Display for LocalGuard<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        self.0.fmt(formatter)
+    }
+}
+
+/// A single-threaded storage for a reference counted smart pointer.
+///
+/// It has the same API as the [`ArcSwapAny`][crate::ArcSwapAny] (apart from the parts that make
+/// sense only with multiple threads), but is implemented by a plain cell. See the [module
+/// documentation](self).
+///
+/// Replacing the value from within a method (eg. from the closure of [`rcu`][Self::rcu] or from
+/// a destructor of the replaced value) is fine.
+pub struct LocalArcSwapAny<T: RefCnt> {
+    // Invariant: no reference into it survives any method call (and the only code that runs while
+    // we look inside is the cloning of T, which doesn't touch us).
+    value: UnsafeCell<T>,
+}
+
+impl<T: RefCnt> LocalArcSwapAny<T> {
+    /// Constructs a new storage.
+    pub fn new(val: T) -> Self {
+        LocalArcSwapAny {
+            value: UnsafeCell::new(val),
+        }
+    }
+
+    /// Extracts the value inside.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Loads the value.
+    ///
+    /// This is just a cheap increment of the reference count.
+    #[inline]
+    pub fn load(&self) -> LocalGuard<T> {
+        LocalGuard(self.load_full())
+    }
+
+    /// Loads the value, without the guard.
+    ///
+    /// Here it is the same as [`load`][Self::load].
+    #[inline]
+    pub fn load_full(&self) -> T {
+        // Safety: see the invariant of the field
+        unsafe { (*self.value.get()).clone() }
+    }
+
+    /// Replaces the value inside this instance.
+    pub fn store(&self, val: T) {
+        drop(self.swap(val));
+    }
+
+    /// Exchanges the value inside this instance.
+    pub fn swap(&self, new: T) -> T {
+        // Safety: see the invariant of the field (the old value is dropped by the caller, after
+        // we are done)
+        unsafe { mem::replace(&mut *self.value.get(), new) }
+    }
+
+    /// Swaps the stored value if it is the same as `current`.
+    ///
+    /// Returns the previously stored value (whether it was replaced or not). See
+    /// [`ArcSwapAny::compare_and_swap`][crate::ArcSwapAny::compare_and_swap].
+    pub fn compare_and_swap<C>(&self, current: C, new: T) -> LocalGuard<T>
+    where
+        C: AsRaw<T::Base>,
+    {
+        let cur = self.load_full();
+        if thin::addr(T::as_ptr(&cur)) == thin::addr(current.as_raw()) {
+            LocalGuard(self.swap(new))
+        } else {
+            LocalGuard(cur)
+        }
+    }
+
+    /// Read-Copy-Update of the current value.
+    ///
+    /// The closure is called with the current value and its result is stored. Nobody else can
+    /// change the value in the meantime, unless the closure itself does (in which case it is
+    /// called again with the new value, as in [`ArcSwapAny::rcu`][crate::ArcSwapAny::rcu]).
+    ///
+    /// Returns the replaced value.
+    pub fn rcu<R, F>(&self, mut f: F) -> T
+    where
+        F: FnMut(&T) -> R,
+        R: Into<T>,
+    {
+        let mut cur = self.load();
+        loop {
+            let new = f(&cur).into();
+            let prev = self.compare_and_swap(&*cur, new);
+            if thin::addr(T::as_ptr(&cur)) == thin::addr(T::as_ptr(&prev)) {
+                return LocalGuard::into_inner(prev);
+            }
+            cur = prev;
+        }
+    }
+
+    /// Provides an access to an up to date projection of the carried data.
+    ///
+    /// See [`ArcSwapAny::map`][crate::ArcSwapAny::map].
+    pub fn map<I, R, F>(&self, f: F) -> Map<&Self, I, F>
+    where
+        F: Fn(&I) -> &R + Clone,
+        Self: Access<I>,
+    {
+        Map::new(self, f)
+    }
+}
+
+impl<T: RefCnt> From<T> for LocalArcSwapAny<T> {
+    fn from(val: T) -> Self {
+        Self::new(val)
+    }
+}
+
+impl<T: RefCnt + Default> Default for LocalArcSwapAny<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: RefCnt + Debug> Debug for LocalArcSwapAny<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_tuple("LocalArcSwapAny")
+            .field(&self.load())
+            .finish()
+    }
+}
+
+impl<T: RefCnt + Display> Display for LocalArcSwapAny<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        self.load().fmt(formatter)
+    }
+}
+
+impl<T: RefCnt> Access<T> for LocalArcSwapAny<T> {
+    type Guard = LocalGuard<T>;
+
+    fn load(&self) -> Self::Guard {
+        self.load()
+    }
+}
+
+impl<T> Access<T> for LocalArcSwapAny<Rc<T>> {
+    type Guard = Rc<T>;
+
+    fn load(&self) -> Self::Guard {
+        self.load_full()
+    }
+}
+
+impl<T> Access<T> for LocalArcSwapAny<Arc<T>> {
+    type Guard = Arc<T>;
+
+    fn load(&self) -> Self::Guard {
+        self.load_full()
+    }
+}
+
+/// A single-threaded storage for an [`Rc`].
+pub type LocalArcSwap<T> = LocalArcSwapAny<Rc<T>>;
+
+impl<T> LocalArcSwapAny<Rc<T>> {
+    /// A convenience constructor directly from the pointed-to value.
+    pub fn from_pointee(val: T) -> Self {
+        Self::new(Rc::new(val))
+    }
+}
+
+/// A single-threaded storage for an `Option<Rc>`.
+pub type LocalArcSwapOption<T> = LocalArcSwapAny<Option<Rc<T>>>;
+
+impl<T> LocalArcSwapAny<Option<Rc<T>>> {
+    /// A convenience constructor directly from a pointed-to value.
+    pub fn from_pointee<V: Into<Option<T>>>(val: V) -> Self {
+        Self::new(val.into().map(Rc::new))
+    }
+
+    /// A convenience constructor for an empty value.
+    pub fn empty() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn load_store() {
+        let shared = LocalArcSwap::from_pointee(1);
+        let first = shared.load();
+        shared.store(Rc::new(2));
+        assert_eq!(1, **first);
+        assert_eq!(2, **shared.load());
+        assert_eq!(2, *shared.swap(Rc::new(3)));
+        assert_eq!(1, Rc::strong_count(&LocalGuard::into_inner(first)));
+        assert_eq!(3, *shared.into_inner());
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let shared = LocalArcSwapOption::empty();
+        let a = Rc::new(1);
+        let prev = shared.compare_and_swap(&a, Some(Rc::new(2)));
+        assert!(prev.is_none());
+        assert!(shared.load().is_none());
+        let prev = shared.compare_and_swap(&None::<Rc<usize>>, Some(Rc::clone(&a)));
+        assert!(prev.is_none());
+        let prev = shared.compare_and_swap(shared.load(), None);
+        assert!(Rc::ptr_eq(&a, prev.as_ref().unwrap()));
+        assert!(shared.load().is_none());
+    }
+
+    /// The closure of rcu replaces the value itself, so it gets called again.
+    #[test]
+    fn rcu_reentrant() {
+        let shared = LocalArcSwap::from_pointee(0);
+        let calls = Cell::new(0);
+        let old = shared.rcu(|cur| {
+            calls.set(calls.get() + 1);
+            if calls.get() == 1 {
+                shared.store(Rc::new(10));
+            }
+            **cur + 1
+        });
+        assert_eq!(2, calls.get());
+        assert_eq!(10, *old);
+        assert_eq!(11, **shared.load());
+    }
+
+    #[test]
+    fn access() {
+        struct Cfg {
+            value: usize,
+        }
+
+        let shared = LocalArcSwap::from_pointee(Cfg { value: 1 });
+        let mapped = shared.map(|cfg: &Cfg| &cfg.value);
+        assert_eq!(1, *mapped.load());
+        shared.store(Rc::new(Cfg { value: 2 }));
+        assert_eq!(2, *mapped.load());
+
+        let shared = Rc::new(shared);
+        let dynamic: Box<dyn crate::access::DynAccess<Cfg>> = Box::new(Rc::clone(&shared));
+        assert_eq!(2, dynamic.load().value);
+    }
+}
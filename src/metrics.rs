@@ -0,0 +1,284 @@
+//! Counting what the internal algorithms do.
+//!
+//! Available with the `metrics` feature. Without it, nothing is counted and there's no cost.
+//!
+//! The counters can show if the usage pattern of the application is good for the performance. Most
+//! of the loads should take the fast path, needing the fallback only occasionally (usually because
+//! the thread holds too many [`Guard`][crate::Guard]s at once, see the
+//! [limitations][crate::docs::limitations#too-many-guards]).
+//!
+//! Each thread counts into its own counters, so there's no contention. The [`thread`] function
+//! reads the ones of the calling thread, the [`global`] one sums them across all the threads
+//! (including the ones that already terminated). The counters only grow, to measure some period
+//! of time, take two readings and [subtract][Metrics::since] them.
+//!
+//! Only the strategies based on debts (the default one) count the loads and debts.
+//!
+//! The counters of a thread are created on its first counted event. That allocates and takes a
+//! global lock, which breaks the guarantee of [loading without
+//! allocations](crate::nodes#loading-without-allocations). Calling [`thread`] or
+//! [`thread::register`][crate::thread::register] when the thread starts creates them in advance.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use arc_swap::ArcSwap;
+//! use arc_swap::metrics;
+//!
+//! let shared = ArcSwap::from_pointee(0);
+//! let before = metrics::thread();
+//! for _ in 0..10 {
+//!     let _guard = shared.load();
+//! }
+//! let loads = metrics::thread().since(&before);
+//! assert_eq!(10, loads.fast_path());
+//! assert_eq!(0, loads.fallback());
+//! ```
+
+use std::ptr;
+use std::sync::atomic::Ordering::*;
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
+#[cfg(unix)]
+use std::sync::TryLockError;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A reading of the counters.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metrics {
+    fast_path: usize,
+    fallback: usize,
+    helped: usize,
+    debts_paid: usize,
+    rcu_retries: usize,
+}
+
+impl Metrics {
+    /// Number of loads that succeeded on the fast path.
+    pub fn fast_path(&self) -> usize {
+        self.fast_path
+    }
+
+    /// Number of loads that had to use the fallback.
+    pub fn fallback(&self) -> usize {
+        self.fallback
+    }
+
+    /// Number of times a writer helped a reader in the fallback to finish its load.
+    pub fn helped(&self) -> usize {
+        self.helped
+    }
+
+    /// Number of debts paid by writers.
+    ///
+    /// Each is a guard that was alive while a writer replaced the value it pointed to.
+    pub fn debts_paid(&self) -> usize {
+        self.debts_paid
+    }
+
+    /// Number of times an [`rcu`][crate::ArcSwapAny::rcu] (or its variants) had to retry because
+    /// of a concurrent write.
+    pub fn rcu_retries(&self) -> usize {
+        self.rcu_retries
+    }
+
+    /// The difference between this and an earlier reading.
+    pub fn since(&self, earlier: &Metrics) -> Metrics {
+        Metrics {
+            fast_path: self.fast_path.wrapping_sub(earlier.fast_path),
+            fallback: self.fallback.wrapping_sub(earlier.fallback),
+            helped: self.helped.wrapping_sub(earlier.helped),
+            debts_paid: self.debts_paid.wrapping_sub(earlier.debts_paid),
+            rcu_retries: self.rcu_retries.wrapping_sub(earlier.rcu_retries),
+        }
+    }
+
+    fn add(&mut self, other: &Metrics) {
+        self.fast_path = self.fast_path.wrapping_add(other.fast_path);
+        self.fallback = self.fallback.wrapping_add(other.fallback);
+        self.helped = self.helped.wrapping_add(other.helped);
+        self.debts_paid = self.debts_paid.wrapping_add(other.debts_paid);
+        self.rcu_retries = self.rcu_retries.wrapping_add(other.rcu_retries);
+    }
+}
+
+/// The counters of one thread.
+///
+/// Only the owning thread writes them, others may read.
+#[derive(Default)]
+pub(crate) struct Counters {
+    pub(crate) fast_path: AtomicUsize,
+    pub(crate) fallback: AtomicUsize,
+    pub(crate) helped: AtomicUsize,
+    pub(crate) debts_paid: AtomicUsize,
+    pub(crate) rcu_retries: AtomicUsize,
+}
+
+impl Counters {
+    fn read(&self) -> Metrics {
+        Metrics {
+            fast_path: self.fast_path.load(Relaxed),
+            fallback: self.fallback.load(Relaxed),
+            helped: self.helped.load(Relaxed),
+            debts_paid: self.debts_paid.load(Relaxed),
+            rcu_retries: self.rcu_retries.load(Relaxed),
+        }
+    }
+}
+
+/// All the counters.
+#[derive(Default)]
+struct Registry {
+    live: Vec<Arc<Counters>>,
+    /// Sum of the threads that already terminated.
+    terminated: Metrics,
+}
+
+static REGISTRY: AtomicPtr<Mutex<Registry>> = AtomicPtr::new(ptr::null_mut());
+
+fn registry() -> MutexGuard<'static, Registry> {
+    let current = REGISTRY.load(Acquire);
+    let registry = if let Some(registry) = unsafe { current.as_ref() } {
+        registry
+    } else {
+        let new = Box::into_raw(Box::new(Mutex::new(Registry::default())));
+        match REGISTRY.compare_exchange(ptr::null_mut(), new, AcqRel, Acquire) {
+            // It's leaked on purpose, it lives for the rest of the program.
+            Ok(_) => unsafe { &*new },
+            Err(other) => {
+                // Someone was faster, use theirs.
+                drop(unsafe { Box::from_raw(new) });
+                unsafe { &*other }
+            }
+        }
+    };
+    // Nothing panics while holding the lock.
+    registry.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Replaces the registry if a thread that no longer exists (after a `fork`) holds its lock.
+///
+/// The counters of the current thread then no longer show in the [`global`] ones.
+#[cfg(unix)]
+pub(crate) fn after_fork_child() {
+    if let Some(registry) = unsafe { REGISTRY.load(Acquire).as_ref() } {
+        if let Err(TryLockError::WouldBlock) = registry.try_lock() {
+            // Leaked, someone still holds it.
+            REGISTRY.store(ptr::null_mut(), SeqCst);
+        }
+    }
+}
+
+/// The registration of the thread's counters.
+struct Local(Arc<Counters>);
+
+impl Local {
+    fn new() -> Self {
+        let counters = Arc::new(Counters::default());
+        registry().live.push(Arc::clone(&counters));
+        Local(counters)
+    }
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        let mut registry = registry();
+        let counters = &self.0;
+        registry.live.retain(|live| !Arc::ptr_eq(live, counters));
+        let last = counters.read();
+        registry.terminated.add(&last);
+    }
+}
+
+thread_local! {
+    static LOCAL: Local = Local::new();
+}
+
+/// Creates the counters of the current thread, if they don't exist yet.
+pub(crate) fn register() {
+    let _ = LOCAL.try_with(|_| ());
+}
+
+/// Increments the selected counter of the current thread.
+#[inline]
+pub(crate) fn record<F: FnOnce(&Counters) -> &AtomicUsize>(counter: F) {
+    // During the thread shutdown the counters may be already gone. Then we just don't count.
+    let _ = LOCAL.try_with(|local| {
+        let counter = counter(&local.0);
+        // Only we write it, so no need for the more expensive fetch_add.
+        counter.store(counter.load(Relaxed).wrapping_add(1), Relaxed);
+    });
+}
+
+/// Reads the counters of the current thread.
+pub fn thread() -> Metrics {
+    LOCAL.try_with(|local| local.0.read()).unwrap_or_default()
+}
+
+/// Reads the sum of the counters of all the threads.
+///
+/// The threads are not stopped while reading, so the result is not an exact snapshot.
+pub fn global() -> Metrics {
+    let registry = registry();
+    let mut result = registry.terminated;
+    for live in &registry.live {
+        result.add(&live.read());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::ArcSwap;
+
+    #[test]
+    fn loads_and_writes() {
+        let shared = ArcSwap::from_pointee(0);
+        let before = thread();
+        let guards = (0..100).map(|_| shared.load()).collect::<Vec<_>>();
+        let after = thread().since(&before);
+        assert_eq!(100, after.fast_path() + after.fallback());
+        assert!(after.fallback() > 0, "Not enough fast slots for all");
+
+        // Only the guards from the fast path hold a debt, the rest own a reference.
+        let before = thread();
+        shared.store(Arc::new(1));
+        assert_eq!(after.fast_path(), thread().since(&before).debts_paid());
+        drop(guards);
+    }
+
+    #[test]
+    fn rcu_retry() {
+        let shared = ArcSwap::from_pointee(0);
+        let before = thread();
+        let mut first = true;
+        shared.rcu(|v| {
+            if first {
+                first = false;
+                // Someone got in the way
+                shared.store(Arc::new(10));
+            }
+            **v + 1
+        });
+        assert_eq!(1, thread().since(&before).rcu_retries());
+        assert_eq!(11, **shared.load());
+    }
+
+    #[test]
+    fn terminated_threads() {
+        let before = global();
+        thread::spawn(|| {
+            let shared = ArcSwap::from_pointee(0);
+            shared.rcu(|v| **v + 1);
+            drop(shared.load());
+        })
+        .join()
+        .unwrap();
+        let after = global().since(&before);
+        // Other tests run in parallel, so there may be more.
+        assert!(after.fast_path() >= 1);
+    }
+}
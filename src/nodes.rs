@@ -0,0 +1,399 @@
+//! Controlling the per-thread bookkeeping.
+//!
+//! Each thread that loads from an [`ArcSwapAny`][crate::ArcSwapAny] (with the default strategy)
+//! gets a node with slots to record its borrowed [`Guard`][crate::Guard]s into. The nodes form a
+//! list that every writer walks, so the cost of a write grows with the number of nodes.
+//!
+//! When a thread terminates, its node is left for the next thread to reuse. If a writer comes
+//! across more such unused nodes than a few, it unlinks them from the list and a later writer frees
+//! them (the ones still referenced by some outstanding guard only once the guard goes away and more
+//! nodes become unused). The terminating thread itself doesn't do any of that. Therefore the cost
+//! of writes tracks the current number of threads, not the historical peak. The [`reclaim`]
+//! function frees all the unused nodes right away. The [`thread`][crate::thread] module allows a thread to claim
+//! and give up its node at explicit times.
+//!
+//! # Limiting the number of nodes
+//!
+//! The number of nodes can be bounded with [`set_limit`]. A thread that finds all the nodes taken
+//! then works without one:
+//!
+//! * Its loads register in a shared counter and increment the reference count. This is slower and the
+//!   returned guards hold a full reference, so they behave like a loaded `Arc`.
+//! * The writes from anywhere have to wait for these loads to finish (but only if there are any
+//!   right now).
+//! * Its writes share one set of slots for the bookkeeping, therefore they serialize with each
+//!   other.
+//!
+//! The counter, the waiting and the slots are shared by all the instances using the shared list,
+//! not kept for each instance separately. While any thread is loading without a node, every write
+//! in the process (to any instance with the default strategy) waits for that load and these
+//! writes also serialize with each other on a single lock. So does every write from a thread
+//! without a node. This is fine for an occasional overflow, but if many threads run without a
+//! node for a long time, the writes become a global bottleneck and the limit should be raised.
+//!
+//! The thread tries to get a node again on each load, so it gets back to the fast path once some
+//! node is freed.
+//!
+//! This applies only to the shared list. Instances with a [private
+//! domain][crate::IndependentStrategy] create as many nodes as they need.
+//!
+//! # Loading without allocations
+//!
+//! Usually, the first load on a thread may need to allocate a node (and so may a load during the
+//! thread's shutdown, when the thread local variable is already gone). That is a problem for
+//! real-time threads (audio processing and similar), which must not allocate at all.
+//!
+//! The [`preallocate`] function creates the nodes in advance, one for each thread expected to
+//! load. These are kept even when the threads terminate and the writers free the unused nodes
+//! (unless [`reclaim`] is called). After
+//! [`set_allocating(false)`][set_allocating], no load (or store) creates another node. A thread
+//! that finds all the nodes taken then uses the fallback described above, which doesn't allocate
+//! either. Therefore, with the default strategy, loading never allocates:
+//!
+//! * The thread with a node puts its debts into the node's slots.
+//! * If it holds too many guards at once, it uses the node's helping slot.
+//! * A thread without a node increments the reference count.
+//!
+//! Some things are not covered by this:
+//!
+//! * [Configurations][crate::strategy::hybrid::Config] with more than 8 fast slots allocate the
+//!   additional ones on demand. When that is turned off, they use only the slots they already
+//!   have.
+//! * With the `metrics` feature, each thread allocates its counters (and registers them under a
+//!   global lock) on its first load. Calling [`thread::register`][crate::thread::register] or
+//!   [`metrics::thread`](../metrics/fn.thread.html) when the thread starts does that in advance.
+//! * Accessing the thread local variable for the first time may allocate on some platforms (not
+//!   with glibc). A [`NodeProvider`] avoids that, or [`thread::register`][crate::thread::register]
+//!   can be called when the thread starts.
+//! * The [private domains][crate::IndependentStrategy] always allocate their nodes as needed.
+//!
+//! # Where the nodes are kept
+//!
+//! By default (unless in the `no-std` mode), each thread keeps its node in a thread local variable.
+//! Without `std`, there are no thread locals, so each operation needs to find a node in the list,
+//! which is slow. A [`NodeProvider`] can supply the storage instead ‒ per thread, per CPU or
+//! anything else that guarantees the node is not used by two threads at the same time. It takes
+//! precedence over the thread locals, if any.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use arc_swap::nodes;
+//!
+//! nodes::set_limit(Some(64));
+//! assert_eq!(Some(64), nodes::limit());
+//! # nodes::set_limit(None);
+//! ```
+//!
+//! ```rust
+//! use arc_swap::{nodes, ArcSwap};
+//!
+//! let shared = ArcSwap::from_pointee(42);
+//! // One for each of the audio threads
+//! arc_swap::preallocate(4);
+//! nodes::set_allocating(false);
+//! assert!(nodes::count() >= 4);
+//!
+//! std::thread::spawn(move || {
+//!     // No allocations in here
+//!     assert_eq!(42, **shared.load());
+//! })
+//! .join()
+//! .unwrap();
+//! # nodes::set_allocating(true);
+//! ```
+
+use alloc::boxed::Box;
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+use core::ptr;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering::*;
+
+use crate::debt::{self, Domain, LocalNode};
+
+/// Sets the maximum number of nodes.
+///
+/// `None` means unlimited, which is the default. If there are more nodes already, they are not
+/// removed, but new ones are not created until the number drops below the limit.
+///
+/// See the [module documentation](self).
+pub fn set_limit(limit: Option<usize>) {
+    debt::set_limit(limit.unwrap_or(usize::MAX));
+}
+
+/// The current maximum number of nodes, as set by [`set_limit`].
+pub fn limit() -> Option<usize> {
+    match debt::limit() {
+        usize::MAX => None,
+        limit => Some(limit),
+    }
+}
+
+/// The number of nodes currently in existence, both used and unused.
+pub fn count() -> usize {
+    Domain::global().count()
+}
+
+/// Frees all the unused nodes.
+///
+/// Nodes that are not owned by any thread but are still referenced by some
+/// [`Guard`][crate::Guard] are kept. Unlike the writers freeing the nodes on their own, this waits
+/// for everyone walking the list at the moment to move past the freed nodes.
+///
+/// Returns the number of nodes freed. If another thread is freeing nodes at the same time, this
+/// returns `0` right away.
+pub fn reclaim() -> usize {
+    Domain::global().reclaim(0)
+}
+
+/// Creates nodes in advance, so there are at least `n` of them.
+///
+/// The nodes are created unused, to be claimed by the threads later on. The writers don't free
+/// them, only an explicit [`reclaim`] does. If the [limit][set_limit] is lower, only
+/// as many nodes as it allows are created.
+///
+/// Returns the number of the newly created nodes.
+///
+/// See the [module documentation](self#loading-without-allocations).
+pub fn preallocate(n: usize) -> usize {
+    debt::preallocate(n)
+}
+
+/// Allows or forbids creating new nodes on demand.
+///
+/// When forbidden, the threads use only the nodes that already exist (eg. created by
+/// [`preallocate`]) and the ones that don't get any use the slower fallback. The default is to
+/// allow it.
+///
+/// See the [module documentation](self#loading-without-allocations).
+pub fn set_allocating(allocating: bool) {
+    debt::set_allocating(allocating);
+}
+
+/// Are new nodes created on demand, as set by [`set_allocating`]?
+pub fn allocating() -> bool {
+    debt::allocating()
+}
+
+/// A place for a node, to be kept by a [`NodeProvider`].
+///
+/// It starts empty and gets a node on the first use. Dropping it gives the node up, for another
+/// thread to use.
+pub struct ThreadNode(pub(crate) LocalNode);
+
+// The NodeProvider guarantees nobody uses it from two threads at once.
+unsafe impl Send for ThreadNode {}
+unsafe impl Sync for ThreadNode {}
+
+impl ThreadNode {
+    /// Creates an empty one.
+    ///
+    /// This is a `const fn`, so it can be used to initialize eg. a per-CPU array in a `static`.
+    pub const fn new() -> Self {
+        ThreadNode(LocalNode::cached())
+    }
+}
+
+impl Default for ThreadNode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A node owned explicitly, to load through.
+///
+/// The usual loads find the node of the current thread in a thread local variable (or through
+/// the [`NodeProvider`]). Loading with a handle skips that lookup and uses the handle's node
+/// instead. This is useful in tight loops or on runtimes where the thread locals are slow or don't
+/// match the unit of execution (green threads, fibers, ...).
+///
+/// The handle claims a node when created. It can be moved to another thread, but not shared. Only
+/// the strategies using the global list of nodes (like the [`DefaultStrategy`]) make use of the
+/// handle, the others ignore it and load as usual.
+///
+/// Dropping the handle gives the node up, for someone else to use (the [`Guard`]s loaded through
+/// it may outlive it).
+///
+/// # Examples
+///
+/// ```rust
+/// use arc_swap::ArcSwap;
+/// use arc_swap::access::{Access, WithHandle};
+/// use arc_swap::nodes::ReaderHandle;
+///
+/// let shared = ArcSwap::from_pointee(42);
+/// let handle = ReaderHandle::new();
+/// for _ in 0..10 {
+///     assert_eq!(42, **shared.load_with(&handle));
+/// }
+///
+/// let access = WithHandle::new(&shared, &handle);
+/// assert_eq!(42, *Access::<usize>::load(&access));
+/// ```
+///
+/// [`DefaultStrategy`]: crate::DefaultStrategy
+/// [`Guard`]: crate::Guard
+pub struct ReaderHandle(pub(crate) LocalNode);
+
+impl ReaderHandle {
+    /// Creates a handle, claiming a node for it.
+    ///
+    /// If the [limit][set_limit] doesn't allow another node, the handle tries again on each load
+    /// (and the loads use the same slower fallback as threads without a node).
+    pub fn new() -> Self {
+        let local = LocalNode::cached();
+        local.run(|_| ());
+        ReaderHandle(local)
+    }
+
+    /// Does the handle currently own a node?
+    pub fn has_node(&self) -> bool {
+        self.0.is_claimed()
+    }
+}
+
+impl Default for ReaderHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for ReaderHandle {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_struct("ReaderHandle")
+            .field("has_node", &self.has_node())
+            .finish()
+    }
+}
+
+/// Provides the [`ThreadNode`] of the current thread (or CPU, etc).
+///
+/// Registered with [`set_provider`].
+///
+/// # Safety
+///
+/// The provider must never pass the same [`ThreadNode`] to two closures running at the same time
+/// on different threads (eg. a per-CPU node must not be used after the thread migrated to another
+/// CPU while in the closure, so the preemption needs to be disabled or similar).
+///
+/// The `with_node` may be called recursively from within the closure, in which case it must
+/// provide the same node (or none at all).
+///
+/// # Examples
+///
+/// ```rust
+/// use arc_swap::ArcSwap;
+/// use arc_swap::nodes::{self, NodeProvider, ThreadNode};
+///
+/// struct PerThread;
+///
+/// thread_local! {
+///     static NODE: ThreadNode = const { ThreadNode::new() };
+/// }
+///
+/// unsafe impl NodeProvider for PerThread {
+///     fn with_node(&self, f: &mut dyn FnMut(&ThreadNode)) {
+///         // During the thread shutdown, there's no node and a slower fallback is used.
+///         let _ = NODE.try_with(|node| f(node));
+///     }
+/// }
+///
+/// nodes::set_provider(&PerThread);
+/// let shared = ArcSwap::from_pointee(42);
+/// assert_eq!(42, **shared.load());
+/// ```
+pub unsafe trait NodeProvider: Sync {
+    /// Calls `f` with the node of the current thread, if there's one.
+    ///
+    /// If there isn't one (eg. in an interrupt handler), the provider doesn't call `f` at all and
+    /// a slower fallback is used.
+    fn with_node(&self, f: &mut dyn FnMut(&ThreadNode));
+}
+
+static PROVIDER: AtomicPtr<&'static dyn NodeProvider> = AtomicPtr::new(ptr::null_mut());
+
+/// Sets the [`NodeProvider`] to use from now on.
+///
+/// It replaces any previously set one (the nodes kept by the old one stay valid).
+pub fn set_provider(provider: &'static dyn NodeProvider) {
+    let new = Box::into_raw(Box::new(provider));
+    // The old one is leaked on purpose, someone may still be using it.
+    PROVIDER.swap(new, AcqRel);
+}
+
+pub(crate) fn provider() -> Option<&'static dyn NodeProvider> {
+    unsafe { PROVIDER.load(Acquire).as_ref() }.copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::access::{Access, Map, WithHandle};
+    use crate::{ArcSwap, Cache};
+
+    /// Keeps the nodes in a thread local, like the default, but counts the uses.
+    struct Counting(AtomicUsize);
+
+    thread_local! {
+        static NODE: ThreadNode = const { ThreadNode::new() };
+    }
+
+    unsafe impl NodeProvider for Counting {
+        fn with_node(&self, f: &mut dyn FnMut(&ThreadNode)) {
+            self.0.fetch_add(1, Relaxed);
+            let _ = NODE.try_with(|node| f(node));
+        }
+    }
+
+    static COUNTING: Counting = Counting(AtomicUsize::new(0));
+
+    /// The loads through a handle, including after it moved to another thread.
+    #[test]
+    fn reader_handle() {
+        let shared = Arc::new(ArcSwap::from_pointee(1));
+        let handle = ReaderHandle::new();
+        assert!(handle.has_node());
+        let guard = shared.load_with(&handle);
+        shared.store(Arc::new(2));
+        assert_eq!(1, **guard);
+
+        let (handle, guard) = {
+            let shared = Arc::clone(&shared);
+            std::thread::spawn(move || {
+                let guard = shared.load_with(&handle);
+                (handle, guard)
+            })
+            .join()
+            .unwrap()
+        };
+        assert_eq!(2, **guard);
+
+        let mut cache = Cache::new(&*shared);
+        let access = Map::new(WithHandle::new(&shared, &handle), |v: &usize| v);
+        shared.store(Arc::new(3));
+        assert_eq!(3, **cache.load_with(&handle));
+        assert_eq!(3, *access.load());
+
+        // The guards may outlive the handle.
+        drop(handle);
+        shared.store(Arc::new(4));
+        assert_eq!(2, **guard);
+        assert_eq!(4, *shared.load_full());
+    }
+
+    #[test]
+    fn provider_used() {
+        set_provider(&COUNTING);
+        let before = COUNTING.0.load(Relaxed);
+        let shared = ArcSwap::from_pointee(1);
+        let guard = shared.load();
+        shared.store(Arc::new(2));
+        assert_eq!(1, **guard);
+        assert_eq!(2, **shared.load());
+        assert!(COUNTING.0.load(Relaxed) > before);
+    }
+}
@@ -0,0 +1,538 @@
+//! Getting notified about changes.
+//!
+//! The [`ArcSwapAny`] itself has no way to tell anyone the value changed, one has to keep
+//! [`load`][ArcSwapAny::load]ing it (or use a [`Cache`][crate::Cache]). The [`Notifying`] wrapper
+//! adds that on top, for the price of slightly more expensive writes (each write takes a lock
+//! after storing the value, to count the change and pick the wakers). The reads stay the same.
+//!
+//! The waiting is done through the [`std::task::Waker`] only, so it works with any executor.
+//!
+//! The [observers][crate::observe] of the changes can be registered with it as well.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use std::sync::Arc;
+//! use arc_swap::notify::Notifying;
+//!
+//! async fn watch_config(config: Arc<Notifying<Arc<String>>>) {
+//!     loop {
+//!         let new = config.changed().await;
+//!         println!("New config: {}", new);
+//!     }
+//! }
+//! # let _ = watch_config;
+//! ```
+
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use crate::observe::{self, ObserverId};
+use crate::retry::{self, TryRcu};
+use crate::strategy::{CaS, DefaultStrategy, Strategy};
+use crate::{ArcSwapAny, AsRaw, Guard, RefCnt};
+
+/// The registered wakers.
+#[derive(Default)]
+struct Waiters {
+    /// Bumped after each change.
+    ///
+    /// The writes take the lock only after the value is stored, so whoever reads the version also
+    /// sees the values of all the counted changes. A change may already be visible before it is
+    /// counted, so a [`Changed`] may resolve for a change that happened just before it was
+    /// created (it then yields the current value anyway). But a change not yet visible to a load
+    /// done after [`Notifying::changed`] is always counted after it.
+    version: usize,
+    next_id: usize,
+    wakers: Vec<(usize, Waker)>,
+}
+
+/// An [`ArcSwapAny`] notifying about its changes.
+///
+/// This provides the same reading methods as [`ArcSwapAny`] and the writing ones (which then
+/// wake up whoever waits for a change). One can wait for a change with [`changed`].
+///
+/// Every write that puts a value in counts as a change, even if it is the same pointer as before.
+/// A failed compare and swap is not a change.
+///
+/// Each change is first reported to the [observers][Notifying::observe] and then the waiting
+/// futures are woken up.
+///
+/// [`changed`]: Notifying::changed
+pub struct Notifying<T: RefCnt, S: Strategy<T> = DefaultStrategy> {
+    inner: ArcSwapAny<T, S>,
+    waiters: Mutex<Waiters>,
+}
+
+impl<T: RefCnt, S: Default + Strategy<T>> From<T> for Notifying<T, S> {
+    fn from(val: T) -> Self {
+        Self::with_strategy(val, S::default())
+    }
+}
+
+impl<T: RefCnt + Default, S: Default + Strategy<T>> Default for Notifying<T, S> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: RefCnt + Debug, S: Strategy<T>> Debug for Notifying<T, S> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_tuple("Notifying")
+            .field(&self.load())
+            .finish()
+    }
+}
+
+impl<T: RefCnt, S: Strategy<T>> Notifying<T, S> {
+    /// Constructs a new storage.
+    pub fn new(val: T) -> Self
+    where
+        S: Default,
+    {
+        Self::from(val)
+    }
+
+    /// Constructs a new storage while customizing the protection strategy.
+    pub fn with_strategy(val: T, strategy: S) -> Self {
+        Self {
+            inner: ArcSwapAny::with_strategy(val, strategy),
+            waiters: Mutex::default(),
+        }
+    }
+
+    /// Extracts the value inside.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    /// See [`ArcSwapAny::load`].
+    #[inline]
+    pub fn load(&self) -> Guard<T, S> {
+        self.inner.load()
+    }
+
+    /// See [`ArcSwapAny::load_full`].
+    pub fn load_full(&self) -> T {
+        self.inner.load_full()
+    }
+
+    /// Replaces the value inside and notifies the waiters.
+    pub fn store(&self, val: T) {
+        drop(self.swap(val));
+    }
+
+    /// Exchanges the value inside and notifies the waiters.
+    pub fn swap(&self, new: T) -> T {
+        let old = self.inner.swap(new);
+        Self::wake(self.bump());
+        old
+    }
+
+    /// See [`ArcSwapAny::compare_and_swap`]; notifies the waiters if the swap happened.
+    pub fn compare_and_swap<C>(&self, current: C, new: T) -> Guard<T, S>
+    where
+        C: AsRaw<T::Base>,
+        S: CaS<T>,
+    {
+        match self.compare_exchange(current, new) {
+            Ok(prev) => prev,
+            Err((cur, _)) => cur,
+        }
+    }
+
+    /// See [`ArcSwapAny::compare_exchange`]; notifies the waiters on success.
+    pub fn compare_exchange<C>(&self, current: C, new: T) -> Result<Guard<T, S>, (Guard<T, S>, T)>
+    where
+        C: AsRaw<T::Base>,
+        S: CaS<T>,
+    {
+        self.exchange(new, |inner, new| inner.compare_exchange(current, new))
+    }
+
+    /// See [`ArcSwapAny::compare_exchange_weak`]; notifies the waiters on success.
+    pub fn compare_exchange_weak<C>(
+        &self,
+        current: C,
+        new: T,
+    ) -> Result<Guard<T, S>, (Guard<T, S>, T)>
+    where
+        C: AsRaw<T::Base>,
+        S: CaS<T>,
+    {
+        self.exchange(new, |inner, new| inner.compare_exchange_weak(current, new))
+    }
+
+    /// See [`ArcSwapAny::rcu`]; notifies the waiters.
+    pub fn rcu<R, F>(&self, f: F) -> T
+    where
+        F: FnMut(&T) -> R,
+        R: Into<T>,
+        S: CaS<T>,
+    {
+        retry::rcu(self, f)
+    }
+
+    /// See [`ArcSwapAny::rcu_bounded`]; notifies the waiters on success.
+    pub fn rcu_bounded<R, F, B>(
+        &self,
+        budget: retry::Budget,
+        backoff: B,
+        f: F,
+    ) -> Result<(T, usize), retry::Exhausted<T, S>>
+    where
+        F: FnMut(&T) -> R,
+        R: Into<T>,
+        B: retry::Backoff,
+        S: CaS<T>,
+    {
+        retry::rcu_bounded(self, budget, backoff, f)
+    }
+
+    /// See [`ArcSwapAny::try_rcu`]; notifies the waiters unless aborted.
+    pub fn try_rcu<O, F>(&self, f: F) -> O::Output
+    where
+        F: FnMut(&T) -> O,
+        O: TryRcu<T>,
+        S: CaS<T>,
+    {
+        retry::try_rcu(self, f)
+    }
+
+    /// Registers an observer.
+    ///
+    /// See [`ArcSwapAny::observe`], the observers of the inner storage are called first, before
+    /// the waiters are woken up.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use std::sync::Arc;
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use arc_swap::notify::Notifying;
+    ///
+    /// let shared: Arc<Notifying<Arc<usize>>> = Arc::new(Notifying::new(Arc::new(0)));
+    /// let sum = Arc::new(AtomicUsize::new(0));
+    /// let sum_cp = Arc::clone(&sum);
+    /// let id = shared.observe(move |old, new| {
+    ///     sum_cp.fetch_add(**old + **new, Ordering::Relaxed);
+    /// });
+    /// shared.store(Arc::new(1));
+    /// shared.store(Arc::new(2));
+    /// assert!(shared.unobserve(id));
+    /// shared.store(Arc::new(3));
+    /// assert_eq!(4, sum.load(Ordering::Relaxed));
+    /// ```
+    pub fn observe<F>(self: &Arc<Self>, callback: F) -> ObserverId
+    where
+        T: 'static,
+        F: Fn(&T, &T) + Send + Sync + 'static,
+    {
+        observe::register(self.inner.addr(), self, callback)
+    }
+
+    /// Unregisters an observer.
+    ///
+    /// See [`ArcSwapAny::unobserve`].
+    pub fn unobserve(&self, id: ObserverId) -> bool {
+        observe::unregister(self.inner.addr(), id)
+    }
+
+    /// Waits for the next change.
+    ///
+    /// The returned future resolves once a change happens after this method was called. It then
+    /// provides the value present at the time of the wake up (which may be newer than the one the
+    /// change stored).
+    ///
+    /// Changes between calls to this method are not reported. If that is a concern, call it before
+    /// looking at the current value:
+    ///
+    /// ```rust
+    /// # async fn f() {
+    /// # use std::sync::Arc;
+    /// # use arc_swap::notify::Notifying;
+    /// let shared: Notifying<Arc<usize>> = Notifying::new(Arc::new(0));
+    /// # shared.store(Arc::new(1));
+    /// let changed = shared.changed();
+    /// if **shared.load() == 0 {
+    ///     // Any change after the load is reported
+    ///     changed.await;
+    /// }
+    /// # }
+    /// # let _ = f;
+    /// ```
+    pub fn changed(&self) -> Changed<'_, T, S> {
+        Changed {
+            notifying: self,
+            seen: self.waiters().version,
+            id: None,
+        }
+    }
+
+    fn waiters(&self) -> MutexGuard<'_, Waiters> {
+        self.waiters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Writes through the `exchange` and reports it if it succeeded.
+    fn exchange<F>(&self, new: T, exchange: F) -> Result<Guard<T, S>, (Guard<T, S>, T)>
+    where
+        F: FnOnce(&ArcSwapAny<T, S>, T) -> Result<Guard<T, S>, (Guard<T, S>, T)>,
+    {
+        let result = exchange(&self.inner, new);
+        if result.is_ok() {
+            Self::wake(self.bump());
+        }
+        result
+    }
+
+    /// Counts a change, to be called after the write.
+    ///
+    /// The lock is held only for the counting, not for the write itself. Returns the wakers to
+    /// wake up, once the lock is released (they may want to register again right away).
+    fn bump(&self) -> Vec<(usize, Waker)> {
+        let mut waiters = self.waiters();
+        waiters.version = waiters.version.wrapping_add(1);
+        std::mem::take(&mut waiters.wakers)
+    }
+
+    fn wake(wakers: Vec<(usize, Waker)>) {
+        for (_, waker) in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl<T: RefCnt, S: CaS<T>> retry::Exchange<T, S> for Notifying<T, S> {
+    fn load(&self) -> Guard<T, S> {
+        Notifying::load(self)
+    }
+
+    fn compare_and_swap(&self, current: &T, new: T) -> Guard<T, S> {
+        Notifying::compare_and_swap(self, current, new)
+    }
+}
+
+/// A future waiting for a change of [`Notifying`].
+///
+/// Created by [`Notifying::changed`].
+pub struct Changed<'a, T: RefCnt, S: Strategy<T> = DefaultStrategy> {
+    notifying: &'a Notifying<T, S>,
+    seen: usize,
+    id: Option<usize>,
+}
+
+impl<T: RefCnt, S: Strategy<T>> Changed<'_, T, S> {
+    fn is_changed(&self) -> bool {
+        self.notifying.waiters().version != self.seen
+    }
+
+    fn unregister(&mut self) {
+        if let Some(id) = self.id.take() {
+            let mut waiters = self.notifying.waiters();
+            waiters.wakers.retain(|(registered, _)| *registered != id);
+        }
+    }
+}
+
+impl<T: RefCnt, S: Strategy<T>> Future for Changed<'_, T, S> {
+    type Output = Guard<T, S>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Guard<T, S>> {
+        // Nothing in here is structurally pinned.
+        let me = self.get_mut();
+        {
+            let mut waiters = me.notifying.waiters();
+            let waiters = &mut *waiters;
+            // The writers bump the version under the same lock, so it can't change between the
+            // check and the registration.
+            if waiters.version != me.seen {
+                if let Some(id) = me.id.take() {
+                    waiters.wakers.retain(|(registered, _)| *registered != id);
+                }
+            } else {
+                // It might have been woken up (and removed) in the meantime, so look for it.
+                let existing = me
+                    .id
+                    .and_then(|id| waiters.wakers.iter_mut().find(|(reg, _)| *reg == id));
+                match existing {
+                    Some((_, waker)) => {
+                        if !waker.will_wake(ctx.waker()) {
+                            *waker = ctx.waker().clone();
+                        }
+                    }
+                    None => {
+                        let id = waiters.next_id;
+                        waiters.next_id = waiters.next_id.wrapping_add(1);
+                        waiters.wakers.push((id, ctx.waker().clone()));
+                        me.id = Some(id);
+                    }
+                }
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(me.notifying.load())
+    }
+}
+
+impl<T: RefCnt, S: Strategy<T>> Drop for Changed<'_, T, S> {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+impl<T: RefCnt, S: Strategy<T>> Debug for Changed<'_, T, S> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_struct("Changed")
+            .field("seen", &self.seen)
+            .field("changed", &self.is_changed())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    use super::*;
+
+    struct ThreadWaker {
+        thread: Thread,
+        woken: AtomicBool,
+    }
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.woken.store(true, Ordering::SeqCst);
+            self.thread.unpark();
+        }
+    }
+
+    /// A minimal executor for a single future.
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let waker = Arc::new(ThreadWaker {
+            thread: thread::current(),
+            woken: AtomicBool::new(false),
+        });
+        let as_waker = Waker::from(Arc::clone(&waker));
+        let mut ctx = Context::from_waker(&as_waker);
+        loop {
+            if let Poll::Ready(result) = fut.as_mut().poll(&mut ctx) {
+                return result;
+            }
+            while !waker.woken.swap(false, Ordering::SeqCst) {
+                thread::park();
+            }
+        }
+    }
+
+    #[test]
+    fn changed_before_poll() {
+        let shared: Notifying<Arc<usize>> = Notifying::new(Arc::new(0));
+        let changed = shared.changed();
+        shared.store(Arc::new(1));
+        assert_eq!(1, **block_on(changed));
+    }
+
+    #[test]
+    fn not_changed() {
+        let shared: Notifying<Arc<usize>> = Notifying::new(Arc::new(0));
+        let other = Arc::new(1);
+        let changed = shared.changed();
+        // Failed CaS is not a change
+        assert!(shared.compare_exchange(&other, Arc::new(2)).is_err());
+        assert!(!changed.is_changed());
+        drop(changed);
+        assert!(shared.waiters.lock().unwrap().wakers.is_empty());
+    }
+
+    /// All the writing methods count as changes (unless they fail).
+    #[test]
+    fn all_writes_notify() {
+        let shared: Arc<Notifying<Arc<usize>>> = Arc::new(Notifying::new(Arc::new(0)));
+        let count = Arc::new(AtomicUsize::new(0));
+        let count_cp = Arc::clone(&count);
+        shared.observe(move |_, _| {
+            count_cp.fetch_add(1, Ordering::Relaxed);
+        });
+        let check = |write: &dyn Fn()| {
+            let changed = shared.changed();
+            let before = count.load(Ordering::Relaxed);
+            write();
+            assert!(changed.is_changed());
+            assert_eq!(before + 1, count.load(Ordering::Relaxed));
+        };
+        check(&|| {
+            let cur = shared.load();
+            while shared.compare_exchange_weak(&*cur, Arc::new(1)).is_err() {}
+        });
+        check(&|| {
+            shared.try_rcu(|cur| Ok::<_, ()>((**cur + 1, ()))).unwrap();
+        });
+        check(&|| {
+            shared.try_rcu(|cur| Some((**cur + 1, ()))).unwrap();
+        });
+        check(&|| {
+            shared
+                .rcu_bounded(retry::Budget::new(), retry::Spin, |cur| **cur + 1)
+                .unwrap();
+        });
+        assert_eq!(4, **shared.load());
+
+        // Aborted updates are not changes
+        let changed = shared.changed();
+        assert!(shared.try_rcu(|_| None::<(Arc<usize>, ())>).is_none());
+        assert!(!changed.is_changed());
+        assert_eq!(4, count.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn wake_other_thread() {
+        let shared: Notifying<Arc<usize>> = Notifying::new(Arc::new(0));
+        crossbeam_utils::thread::scope(|scope| {
+            let waiters = (0..4)
+                .map(|_| {
+                    // Make sure the changed is created before the change
+                    let changed = shared.changed();
+                    scope.spawn(move |_| **block_on(changed))
+                })
+                .collect::<Vec<_>>();
+            shared.rcu(|old| **old + 1);
+            for waiter in waiters {
+                assert_eq!(1, waiter.join().unwrap());
+            }
+        })
+        .unwrap();
+        assert!(shared.waiters.lock().unwrap().wakers.is_empty());
+    }
+
+    #[test]
+    fn repeated() {
+        const ITERATIONS: usize = 100;
+        let shared: Arc<Notifying<Arc<usize>>> = Arc::new(Notifying::new(Arc::new(0)));
+        let shared_cp = Arc::clone(&shared);
+        let writer = thread::spawn(move || {
+            for i in 1..=ITERATIONS {
+                shared_cp.store(Arc::new(i));
+            }
+        });
+        let mut last = 0;
+        while last < ITERATIONS {
+            let changed = shared.changed();
+            if **shared.load() == last {
+                let new = **block_on(changed);
+                assert!(new > last);
+            }
+            last = **shared.load();
+        }
+        writer.join().unwrap();
+    }
+}
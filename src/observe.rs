@@ -0,0 +1,463 @@
+//! Observers of the changes.
+//!
+//! The observers are callbacks registered with [`ArcSwapAny::observe`] (or
+//! [`Notifying::observe`][crate::notify::Notifying::observe]), getting the old and the new value
+//! after each successful write.
+//!
+//! They are kept in a global registry, split into buckets by the address of the storage they
+//! observe, much like the waiting threads of [`wait_until_changed`][ArcSwapAny::wait_until_changed].
+//! Each bucket counts the observed instances in it. A writer looks at the counter of its bucket
+//! only and takes the lock of the bucket only if some instance there is observed. As long as
+//! nothing was ever observed, the cost of a write is a single relaxed load of the pointer to the
+//! registry.
+//!
+//! The registry needs the instance to stay at the same address while it is observed. Therefore
+//! the instance needs to live inside an [`Arc`] and each observer keeps a [`Weak`] to it (which
+//! keeps the memory, not the instance alive). The observers are removed when the instance is
+//! dropped.
+//!
+//! # Contract
+//!
+//! * The callback runs synchronously in the thread doing the write, after the new value is
+//!   visible to the readers and before the write method returns. If it panics, the panic
+//!   propagates to the writer (the value is already stored).
+//! * The writes to an observed instance are serialized (they take a lock of the instance). The
+//!   callbacks run one write at a time, in the order of the writes, so each one gets as the old
+//!   value the new value of the previous one. Within a single write, the observers are called in
+//!   the order of their registration.
+//! * The writes that started before an observer was registered (or while it is being registered)
+//!   may or may not be reported to it, without the above ordering guarantee. The same goes for
+//!   unregistering.
+//! * A failed compare and swap is not reported.
+//!
+//! Registering and unregistering is safe at any time, from any thread, including from inside of
+//! an observer. The callback must not write into the same instance (it would deadlock) and must
+//! not wait for another thread doing so.
+//!
+//! [`Weak`]: std::sync::Weak
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::Ordering::*;
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use crate::{ArcSwapAny, RefCnt};
+
+const BUCKET_CNT: usize = 16;
+
+/// An identifier of a registered observer.
+///
+/// Returned by [`ArcSwapAny::observe`], can be used to [`unobserve`][ArcSwapAny::unobserve] it.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ObserverId(usize);
+
+/// The callback with the types erased, getting pointers to the old and new value.
+type Report = dyn Fn(*const (), *const ()) + Send + Sync;
+
+/// Keeps the memory of the observed instance allocated.
+///
+/// The address therefore can't be reused by another instance (possibly of a different type)
+/// while the observer is registered.
+struct Anchor {
+    ptr: *const (),
+    release: unsafe fn(*const ()),
+}
+
+impl Anchor {
+    fn new<A>(anchor: &Arc<A>) -> Self {
+        unsafe fn release<A>(ptr: *const ()) {
+            drop(Weak::from_raw(ptr as *const A));
+        }
+        Anchor {
+            ptr: Weak::into_raw(Arc::downgrade(anchor)) as *const (),
+            release: release::<A>,
+        }
+    }
+}
+
+// The weak pointer is never upgraded, only dropped. That touches only the reference counts, not
+// the value, so it's fine from any thread.
+unsafe impl Send for Anchor {}
+unsafe impl Sync for Anchor {}
+
+impl Drop for Anchor {
+    fn drop(&mut self) {
+        unsafe { (self.release)(self.ptr) };
+    }
+}
+
+struct Observer {
+    id: usize,
+    report: Arc<Report>,
+    _anchor: Anchor,
+}
+
+/// The observers of one instance.
+struct Observed {
+    addr: usize,
+    /// Serializes the writes (and the reports) of the instance.
+    writing: Arc<Mutex<()>>,
+    observers: Vec<Observer>,
+}
+
+#[derive(Default)]
+#[repr(align(64))]
+struct Bucket {
+    /// Number of observed instances in the list.
+    observed: AtomicUsize,
+    list: Mutex<Vec<Observed>>,
+}
+
+impl Bucket {
+    fn lock(&self) -> MutexGuard<'_, Vec<Observed>> {
+        // We don't panic while holding it, but be robust anyway.
+        self.list.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    buckets: [Bucket; BUCKET_CNT],
+    next_id: AtomicUsize,
+}
+
+impl Registry {
+    fn bucket(&self, addr: usize) -> &Bucket {
+        &self.buckets[(addr / mem::size_of::<usize>()) % BUCKET_CNT]
+    }
+}
+
+/// The lazily-created global registry.
+static REGISTRY: AtomicPtr<Registry> = AtomicPtr::new(ptr::null_mut());
+
+fn registry() -> &'static Registry {
+    let current = REGISTRY.load(Acquire);
+    if let Some(registry) = unsafe { current.as_ref() } {
+        return registry;
+    }
+    let new = Box::into_raw(Box::<Registry>::default());
+    match REGISTRY.compare_exchange(ptr::null_mut(), new, AcqRel, Acquire) {
+        // It's leaked on purpose, it lives for the rest of the program.
+        Ok(_) => unsafe { &*new },
+        Err(other) => {
+            // Someone was faster, use theirs.
+            drop(unsafe { Box::from_raw(new) });
+            unsafe { &*other }
+        }
+    }
+}
+
+/// Registers the `callback` for the instance at `addr`, living inside the `anchor`.
+pub(crate) fn register<T, A, F>(addr: usize, anchor: &Arc<A>, callback: F) -> ObserverId
+where
+    T: RefCnt + 'static,
+    F: Fn(&T, &T) + Send + Sync + 'static,
+{
+    let report = move |old: *const (), new: *const ()| {
+        // The registry passes only pointers to values of the instance at the address, which is
+        // of the type T (the anchor makes sure no other one can appear there).
+        let (old, new) = unsafe { (&*(old as *const T), &*(new as *const T)) };
+        callback(old, new);
+    };
+    let registry = registry();
+    let id = registry.next_id.fetch_add(1, Relaxed);
+    let observer = Observer {
+        id,
+        report: Arc::new(report),
+        _anchor: Anchor::new(anchor),
+    };
+    let bucket = registry.bucket(addr);
+    let mut list = bucket.lock();
+    match list.iter_mut().find(|observed| observed.addr == addr) {
+        Some(observed) => observed.observers.push(observer),
+        None => {
+            list.push(Observed {
+                addr,
+                writing: Arc::default(),
+                observers: vec![observer],
+            });
+            bucket.observed.fetch_add(1, Relaxed);
+        }
+    }
+    ObserverId(id)
+}
+
+/// Removes the observer from the instance at `addr`, returns if it was there.
+pub(crate) fn unregister(addr: usize, id: ObserverId) -> bool {
+    let registry = REGISTRY.load(Acquire);
+    let registry = match unsafe { registry.as_ref() } {
+        Some(registry) => registry,
+        None => return false,
+    };
+    let bucket = registry.bucket(addr);
+    let removed = {
+        let mut list = bucket.lock();
+        let pos = match list.iter().position(|observed| observed.addr == addr) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        let observers = &mut list[pos].observers;
+        let removed = match observers.iter().position(|observer| observer.id == id.0) {
+            Some(idx) => observers.remove(idx),
+            None => return false,
+        };
+        if observers.is_empty() {
+            list.swap_remove(pos);
+            bucket.observed.fetch_sub(1, Relaxed);
+        }
+        removed
+    };
+    // Outside of the lock, the callback may drop arbitrary things.
+    drop(removed);
+    true
+}
+
+/// Removes all the observers of the instance at `addr`, when it goes away.
+#[inline]
+pub(crate) fn forget(addr: usize) {
+    // The registration happened before the instance is dropped, so even relaxed load sees it.
+    if REGISTRY.load(Relaxed).is_null() {
+        return;
+    }
+    forget_slow(addr);
+}
+
+#[inline(never)]
+fn forget_slow(addr: usize) {
+    let registry = unsafe { &*REGISTRY.load(Acquire) };
+    let bucket = registry.bucket(addr);
+    if bucket.observed.load(Relaxed) == 0 {
+        return;
+    }
+    let removed = {
+        let mut list = bucket.lock();
+        match list.iter().position(|observed| observed.addr == addr) {
+            Some(pos) => {
+                bucket.observed.fetch_sub(1, Relaxed);
+                list.swap_remove(pos)
+            }
+            None => return,
+        }
+    };
+    drop(removed);
+}
+
+/// The observers of an instance, found by a writer.
+pub(crate) struct Writing {
+    writing: Arc<Mutex<()>>,
+    reports: Vec<Arc<Report>>,
+}
+
+/// Looks up the observers of the instance at `addr`, if there are any.
+#[inline]
+pub(crate) fn observed(addr: usize) -> Option<Writing> {
+    // Nothing was ever observed -> the registry doesn't even exist and we are done. Relaxed is
+    // fine, an observer registered before the write starts is visible even through that one.
+    if REGISTRY.load(Relaxed).is_null() {
+        return None;
+    }
+    observed_slow(addr)
+}
+
+#[inline(never)]
+fn observed_slow(addr: usize) -> Option<Writing> {
+    // Acquire to see the initialized buckets.
+    let registry = unsafe { &*REGISTRY.load(Acquire) };
+    let bucket = registry.bucket(addr);
+    if bucket.observed.load(Relaxed) == 0 {
+        return None;
+    }
+    let list = bucket.lock();
+    let observed = list.iter().find(|observed| observed.addr == addr)?;
+    Some(Writing {
+        writing: Arc::clone(&observed.writing),
+        reports: observed
+            .observers
+            .iter()
+            .map(|observer| Arc::clone(&observer.report))
+            .collect(),
+    })
+}
+
+impl Writing {
+    /// Does the `write` of `new` and reports it if the `old` finds the previous value in the
+    /// result.
+    pub(crate) fn write<T, R, W>(self, new: T, write: W, old: fn(&R) -> Option<&T>) -> R
+    where
+        T: RefCnt,
+        W: FnOnce(T) -> R,
+    {
+        // The callbacks may panic, but there's nothing the lock protects.
+        let _writing = self.writing.lock().unwrap_or_else(|e| e.into_inner());
+        let new_copy = T::clone(&new);
+        let result = write(new);
+        if let Some(old) = old(&result) {
+            let old = old as *const T as *const ();
+            let new = &new_copy as *const T as *const ();
+            for report in &self.reports {
+                report(old, new);
+            }
+        }
+        result
+    }
+}
+
+impl<T: RefCnt + 'static, S: crate::strategy::Strategy<T>> ArcSwapAny<T, S> {
+    /// Registers an observer.
+    ///
+    /// The `callback` is called after each change with the old and the new value. See the
+    /// [module documentation][crate::observe#contract] for which thread runs it and in what order.
+    ///
+    /// The instance needs to be inside an [`Arc`], so it stays at the same place while observed.
+    /// The observers are dropped together with the instance (or when they are
+    /// [`unobserve`][ArcSwapAny::unobserve]d). If the instance is moved out of the [`Arc`] (eg.
+    /// with [`Arc::try_unwrap`]), the observers stay registered for the original place, not
+    /// observing anything, until unobserved.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use std::sync::Arc;
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use arc_swap::ArcSwap;
+    ///
+    /// let shared = Arc::new(ArcSwap::from_pointee(0));
+    /// let sum = Arc::new(AtomicUsize::new(0));
+    /// let sum_cp = Arc::clone(&sum);
+    /// let id = shared.observe(move |old, new| {
+    ///     sum_cp.fetch_add(**old + **new, Ordering::Relaxed);
+    /// });
+    /// shared.store(Arc::new(1));
+    /// shared.rcu(|old| **old + 1);
+    /// assert!(shared.unobserve(id));
+    /// shared.store(Arc::new(3));
+    /// assert_eq!(4, sum.load(Ordering::Relaxed));
+    /// ```
+    pub fn observe<F>(self: &Arc<Self>, callback: F) -> ObserverId
+    where
+        F: Fn(&T, &T) + Send + Sync + 'static,
+    {
+        register(self.addr(), self, callback)
+    }
+
+    /// Unregisters an observer.
+    ///
+    /// Returns if it was registered with this instance. Note that the observer may still be
+    /// running (or about to be called) in other threads for writes that happened concurrently
+    /// with this call.
+    pub fn unobserve(&self, id: ObserverId) -> bool {
+        unregister(self.addr(), id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{ArcSwap, ArcSwapOption};
+
+    #[test]
+    fn registration_order() {
+        let shared = Arc::new(ArcSwap::from_pointee(0));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let ids = (0..3)
+            .map(|i| {
+                let log = Arc::clone(&log);
+                shared.observe(move |old: &Arc<usize>, new: &Arc<usize>| {
+                    log.lock().unwrap().push((i, **old, **new));
+                })
+            })
+            .collect::<Vec<_>>();
+        shared.store(Arc::new(1));
+        assert!(shared.unobserve(ids[1]));
+        assert!(!shared.unobserve(ids[1]));
+        shared.rcu(|old| **old + 1);
+        // Failed CaS is not reported
+        let other = Arc::new(0);
+        assert!(shared.compare_exchange(&other, Arc::new(10)).is_err());
+        let cur = shared.load_full();
+        shared.compare_exchange(&cur, Arc::new(3)).unwrap();
+        assert_eq!(
+            vec![
+                (0, 0, 1),
+                (1, 0, 1),
+                (2, 0, 1),
+                (0, 1, 2),
+                (2, 1, 2),
+                (0, 2, 3),
+                (2, 2, 3)
+            ],
+            *log.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn other_instance() {
+        let shared = Arc::new(ArcSwapOption::<usize>::empty());
+        let other = Arc::new(ArcSwapOption::<usize>::empty());
+        let id = shared.observe(|_, new| assert!(new.is_some()));
+        assert!(!other.unobserve(id));
+        other.store(None);
+        shared.store(Some(Arc::new(1)));
+        assert!(shared.unobserve(id));
+    }
+
+    /// The observers are dropped with the instance and release its memory.
+    #[test]
+    fn dropped_with_instance() {
+        let shared = Arc::new(ArcSwap::from_pointee(0));
+        let token = Arc::new(());
+        let token_cp = Arc::clone(&token);
+        shared.observe(move |_, _| {
+            let _ = &token_cp;
+        });
+        let weak = Arc::downgrade(&shared);
+        assert_eq!(2, Arc::weak_count(&shared));
+        drop(shared);
+        assert_eq!(1, Arc::strong_count(&token));
+        assert!(weak.upgrade().is_none());
+    }
+
+    /// Concurrent writers get reported one at a time, in the order of the writes.
+    #[test]
+    fn concurrent_order() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 100;
+        let shared = Arc::new(ArcSwap::from_pointee(0));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let log_cp = Arc::clone(&log);
+        shared.observe(move |old: &Arc<usize>, new: &Arc<usize>| {
+            log_cp.lock().unwrap().push((**old, **new));
+        });
+        crossbeam_utils::thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|_| {
+                    for i in 0..ITERATIONS {
+                        if i % 2 == 0 {
+                            shared.rcu(|old| **old + 1);
+                        } else {
+                            shared.store(Arc::new(i * 1000));
+                        }
+                    }
+                });
+            }
+            scope.spawn(|_| {
+                for _ in 0..ITERATIONS {
+                    let id = shared.observe(|_, _| ());
+                    assert!(shared.unobserve(id));
+                }
+            });
+        })
+        .unwrap();
+        let log = log.lock().unwrap();
+        // Each write continues from where the previous one ended.
+        let mut last = 0;
+        for &(old, new) in log.iter() {
+            assert_eq!(last, old);
+            last = new;
+        }
+        assert_eq!(**shared.load(), last);
+    }
+}
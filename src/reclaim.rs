@@ -0,0 +1,495 @@
+//! Dropping the replaced values somewhere else.
+//!
+//! Whoever releases the last reference to a value also runs its destructor. With an
+//! [`ArcSwapAny`][crate::ArcSwapAny], that is either the writer replacing the value or a reader
+//! whose [`Guard`][crate::Guard] happened to be the last one. If the value is large (a big routing
+//! table) or its destructor does something expensive (closing files, flushing buffers), this can
+//! slow down a latency-sensitive thread.
+//!
+//! The [`Reclaimer`] moves the destruction elsewhere ‒ to a background thread or to a
+//! user-supplied executor. The writer can pass the reclaimer to
+//! [`store_reclaimed`][crate::ArcSwapAny::store_reclaimed], which hands the replaced value over.
+//! Values can also be handed to it directly with [`Reclaimer::retire`], or wrapped in
+//! [`Deferred`], which retires its content whenever the last reference goes away (even if a
+//! reader holds it).
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use std::collections::HashMap;
+//! # use std::sync::Arc;
+//! use arc_swap::ArcSwap;
+//! use arc_swap::reclaim::{Deferred, Reclaimer};
+//!
+//! let reclaimer = Reclaimer::background();
+//! let table = |size| Deferred::new((0..size).map(|i| (i, i)).collect(), &reclaimer);
+//! let routes: ArcSwap<Deferred<HashMap<u32, u32>>> = ArcSwap::from_pointee(table(1000));
+//!
+//! // The old table is dropped in the background thread, no matter if this or some reader holds
+//! // the last reference.
+//! routes.store(Arc::new(table(2000)));
+//!
+//! // Wait for the background thread to catch up (eg. in tests).
+//! reclaimer.flush();
+//! ```
+
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::Ordering::*;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, Thread};
+
+/// A piece of work for the executor ‒ dropping one retired value.
+pub type Job = Box<dyn FnOnce() + Send>;
+
+type Executor = dyn Fn(Job) + Send + Sync;
+
+struct Inner {
+    executor: Box<Executor>,
+    /// Number of retired, but not yet dropped values.
+    pending: AtomicUsize,
+    /// Taken only by the [`flush`][Reclaimer::flush] and when the last pending value is dropped.
+    idle: Mutex<()>,
+    done: Condvar,
+}
+
+impl Inner {
+    fn idle(&self) -> MutexGuard<'_, ()> {
+        // Destructors of the values don't run under the lock, so it can't get poisoned by them.
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Marks the value as dropped, even if its destructor panics.
+struct Finished(Arc<Inner>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        if self.0.pending.fetch_sub(1, SeqCst) == 1 {
+            // Under the lock, so a flush can't miss it between checking and going to sleep.
+            let _idle = self.0.idle();
+            self.0.done.notify_all();
+        }
+    }
+}
+
+/// One job in the queue of the background thread.
+struct Node {
+    job: Job,
+    next: *mut Node,
+}
+
+/// The queue of the background thread.
+///
+/// The writers push the jobs onto a lock-free stack. The thread takes the whole stack at once
+/// and runs the jobs in the order they came. It sleeps while the stack is empty, the writer
+/// making it non-empty wakes it up.
+struct Queue {
+    head: AtomicPtr<Node>,
+    /// All the handles are gone, the thread shall terminate once the queue is empty.
+    closed: AtomicBool,
+    thread: Thread,
+}
+
+impl Queue {
+    fn push(&self, job: Job) {
+        let node = Box::into_raw(Box::new(Node {
+            job,
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Relaxed);
+        loop {
+            unsafe { (*node).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, node, Release, Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+        if head.is_null() {
+            self.thread.unpark();
+        }
+    }
+
+    /// Takes all the queued jobs, the oldest first.
+    fn take(&self) -> Vec<Job> {
+        let mut node = self.head.swap(ptr::null_mut(), Acquire);
+        let mut jobs = Vec::new();
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next;
+            jobs.push(boxed.job);
+        }
+        jobs.reverse();
+        jobs
+    }
+
+    fn run(&self) {
+        loop {
+            let jobs = self.take();
+            if jobs.is_empty() {
+                // Acquire to see all the jobs pushed before closing.
+                if self.closed.load(Acquire) {
+                    if self.head.load(Acquire).is_null() {
+                        return;
+                    }
+                } else {
+                    thread::park();
+                }
+                continue;
+            }
+            for job in jobs {
+                // The panic is reported by the panic hook as usual, but the thread keeps going
+                // for the other values. The job itself marks the value as dropped even on panic.
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+        }
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        // Only if the thread didn't get to run at all. Dropping them here is better than leaking.
+        for job in self.take() {
+            job();
+        }
+    }
+}
+
+/// Closes the queue once the last handle is gone.
+struct Closer(Arc<Queue>);
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Release);
+        self.0.thread.unpark();
+    }
+}
+
+/// Drops retired values off the current thread.
+///
+/// It is a cheap handle, cloning it produces another handle to the same reclaimer.
+///
+/// See the [module documentation](self).
+#[derive(Clone)]
+pub struct Reclaimer {
+    inner: Arc<Inner>,
+}
+
+impl Reclaimer {
+    /// Creates a reclaimer with its own background thread.
+    ///
+    /// Handing a value over to the thread is lock-free. If a destructor panics, the panic is
+    /// reported the usual way (through the panic hook) and the thread continues with the other
+    /// values.
+    ///
+    /// The thread terminates once all the handles to the reclaimer (including the ones inside
+    /// [`Deferred`]s) are gone and the pending values are dropped.
+    ///
+    /// # Panics
+    ///
+    /// If the thread can't be spawned.
+    pub fn background() -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel::<Arc<Queue>>();
+        let thread = thread::Builder::new()
+            .name("arc-swap-reclaim".to_owned())
+            .spawn(move || {
+                if let Ok(queue) = receiver.recv() {
+                    queue.run();
+                }
+            })
+            .expect("Failed to spawn the reclaim thread");
+        let queue = Arc::new(Queue {
+            head: AtomicPtr::new(ptr::null_mut()),
+            closed: AtomicBool::new(false),
+            thread: thread.thread().clone(),
+        });
+        sender
+            .send(Arc::clone(&queue))
+            .expect("The reclaim thread is gone before starting");
+        let closer = Closer(queue);
+        Self::with_executor(move |job| closer.0.push(job))
+    }
+
+    /// Creates a reclaimer that submits the drops to the provided executor.
+    ///
+    /// Each retired value produces one [`Job`]. The executor is expected to run it eventually
+    /// (and it can run them in any order and on any thread). Not running a job leaks the value and
+    /// makes [`flush`][Reclaimer::flush] block forever.
+    ///
+    /// ```rust
+    /// # use std::thread;
+    /// use arc_swap::reclaim::Reclaimer;
+    ///
+    /// // Well, one would probably use some thread pool in practice
+    /// let reclaimer = Reclaimer::with_executor(|job| {
+    ///     thread::spawn(job);
+    /// });
+    /// reclaimer.retire(vec![1, 2, 3]);
+    /// reclaimer.flush();
+    /// ```
+    pub fn with_executor<E>(executor: E) -> Self
+    where
+        E: Fn(Job) + Send + Sync + 'static,
+    {
+        Self {
+            inner: Arc::new(Inner {
+                executor: Box::new(executor),
+                pending: AtomicUsize::new(0),
+                idle: Mutex::new(()),
+                done: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Hands the value over to be dropped elsewhere.
+    ///
+    /// This is usually used with the value returned from [`swap`][crate::ArcSwapAny::swap].
+    /// Note that if it is some smart pointer (like [`Arc`]), only this reference is dropped
+    /// elsewhere. If someone else still holds another reference, they'll do the final drop
+    /// themselves; use [`Deferred`] to handle that case too.
+    pub fn retire<T: Send + 'static>(&self, val: T) {
+        self.inner.pending.fetch_add(1, SeqCst);
+        let finished = Finished(Arc::clone(&self.inner));
+        (self.inner.executor)(Box::new(move || {
+            // Drop the value before marking it as done.
+            drop(val);
+            drop(finished);
+        }));
+    }
+
+    /// Blocks until all the retired values are dropped.
+    ///
+    /// This includes the values retired by other threads while waiting, so it might wait for a
+    /// long time if there's a steady stream of them.
+    ///
+    /// This must not be called from within a destructor of a retired value, or it would deadlock.
+    pub fn flush(&self) {
+        let mut idle = self.inner.idle();
+        while self.inner.pending.load(SeqCst) > 0 {
+            idle = self
+                .inner
+                .done
+                .wait(idle)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Number of values retired but not yet dropped.
+    pub fn pending(&self) -> usize {
+        self.inner.pending.load(SeqCst)
+    }
+}
+
+impl Debug for Reclaimer {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_struct("Reclaimer")
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+/// A wrapper that [retires][Reclaimer::retire] the value when it is dropped.
+///
+/// It dereferences to the held value. Putting it inside the [`Arc`] (eg.
+/// `ArcSwap<Deferred<T>>`) makes sure the value is dropped by the reclaimer, no matter which
+/// thread releases the last reference.
+pub struct Deferred<T: Send + 'static> {
+    val: ManuallyDrop<T>,
+    reclaimer: Reclaimer,
+}
+
+impl<T: Send + 'static> Deferred<T> {
+    /// Wraps the value, to be dropped by the given reclaimer.
+    pub fn new(val: T, reclaimer: &Reclaimer) -> Self {
+        Self {
+            val: ManuallyDrop::new(val),
+            reclaimer: reclaimer.clone(),
+        }
+    }
+
+    /// Unwraps the value, without dropping it through the reclaimer.
+    pub fn into_inner(deferred: Self) -> T {
+        let mut deferred = ManuallyDrop::new(deferred);
+        unsafe {
+            // We read both out of the ManuallyDrop and never touch them again.
+            drop(std::ptr::read(&deferred.reclaimer));
+            ManuallyDrop::take(&mut deferred.val)
+        }
+    }
+}
+
+impl<T: Send + 'static> Deref for Deferred<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.val
+    }
+}
+
+impl<T: Send + 'static> DerefMut for Deferred<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.val
+    }
+}
+
+impl<T: Send + 'static> Drop for Deferred<T> {
+    fn drop(&mut self) {
+        // Safe: we are being dropped, so nobody accesses the value any more.
+        let val = unsafe { ManuallyDrop::take(&mut self.val) };
+        self.reclaimer.retire(val);
+    }
+}
+
+impl<T: Debug + Send + 'static> Debug for Deferred<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter.debug_tuple("Deferred").field(&*self.val).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::ThreadId;
+
+    use super::*;
+    use crate::ArcSwap;
+
+    type Drops = Arc<Mutex<Vec<ThreadId>>>;
+
+    /// Records where and whether it got dropped.
+    struct Tracked {
+        drops: Drops,
+    }
+
+    impl Tracked {
+        fn new(drops: &Drops) -> Self {
+            Self {
+                drops: Arc::clone(drops),
+            }
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.drops.lock().unwrap().push(thread::current().id());
+        }
+    }
+
+    #[test]
+    fn background() {
+        let drops = Drops::default();
+        let reclaimer = Reclaimer::background();
+        let shared = ArcSwap::from_pointee(Deferred::new(Tracked::new(&drops), &reclaimer));
+        // A reader holding the old value, so it does the last drop.
+        let guard = shared.load();
+        shared.store(Arc::new(Deferred::new(Tracked::new(&drops), &reclaimer)));
+        assert!(drops.lock().unwrap().is_empty());
+        drop(guard);
+        reclaimer.flush();
+        assert_eq!(0, reclaimer.pending());
+        let dropped = drops.lock().unwrap().clone();
+        assert_eq!(1, dropped.len());
+        assert_ne!(thread::current().id(), dropped[0]);
+
+        let new = Deferred::into_inner(Arc::try_unwrap(shared.into_inner()).ok().unwrap());
+        drop(new);
+        // Dropped right away, in here.
+        assert_eq!(
+            thread::current().id(),
+            *drops.lock().unwrap().last().unwrap()
+        );
+    }
+
+    #[test]
+    fn store_reclaimed() {
+        let drops = Drops::default();
+        let reclaimer = Reclaimer::background();
+        let shared = ArcSwap::from_pointee(Tracked::new(&drops));
+        shared.store_reclaimed(Arc::new(Tracked::new(&drops)), &reclaimer);
+        reclaimer.flush();
+        let dropped = drops.lock().unwrap().clone();
+        assert_eq!(1, dropped.len());
+        assert_ne!(thread::current().id(), dropped[0]);
+    }
+
+    struct Panicky;
+
+    impl Drop for Panicky {
+        fn drop(&mut self) {
+            panic!("Panicky destructor");
+        }
+    }
+
+    /// A panicking destructor doesn't stop the background thread.
+    #[test]
+    fn panic_in_background() {
+        let drops = Drops::default();
+        let reclaimer = Reclaimer::background();
+        reclaimer.retire(Panicky);
+        reclaimer.flush();
+        for _ in 0..3 {
+            reclaimer.retire(Tracked::new(&drops));
+        }
+        reclaimer.flush();
+        let dropped = drops.lock().unwrap().clone();
+        assert_eq!(3, dropped.len());
+        assert!(dropped.iter().all(|id| *id != thread::current().id()));
+    }
+
+    /// Concurrent writers, the values are dropped in the order of each writer.
+    #[test]
+    fn concurrent_retire() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 1000;
+        let reclaimer = Reclaimer::background();
+        let order = Arc::new(Mutex::new(vec![0; THREADS]));
+        struct Ordered(usize, usize, Arc<Mutex<Vec<usize>>>);
+        impl Drop for Ordered {
+            fn drop(&mut self) {
+                let mut order = self.2.lock().unwrap();
+                assert_eq!(order[self.0], self.1);
+                order[self.0] += 1;
+            }
+        }
+        crossbeam_utils::thread::scope(|scope| {
+            for t in 0..THREADS {
+                let reclaimer = &reclaimer;
+                let order = &order;
+                scope.spawn(move |_| {
+                    for i in 0..ITERATIONS {
+                        reclaimer.retire(Ordered(t, i, Arc::clone(order)));
+                    }
+                });
+            }
+        })
+        .unwrap();
+        reclaimer.flush();
+        assert_eq!(vec![ITERATIONS; THREADS], *order.lock().unwrap());
+    }
+
+    #[test]
+    fn executor() {
+        let submitted = Arc::new(AtomicUsize::new(0));
+        let reclaimer = Reclaimer::with_executor({
+            let submitted = Arc::clone(&submitted);
+            move |job| {
+                submitted.fetch_add(1, Ordering::Relaxed);
+                thread::spawn(job);
+            }
+        });
+        let shared = ArcSwap::from_pointee(0);
+        for i in 1..=10 {
+            reclaimer.retire(shared.swap(Arc::new(i)));
+        }
+        reclaimer.flush();
+        assert_eq!(0, reclaimer.pending());
+        assert_eq!(10, submitted.load(Ordering::Relaxed));
+    }
+}
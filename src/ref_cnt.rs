@@ -1,7 +1,7 @@
-use std::mem;
-use std::ptr;
-use std::rc::Rc;
-use std::sync::Arc;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use core::mem;
+use core::ptr;
 
 /// A trait describing smart reference counted pointers.
 ///
@@ -17,7 +17,7 @@ use std::sync::Arc;
 ///
 /// It is also implemented for [Rc], but that is not considered very useful (because the
 /// [ArcSwapAny] is not `Send` or `Sync`, therefore there's very little advantage for it to be
-/// atomic).
+/// atomic). The [`LocalArcSwap`][crate::local::LocalArcSwap] is a better fit for it.
 ///
 /// # Safety
 ///
@@ -26,8 +26,7 @@ use std::sync::Arc;
 /// the value is fine as long as the count doesn't drop to 0), it also must satisfy that if two
 /// pointers have the same value, they point to the same object. This is specifically not true for
 /// ZSTs, but it is true for `Arc`s of ZSTs, because they have the reference counts just after the
-/// value. It would be fine to point to a type-erased version of the same object, though (if one
-/// could use this trait with unsized types in the first place).
+/// value. It would be fine to point to a type-erased version of the same object, though.
 ///
 /// Furthermore, the type should be Pin (eg. if the type is cloned or moved, it should still
 /// point/deref to the same place in memory).
@@ -87,12 +86,124 @@ pub unsafe trait RefCnt: Clone {
     }
 }
 
-unsafe impl<T> RefCnt for Arc<T> {
+/// The base type of a pointer to `Self`, as seen by [`RefCnt`].
+///
+/// The [`RefCnt::Base`] is a sized type, because the raw pointers to it are single words that can
+/// be stored in an [`AtomicPtr`][core::sync::atomic::AtomicPtr]. This trait allows the [Arc] and
+/// [Rc] to point to unsized types too (`Arc<str>`, `Arc<[T]>`, `Arc<dyn Trait>`). The raw
+/// pointer then is only the address of the pointee (a pointer to the `Base`), without the length
+/// or the vtable. That's enough to compare the pointers, but not to convert them back ‒ the
+/// [`ArcSwapAny`][crate::ArcSwapAny] keeps the whole smart pointer in such case (see the
+/// [limitations][crate::docs::limitations]).
+///
+/// Every sized type is its own `Base`. For the unsized types, this is implemented for `str` and
+/// `[T]` and for the common trait objects (the [`Any`][core::any::Any], the
+/// [`Debug`][core::fmt::Debug] and the closures with up to 3 arguments, each with the `Send` and
+/// `Sync` combinations). Other trait objects can be added by the crate defining the trait.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::sync::Arc;
+/// use arc_swap::{ArcSwap, Pointee};
+///
+/// trait Handler {
+///     fn handle(&self) -> usize;
+/// }
+///
+/// impl Pointee for dyn Handler + Send + Sync {
+///     type Base = ();
+/// }
+///
+/// struct Fixed(usize);
+///
+/// impl Handler for Fixed {
+///     fn handle(&self) -> usize {
+///         self.0
+///     }
+/// }
+///
+/// let handler: Arc<dyn Handler + Send + Sync> = Arc::new(Fixed(42));
+/// let shared = ArcSwap::new(handler);
+/// assert_eq!(42, shared.load().handle());
+/// ```
+///
+/// [Arc]: std::sync::Arc
+/// [Rc]: std::rc::Rc
+pub trait Pointee {
+    /// The sized type standing for the pointee in raw pointers.
+    type Base;
+}
+
+impl<T> Pointee for T {
+    type Base = T;
+}
+
+impl Pointee for str {
+    type Base = u8;
+}
+
+impl<T> Pointee for [T] {
     type Base = T;
-    fn into_ptr(me: Arc<T>) -> *mut T {
-        Arc::into_raw(me) as *mut T
+}
+
+macro_rules! dyn_pointee {
+    ($([$($param: ident),*] $tr: path;)*) => {
+        $(
+            impl<'a $(, $param)*> Pointee for dyn $tr + 'a {
+                type Base = ();
+            }
+            impl<'a $(, $param)*> Pointee for dyn $tr + Send + 'a {
+                type Base = ();
+            }
+            impl<'a $(, $param)*> Pointee for dyn $tr + Sync + 'a {
+                type Base = ();
+            }
+            impl<'a $(, $param)*> Pointee for dyn $tr + Send + Sync + 'a {
+                type Base = ();
+            }
+        )*
+    };
+}
+
+dyn_pointee! {
+    [] core::any::Any;
+    [] core::fmt::Debug;
+    [R] Fn() -> R;
+    [A, R] Fn(A) -> R;
+    [A, B, R] Fn(A, B) -> R;
+    [A, B, C, R] Fn(A, B, C) -> R;
+    [R] FnMut() -> R;
+    [A, R] FnMut(A) -> R;
+    [A, B, R] FnMut(A, B) -> R;
+    [A, B, C, R] FnMut(A, B, C) -> R;
+}
+
+/// Turns the pointer to the base back into the pointer to the pointee.
+///
+/// The pointer to the base is the whole pointer for the sized types. The others can't be
+/// reconstructed, the crate doesn't store their raw pointers.
+unsafe fn full<T: ?Sized + Pointee>(ptr: *const T::Base) -> *const T {
+    /// Casting from thin to possibly fat pointer is not allowed, so we go through an union.
+    union Pun<T: ?Sized + Pointee> {
+        base: *const T::Base,
+        full: *const T,
+    }
+
+    assert_eq!(
+        mem::size_of::<*const T>(),
+        mem::size_of::<*const T::Base>(),
+        "Can't convert the raw pointer back into a fat pointer"
+    );
+    Pun::<T> { base: ptr }.full
+}
+
+unsafe impl<T: ?Sized + Pointee> RefCnt for Arc<T> {
+    type Base = T::Base;
+    fn into_ptr(me: Arc<T>) -> *mut T::Base {
+        Arc::into_raw(me) as *mut T::Base
     }
-    fn as_ptr(me: &Arc<T>) -> *mut T {
+    fn as_ptr(me: &Arc<T>) -> *mut T::Base {
         // Slightly convoluted way to do this, but this avoids stacked borrows violations. The same
         // intention as
         //
@@ -109,25 +220,25 @@ unsafe impl<T> RefCnt for Arc<T> {
         // possible), so we future-proof it a bit.
 
         // SAFETY: &T cast to *const T will always be aligned, initialised and valid for reads
-        let ptr = Arc::into_raw(unsafe { std::ptr::read(me) });
+        let ptr = Arc::into_raw(unsafe { core::ptr::read(me) });
         let ptr = ptr as *mut T;
 
         // SAFETY: We got the pointer from into_raw just above
         mem::forget(unsafe { Arc::from_raw(ptr) });
 
-        ptr
+        ptr as *mut T::Base
     }
-    unsafe fn from_ptr(ptr: *const T) -> Arc<T> {
-        Arc::from_raw(ptr)
+    unsafe fn from_ptr(ptr: *const T::Base) -> Arc<T> {
+        Arc::from_raw(full::<T>(ptr))
     }
 }
 
-unsafe impl<T> RefCnt for Rc<T> {
-    type Base = T;
-    fn into_ptr(me: Rc<T>) -> *mut T {
-        Rc::into_raw(me) as *mut T
+unsafe impl<T: ?Sized + Pointee> RefCnt for Rc<T> {
+    type Base = T::Base;
+    fn into_ptr(me: Rc<T>) -> *mut T::Base {
+        Rc::into_raw(me) as *mut T::Base
     }
-    fn as_ptr(me: &Rc<T>) -> *mut T {
+    fn as_ptr(me: &Rc<T>) -> *mut T::Base {
         // Slightly convoluted way to do this, but this avoids stacked borrows violations. The same
         // intention as
         //
@@ -144,16 +255,16 @@ unsafe impl<T> RefCnt for Rc<T> {
         // possible), so we future-proof it a bit.
 
         // SAFETY: &T cast to *const T will always be aligned, initialised and valid for reads
-        let ptr = Rc::into_raw(unsafe { std::ptr::read(me) });
+        let ptr = Rc::into_raw(unsafe { core::ptr::read(me) });
         let ptr = ptr as *mut T;
 
         // SAFETY: We got the pointer from into_raw just above
         mem::forget(unsafe { Rc::from_raw(ptr) });
 
-        ptr
+        ptr as *mut T::Base
     }
-    unsafe fn from_ptr(ptr: *const T) -> Rc<T> {
-        Rc::from_raw(ptr)
+    unsafe fn from_ptr(ptr: *const T::Base) -> Rc<T> {
+        Rc::from_raw(full::<T>(ptr))
     }
 }
 
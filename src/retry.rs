@@ -0,0 +1,472 @@
+//! Bounding the retries of [`rcu`][crate::ArcSwapAny::rcu].
+//!
+//! It also contains the [`TryRcu`] trait describing what the closure of
+//! [`try_rcu`][crate::ArcSwapAny::try_rcu] may return.
+//!
+//! Under heavy contention from other writers, the [`rcu`][crate::ArcSwapAny::rcu] may need to
+//! retry the update many times, each time calling the closure again. The
+//! [`rcu_bounded`][crate::ArcSwapAny::rcu_bounded] allows limiting that by a [`Budget`] and
+//! waiting a bit between the attempts by some [`Backoff`] policy.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use std::time::Duration;
+//! use arc_swap::ArcSwap;
+//! use arc_swap::retry::{Budget, Exponential};
+//!
+//! let routes = ArcSwap::from_pointee(vec!["a"]);
+//! let budget = Budget::new()
+//!     .max_attempts(10)
+//!     .timeout(Duration::from_millis(10));
+//! match routes.rcu_bounded(budget, Exponential::default(), |routes| {
+//!     let mut routes = Vec::clone(routes);
+//!     routes.push("b");
+//!     routes
+//! }) {
+//!     Ok((_old, attempts)) => println!("Updated after {} attempts", attempts),
+//!     Err(e) => println!("Gave up after {} attempts", e.attempts()),
+//! }
+//! ```
+
+use core::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use core::hint;
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+use std::thread;
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+use std::time::{Duration, Instant};
+
+use crate::strategy::Strategy;
+use crate::{ptr_eq, Guard, RefCnt};
+
+/// A storage the update loops can run on.
+///
+/// This is the [`ArcSwapAny`][crate::ArcSwapAny] and the wrappers around it, so they share the
+/// loops of [`rcu`], [`rcu_bounded`] and [`try_rcu`].
+pub(crate) trait Exchange<T: RefCnt, S: Strategy<T>> {
+    fn load(&self) -> Guard<T, S>;
+
+    fn compare_and_swap(&self, current: &T, new: T) -> Guard<T, S>;
+}
+
+/// The loop of [`rcu`][crate::ArcSwapAny::rcu].
+pub(crate) fn rcu<T, S, E, R, F>(storage: &E, mut f: F) -> T
+where
+    T: RefCnt,
+    S: Strategy<T>,
+    E: Exchange<T, S>,
+    F: FnMut(&T) -> R,
+    R: Into<T>,
+{
+    let mut cur = storage.load();
+    loop {
+        let new = f(&cur).into();
+        let prev = storage.compare_and_swap(&cur, new);
+        let swapped = ptr_eq(&*cur, &*prev);
+        if swapped {
+            return Guard::into_inner(prev);
+        } else {
+            metric!(rcu_retries);
+            cur = prev;
+        }
+    }
+}
+
+/// The loop of [`rcu_bounded`][crate::ArcSwapAny::rcu_bounded].
+pub(crate) fn rcu_bounded<T, S, E, R, F, B>(
+    storage: &E,
+    budget: Budget,
+    mut backoff: B,
+    mut f: F,
+) -> Result<(T, usize), Exhausted<T, S>>
+where
+    T: RefCnt,
+    S: Strategy<T>,
+    E: Exchange<T, S>,
+    F: FnMut(&T) -> R,
+    R: Into<T>,
+    B: Backoff,
+{
+    let mut cur = storage.load();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let new = f(&cur).into();
+        let prev = storage.compare_and_swap(&cur, new);
+        if ptr_eq(&*cur, &*prev) {
+            return Ok((Guard::into_inner(prev), attempts));
+        }
+        if budget.exhausted(attempts) {
+            return Err(Exhausted {
+                last: prev,
+                attempts,
+            });
+        }
+        metric!(rcu_retries);
+        // Don't hold onto it (and possibly a fast slot) during the wait.
+        drop(prev);
+        backoff.backoff(attempts);
+        cur = storage.load();
+    }
+}
+
+/// The loop of [`try_rcu`][crate::ArcSwapAny::try_rcu].
+pub(crate) fn try_rcu<T, S, E, O, F>(storage: &E, mut f: F) -> O::Output
+where
+    T: RefCnt,
+    S: Strategy<T>,
+    E: Exchange<T, S>,
+    F: FnMut(&T) -> O,
+    O: TryRcu<T>,
+{
+    let mut cur = storage.load();
+    loop {
+        let (new, payload) = match f(&cur).branch() {
+            Ok(update) => update,
+            Err(abort) => return abort,
+        };
+        let prev = storage.compare_and_swap(&cur, new.into());
+        if ptr_eq(&*cur, &*prev) {
+            return O::done(Guard::into_inner(prev), payload);
+        } else {
+            metric!(rcu_retries);
+            cur = prev;
+        }
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+use self::sealed::Sealed;
+
+/// A result of the closure passed to [`try_rcu`][crate::ArcSwapAny::try_rcu].
+///
+/// It is implemented for:
+///
+/// * `Result<(R, P), E>`, where `Ok` carries the new value and a payload and `Err` aborts the
+///   update. The [`try_rcu`][crate::ArcSwapAny::try_rcu] returns `Result<(T, P), E>`.
+/// * `Option<(R, P)>`, where `None` aborts the update. The
+///   [`try_rcu`][crate::ArcSwapAny::try_rcu] returns `Option<(T, P)>`.
+///
+/// The trait is sealed, it can't be implemented outside of this crate.
+pub trait TryRcu<T>: Sealed {
+    /// The new value to store (converted into `T`).
+    type Value: Into<T>;
+
+    /// The side result of a successful update.
+    type Payload;
+
+    /// What the [`try_rcu`][crate::ArcSwapAny::try_rcu] returns.
+    type Output;
+
+    #[doc(hidden)]
+    fn branch(self) -> Result<(Self::Value, Self::Payload), Self::Output>;
+
+    #[doc(hidden)]
+    fn done(old: T, payload: Self::Payload) -> Self::Output;
+}
+
+impl<R, P, E> Sealed for Result<(R, P), E> {}
+
+impl<T, R: Into<T>, P, E> TryRcu<T> for Result<(R, P), E> {
+    type Value = R;
+    type Payload = P;
+    type Output = Result<(T, P), E>;
+
+    fn branch(self) -> Result<(R, P), Self::Output> {
+        self.map_err(Err)
+    }
+
+    fn done(old: T, payload: P) -> Self::Output {
+        Ok((old, payload))
+    }
+}
+
+impl<R, P> Sealed for Option<(R, P)> {}
+
+impl<T, R: Into<T>, P> TryRcu<T> for Option<(R, P)> {
+    type Value = R;
+    type Payload = P;
+    type Output = Option<(T, P)>;
+
+    fn branch(self) -> Result<(R, P), Self::Output> {
+        self.ok_or(None)
+    }
+
+    fn done(old: T, payload: P) -> Self::Output {
+        Some((old, payload))
+    }
+}
+
+/// A policy of waiting between two attempts of an update.
+pub trait Backoff {
+    /// Waits after a failed attempt.
+    ///
+    /// The `attempt` is the number of attempts done so far (starting at 1).
+    fn backoff(&mut self, attempt: usize);
+}
+
+impl<F: FnMut(usize)> Backoff for F {
+    fn backoff(&mut self, attempt: usize) {
+        self(attempt)
+    }
+}
+
+/// Retries right away, only hinting the CPU we are in a spin loop.
+///
+/// This is the behaviour of [`rcu`][crate::ArcSwapAny::rcu].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Spin;
+
+impl Backoff for Spin {
+    fn backoff(&mut self, _: usize) {
+        hint::spin_loop();
+    }
+}
+
+/// Yields the rest of the time slice to other threads before retrying.
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Yield;
+
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+impl Backoff for Yield {
+    fn backoff(&mut self, _: usize) {
+        thread::yield_now();
+    }
+}
+
+/// Exponential backoff.
+///
+/// Spins for exponentially growing number of iterations. Once it would spin for more than
+/// `2^limit` iterations, it yields to other threads instead (or keeps spinning `2^limit` times in
+/// the `no-std` mode).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Exponential {
+    limit: u32,
+}
+
+impl Exponential {
+    /// Creates the backoff with the given limit of the exponent.
+    ///
+    /// The limit is clamped to `usize::BITS - 1`, so the number of iterations always fits.
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit: limit.min(usize::BITS - 1),
+        }
+    }
+}
+
+impl Default for Exponential {
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+impl Backoff for Exponential {
+    fn backoff(&mut self, attempt: usize) {
+        // The first attempt failed -> spin once
+        let exp = attempt.saturating_sub(1);
+        #[cfg(any(feature = "std", not(feature = "no-std")))]
+        {
+            if exp > self.limit as usize {
+                thread::yield_now();
+                return;
+            }
+        }
+        for _ in 0..1usize << exp.min(self.limit as usize) {
+            hint::spin_loop();
+        }
+    }
+}
+
+/// How many attempts or how much time may be spent on an update.
+///
+/// The default is unlimited (in which case it acts like [`rcu`][crate::ArcSwapAny::rcu]). At
+/// least one attempt is always made, even if the deadline already passed.
+///
+/// The deadlines are not available in the `no-std` mode.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Budget {
+    attempts: Option<usize>,
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// Creates an unlimited budget.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the number of attempts (calls of the closure).
+    pub fn max_attempts(mut self, attempts: usize) -> Self {
+        self.attempts = Some(attempts);
+        self
+    }
+
+    /// Sets a deadline after which no more attempts are started.
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    pub fn deadline(self, deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    /// Sets a deadline `timeout` from now.
+    ///
+    /// Note that the time starts running when this is called, not when the update starts.
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.deadline(Instant::now() + timeout)
+    }
+
+    pub(crate) fn exhausted(&self, attempts: usize) -> bool {
+        if self.attempts.map(|max| attempts >= max).unwrap_or(false) {
+            return true;
+        }
+        #[cfg(any(feature = "std", not(feature = "no-std")))]
+        {
+            if let Some(deadline) = self.deadline {
+                return Instant::now() >= deadline;
+            }
+        }
+        false
+    }
+}
+
+/// The update didn't succeed within the [`Budget`].
+///
+/// Returned from [`rcu_bounded`][crate::ArcSwapAny::rcu_bounded].
+pub struct Exhausted<T: RefCnt, S: Strategy<T>> {
+    pub(crate) last: Guard<T, S>,
+    pub(crate) attempts: usize,
+}
+
+impl<T: RefCnt, S: Strategy<T>> Exhausted<T, S> {
+    /// The last value observed in the storage (the one that made the last attempt fail).
+    pub fn last(&self) -> &Guard<T, S> {
+        &self.last
+    }
+
+    /// Extracts the last observed value.
+    pub fn into_last(self) -> Guard<T, S> {
+        self.last
+    }
+
+    /// How many attempts were made.
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+}
+
+impl<T: RefCnt + Debug, S: Strategy<T>> Debug for Exhausted<T, S> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_struct("Exhausted")
+            .field("last", &self.last)
+            .field("attempts", &self.attempts)
+            .finish()
+    }
+}
+
+impl<T: RefCnt, S: Strategy<T>> Display for Exhausted<T, S> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        write!(
+            formatter,
+            "Update not done after {} attempts",
+            self.attempts
+        )
+    }
+}
+
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+impl<T: RefCnt + Debug, S: Strategy<T>> std::error::Error for Exhausted<T, S> {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::ArcSwap;
+
+    #[test]
+    fn uncontended() {
+        let shared = ArcSwap::from_pointee(0);
+        let (old, attempts) = shared
+            .rcu_bounded(Budget::new().max_attempts(1), Spin, |cur| **cur + 1)
+            .unwrap();
+        assert_eq!(0, *old);
+        assert_eq!(1, attempts);
+        assert_eq!(1, **shared.load());
+    }
+
+    /// Someone else always wins, so we run out of the attempts.
+    #[test]
+    fn exhausted() {
+        let shared = ArcSwap::from_pointee(0);
+        let mut backoffs = Vec::new();
+        let err = shared
+            .rcu_bounded(
+                Budget::new().max_attempts(3),
+                |attempt| backoffs.push(attempt),
+                |cur| {
+                    shared.store(Arc::new(**cur + 10));
+                    **cur + 1
+                },
+            )
+            .unwrap_err();
+        assert_eq!(3, err.attempts());
+        assert_eq!(30, ***err.last());
+        assert_eq!(vec![1, 2], backoffs);
+        assert_eq!(30, **shared.load());
+    }
+
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    #[test]
+    fn deadline() {
+        let shared = ArcSwap::from_pointee(0);
+        let budget = Budget::new().deadline(Instant::now());
+        let err = shared
+            .rcu_bounded(budget, Yield, |cur| {
+                shared.store(Arc::new(**cur + 10));
+                **cur + 1
+            })
+            .unwrap_err();
+        assert_eq!(1, err.attempts());
+        assert_eq!(10, **err.into_last());
+    }
+
+    /// Huge limits don't overflow the number of iterations.
+    #[test]
+    fn exponential_limit() {
+        assert_eq!(Exponential::new(usize::BITS - 1), Exponential::new(64));
+        assert_eq!(
+            Exponential::new(usize::BITS - 1),
+            Exponential::new(u32::MAX)
+        );
+    }
+
+    #[test]
+    fn contended() {
+        const THREADS: usize = 10;
+        const ITERATIONS: usize = 50;
+        let shared = ArcSwap::from_pointee(0);
+        crossbeam_utils::thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|_| {
+                    for _ in 0..ITERATIONS {
+                        shared
+                            .rcu_bounded(Budget::new(), Exponential::default(), |cur| **cur + 1)
+                            .unwrap();
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(THREADS * ITERATIONS, **shared.load());
+    }
+}
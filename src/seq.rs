@@ -0,0 +1,120 @@
+//! Detecting writes that overlap with reading of several instances.
+//!
+//! Loading multiple instances one by one can produce a combination of values that never existed
+//! at the same time (a write to one of them happened in between the loads). To detect that, every
+//! write is counted in two counters ‒ one bumped before it changes the storage and one after it.
+//! Reading the `finished` counter before the loads and the `started` one after them and getting
+//! the same value means no write was in progress at any time during the loads, so the loaded
+//! values all were there at the same moment.
+//!
+//! A transaction counts itself as one long write to each of its instances, so the readers don't
+//! see the values of a transaction in progress (or one being rolled back).
+//!
+//! To not make all the writers fight over a single cache line (and to not make readers retry
+//! because of writes to unrelated instances), the counters are sharded by the address of the
+//! storage.
+//!
+//! The counters only ever grow and `started >= finished` for each shard. Therefore, comparing
+//! sums over several shards is the same as comparing each shard.
+
+use alloc::boxed::Box;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::Ordering::*;
+use core::sync::atomic::{self, AtomicPtr, AtomicUsize};
+
+use crate::spin;
+
+const SHARD_CNT: usize = 32;
+
+#[derive(Default)]
+#[repr(align(64))]
+struct Shard {
+    started: AtomicUsize,
+    finished: AtomicUsize,
+}
+
+type Shards = [Shard; SHARD_CNT];
+
+/// The lazily-created shards.
+static SHARDS: AtomicPtr<Shards> = AtomicPtr::new(ptr::null_mut());
+
+fn shards() -> &'static Shards {
+    let current = SHARDS.load(Acquire);
+    if let Some(shards) = unsafe { current.as_ref() } {
+        return shards;
+    }
+    let new = Box::into_raw(Box::<Shards>::default());
+    match SHARDS.compare_exchange(ptr::null_mut(), new, AcqRel, Acquire) {
+        // It's leaked on purpose, it lives for the rest of the program.
+        Ok(_) => unsafe { &*new },
+        Err(other) => {
+            // Someone was faster, use theirs.
+            drop(unsafe { Box::from_raw(new) });
+            unsafe { &*other }
+        }
+    }
+}
+
+fn shard(addr: usize) -> &'static Shard {
+    // Instances next to each other (eg. in the same struct) should land in different shards.
+    &shards()[(addr / mem::size_of::<usize>()) % SHARD_CNT]
+}
+
+/// A write in progress to the storage at the given address.
+///
+/// Finishes on drop.
+pub(crate) struct Writing(&'static Shard);
+
+impl Writing {
+    pub(crate) fn begin(addr: usize) -> Self {
+        let shard = shard(addr);
+        shard.started.fetch_add(1, SeqCst);
+        Writing(shard)
+    }
+}
+
+impl Drop for Writing {
+    fn drop(&mut self) {
+        self.0.finished.fetch_add(1, SeqCst);
+    }
+}
+
+/// Finishes the writes of the threads that no longer exist (after a `fork`).
+///
+/// Otherwise the readers would wait for them forever.
+#[cfg(unix)]
+pub(crate) fn after_fork_child() {
+    if let Some(shards) = unsafe { SHARDS.load(Acquire).as_ref() } {
+        for shard in shards {
+            shard.finished.store(shard.started.load(SeqCst), SeqCst);
+        }
+    }
+}
+
+/// Runs the `read` repeatedly until no write to any of the `addrs` overlaps with it.
+///
+/// This is only lock-free, a reader may need to retry in case of continuous writes.
+pub(crate) fn read_consistent<R, F>(addrs: &[usize], mut read: F) -> R
+where
+    F: FnMut() -> R,
+{
+    let mut attempt = 0usize;
+    loop {
+        let finished = addrs.iter().fold(0usize, |sum, addr| {
+            sum.wrapping_add(shard(*addr).finished.load(SeqCst))
+        });
+        let result = read();
+        atomic::fence(SeqCst);
+        let started = addrs.iter().fold(0usize, |sum, addr| {
+            sum.wrapping_add(shard(*addr).started.load(SeqCst))
+        });
+        if started == finished {
+            return result;
+        }
+        // Release whatever the read holds before trying again.
+        drop(result);
+        spin::relax(attempt);
+        attempt += 1;
+    }
+}
@@ -0,0 +1,84 @@
+//! Support for the [`servo_arc::Arc`], an `Arc` without the weak count.
+
+use core::mem;
+use core::ops::Deref;
+use core::ptr;
+
+use servo_arc::Arc;
+
+use crate::access::{Access, DirectDeref};
+use crate::{ArcSwapAny, RefCnt, Strategy};
+
+unsafe impl<T> RefCnt for Arc<T> {
+    type Base = T;
+    fn into_ptr(me: Arc<T>) -> *mut T {
+        Arc::into_raw(me) as *mut T
+    }
+    fn as_ptr(me: &Arc<T>) -> *mut T {
+        // There's no as_ptr, so we go through a shallow copy that doesn't own its reference, the
+        // same way as with the std Arc (see there for why not just casting the reference).
+
+        // SAFETY: &Arc cast to *const Arc is aligned, initialised and valid for reads
+        let ptr = Arc::into_raw(unsafe { ptr::read(me) }) as *mut T;
+        // SAFETY: We got the pointer from into_raw just above
+        mem::forget(unsafe { Arc::from_raw(ptr) });
+        ptr
+    }
+    unsafe fn from_ptr(ptr: *const T) -> Arc<T> {
+        Arc::from_raw(ptr)
+    }
+}
+
+impl<T, S: Strategy<Arc<T>>> Deref for DirectDeref<Arc<T>, S> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.0.deref().deref()
+    }
+}
+
+impl<T, S: Strategy<Arc<T>>> Access<T> for ArcSwapAny<Arc<T>, S> {
+    type Guard = DirectDeref<Arc<T>, S>;
+    fn load(&self) -> Self::Guard {
+        DirectDeref(self.load())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+
+    #[test]
+    fn load_store() {
+        let shared: ArcSwapAny<_> = ArcSwapAny::new(Arc::new(1));
+        let first = shared.load_full();
+        shared.store(Arc::new(2));
+        assert_eq!(1, *first);
+        assert_eq!(2, **shared.load());
+
+        let prev = shared.compare_and_swap(&first, Arc::new(3));
+        assert_eq!(2, **prev);
+        let prev = shared.compare_and_swap(&prev, Arc::new(3));
+        assert_eq!(2, **prev);
+        assert_eq!(3, **shared.load());
+        drop(prev);
+        assert!(first.is_unique());
+    }
+
+    #[test]
+    fn access_and_cache() {
+        struct Cfg {
+            value: usize,
+        }
+
+        let shared: ArcSwapAny<_> = ArcSwapAny::new(Arc::new(Cfg { value: 1 }));
+        let mapped = shared.map(|cfg: &Cfg| &cfg.value);
+        let mut cache = Cache::new(&shared);
+        assert_eq!(1, *Access::<usize>::load(&mapped));
+        assert_eq!(1, cache.load().value);
+
+        shared.store(Arc::new(Cfg { value: 2 }));
+        assert_eq!(2, *Access::<usize>::load(&mapped));
+        assert_eq!(2, cache.load().value);
+    }
+}
@@ -0,0 +1,228 @@
+//! Loading several instances at once.
+//!
+//! Loading multiple [`ArcSwapAny`]s one after another may produce a combination of values that
+//! never existed at the same time ‒ some other thread may have changed one of them in between the
+//! loads. The [`load_consistent`] loads them in a way that all the values were there at the same
+//! moment.
+//!
+//! This works with any [`Strategy`] and doesn't need any cooperation from the writers (they
+//! can keep using the usual [`store`][ArcSwapAny::store], [`rcu`][ArcSwapAny::rcu], etc).
+//!
+//! Note that it doesn't make several writes into one ‒ if a writer updates one instance and then
+//! another, the snapshot can still be taken in between these two writes. Put both updates into
+//! one [`Transaction`] to change several instances at once.
+//!
+//! # Performance
+//!
+//! The loads are retried if some write to any of the instances overlaps with them. Therefore this
+//! is only lock-free, not wait-free like the plain [`load`][ArcSwapAny::load], and readers can be
+//! slowed down by very busy writers. Writes to unrelated instances may occasionally cause a retry
+//! too.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use arc_swap::ArcSwap;
+//! use arc_swap::snapshot::load_consistent;
+//! # use std::sync::Arc;
+//! # use arc_swap::transaction::Transaction;
+//!
+//! let users = ArcSwap::from_pointee(vec!["alice"]);
+//! let admins = ArcSwap::from_pointee(vec!["alice"]);
+//! let limits = ArcSwap::from_pointee(10);
+//!
+//! let mut transaction = Transaction::new();
+//! transaction
+//!     .swap(&users, Arc::new(vec!["alice", "bob"]))
+//!     .swap(&admins, Arc::new(vec!["alice", "bob"]));
+//! transaction.commit().unwrap();
+//!
+//! // Instances of the same type can be passed as an array and produce an array of guards
+//! let [user_list, admin_list] = load_consistent(&[&users, &admins]);
+//! assert_eq!(user_list.len(), admin_list.len());
+//!
+//! // Or as a tuple, if they are of different types
+//! let (user_list, limit) = load_consistent((&users, &limits));
+//! assert_eq!(2, user_list.len());
+//! assert_eq!(10, **limit);
+//! ```
+//!
+//! [`Transaction`]: crate::transaction::Transaction
+
+use alloc::vec::Vec;
+
+use crate::strategy::Strategy;
+use crate::{seq, ArcSwapAny, Guard, RefCnt};
+
+pub(crate) mod sealed {
+    pub trait Instances {
+        type Addrs: AsRef<[usize]>;
+        fn addrs(&self) -> Self::Addrs;
+        fn load(&self) -> <Self as super::Instances>::Guards
+        where
+            Self: super::Instances;
+    }
+}
+
+/// A set of instances that can be loaded together.
+///
+/// This is implemented for:
+///
+/// * Tuples of references to [`ArcSwapAny`] (up to 8 elements), possibly of different types. The
+///   result is a tuple of [`Guard`]s.
+/// * References to arrays of references to the same type of [`ArcSwapAny`] (up to 8 elements).
+///   The result is an array of [`Guard`]s.
+/// * Slices of references to [`ArcSwapAny`] of any length. The result is a [`Vec`] of
+///   [`Guard`]s.
+///
+/// The trait is sealed and can't be implemented outside of this crate.
+pub trait Instances: sealed::Instances {
+    /// The loaded values.
+    type Guards;
+}
+
+/// Loads the values of all the `instances`, such that they all existed at the same time.
+///
+/// See the [module documentation](self).
+pub fn load_consistent<I: Instances>(instances: I) -> I::Guards {
+    let addrs = instances.addrs();
+    seq::read_consistent(addrs.as_ref(), || instances.load())
+}
+
+impl<T: RefCnt, S: Strategy<T>> Instances for &[&ArcSwapAny<T, S>] {
+    type Guards = Vec<Guard<T, S>>;
+}
+
+impl<T: RefCnt, S: Strategy<T>> sealed::Instances for &[&ArcSwapAny<T, S>] {
+    type Addrs = Vec<usize>;
+
+    fn addrs(&self) -> Vec<usize> {
+        self.iter().map(|instance| instance.addr()).collect()
+    }
+
+    fn load(&self) -> <Self as Instances>::Guards {
+        self.iter().map(|instance| instance.load()).collect()
+    }
+}
+
+macro_rules! array {
+    ($($len: expr => ($($idx: tt)*);)*) => {
+        $(
+            impl<T: RefCnt, S: Strategy<T>> Instances for &[&ArcSwapAny<T, S>; $len] {
+                type Guards = [Guard<T, S>; $len];
+            }
+
+            impl<T: RefCnt, S: Strategy<T>> sealed::Instances for &[&ArcSwapAny<T, S>; $len] {
+                type Addrs = [usize; $len];
+
+                fn addrs(&self) -> [usize; $len] {
+                    [$(self[$idx].addr()),*]
+                }
+
+                fn load(&self) -> <Self as Instances>::Guards {
+                    [$(self[$idx].load()),*]
+                }
+            }
+        )*
+    };
+}
+
+array! {
+    1 => (0);
+    2 => (0 1);
+    3 => (0 1 2);
+    4 => (0 1 2 3);
+    5 => (0 1 2 3 4);
+    6 => (0 1 2 3 4 5);
+    7 => (0 1 2 3 4 5 6);
+    8 => (0 1 2 3 4 5 6 7);
+}
+
+macro_rules! tuple {
+    ($($len: expr => ($($t: ident $s: ident $idx: tt)*);)*) => {
+        $(
+            impl<'a, $($t: RefCnt, $s: Strategy<$t>),*> Instances
+                for ($(&'a ArcSwapAny<$t, $s>,)*)
+            {
+                type Guards = ($(Guard<$t, $s>,)*);
+            }
+
+            impl<'a, $($t: RefCnt, $s: Strategy<$t>),*> sealed::Instances
+                for ($(&'a ArcSwapAny<$t, $s>,)*)
+            {
+                type Addrs = [usize; $len];
+
+                fn addrs(&self) -> [usize; $len] {
+                    [$(self.$idx.addr()),*]
+                }
+
+                fn load(&self) -> <Self as Instances>::Guards {
+                    ($(self.$idx.load(),)*)
+                }
+            }
+        )*
+    };
+}
+
+tuple! {
+    1 => (A SA 0);
+    2 => (A SA 0 B SB 1);
+    3 => (A SA 0 B SB 1 C SC 2);
+    4 => (A SA 0 B SB 1 C SC 2 D SD 3);
+    5 => (A SA 0 B SB 1 C SC 2 D SD 3 E SE 4);
+    6 => (A SA 0 B SB 1 C SC 2 D SD 3 E SE 4 F SF 5);
+    7 => (A SA 0 B SB 1 C SC 2 D SD 3 E SE 4 F SF 5 G SG 6);
+    8 => (A SA 0 B SB 1 C SC 2 D SD 3 E SE 4 F SF 5 G SG 6 H SH 7);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{ArcSwap, ArcSwapOption};
+
+    #[test]
+    fn shapes() {
+        let a = ArcSwap::from_pointee(1);
+        let b = ArcSwap::from_pointee(2);
+        let c = ArcSwapOption::<String>::empty();
+
+        let [x, y] = load_consistent(&[&a, &b]);
+        assert_eq!((1, 2), (**x, **y));
+
+        let (x, z) = load_consistent((&a, &c));
+        assert_eq!(1, **x);
+        assert!(z.is_none());
+
+        let instances = [&a, &b, &a];
+        let all = load_consistent(&instances[..]);
+        assert_eq!(vec![1, 2, 1], all.iter().map(|g| ***g).collect::<Vec<_>>());
+    }
+
+    /// A writer changing the instances in order (a first) never lets a reader see a newer value
+    /// in a later instance. The plain writes are detected too.
+    #[test]
+    fn ordered_writes() {
+        const ITERATIONS: usize = 1000;
+        let a = ArcSwap::from_pointee(0);
+        let b = ArcSwap::from_pointee(0);
+        crossbeam_utils::thread::scope(|scope| {
+            scope.spawn(|_| {
+                for i in 1..=ITERATIONS {
+                    a.store(Arc::new(i));
+                    b.rcu(|old| **old + 1);
+                }
+            });
+            scope.spawn(|_| loop {
+                // Load in the other order, a plain load could see b newer than a.
+                let (y, x) = load_consistent((&b, &a));
+                assert!(**x == **y || **x == **y + 1, "{} vs {}", x, y);
+                if **y == ITERATIONS {
+                    break;
+                }
+            });
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+//! Spinning synchronization primitives.
+//!
+//! The few places that need to wait for another thread (and don't have a good reason to park) use
+//! these. Unlike the ones from the standard library, they are available without `std`.
+
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::Ordering::*;
+
+use crate::sync::{self, AtomicBool, MutPtr, UnsafeCell};
+
+/// Waits a little bit before trying something again.
+///
+/// The `attempt` is the number of the failed attempts so far. The first few just hint the CPU, the
+/// later ones give up the time slice (if there's an OS to give it to).
+pub(crate) fn relax(attempt: usize) {
+    if attempt < 16
+        || cfg!(any(
+            not(any(feature = "std", not(feature = "no-std"))),
+            loom
+        ))
+    {
+        sync::spin_loop_hint();
+    } else {
+        #[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+        std::thread::yield_now();
+    }
+}
+
+/// A simple spin lock.
+///
+/// Meant for the rarely contended places with short critical sections.
+pub(crate) struct Mutex<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    const_fn! {
+        pub(crate) fn new(data: T) -> Self {
+            Mutex {
+                locked: AtomicBool::new(false),
+                data: UnsafeCell::new(data),
+            }
+        }
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        let mut attempt = 0;
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Acquire, Relaxed)
+            .is_err()
+        {
+            relax(attempt);
+            attempt += 1;
+        }
+        MutexGuard {
+            lock: self,
+            data: sync::cell_ptr(&self.data),
+        }
+    }
+
+    /// Locks the mutex, unless someone else already holds it.
+    pub(crate) fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Acquire, Relaxed)
+            .ok()
+            .map(|_| MutexGuard {
+                lock: self,
+                data: sync::cell_ptr(&self.data),
+            })
+    }
+
+    /// Unlocks the mutex, even if someone holds it.
+    ///
+    /// # Safety
+    ///
+    /// The holder must no longer exist (eg. a thread that is gone after a `fork`).
+    #[cfg(unix)]
+    pub(crate) unsafe fn force_unlock(&self) {
+        self.locked.store(false, Release);
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub(crate) struct MutexGuard<'a, T> {
+    lock: &'a Mutex<T>,
+    data: MutPtr<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // We hold the lock
+        unsafe { sync::deref_cell(&self.data) }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // We hold the lock
+        unsafe { sync::deref_cell(&self.data) }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter() {
+        const ITERATIONS: usize = 1000;
+        let lock = Mutex::new(0);
+        crossbeam_utils::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|_| {
+                    for _ in 0..ITERATIONS {
+                        *lock.lock() += 1;
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!(4 * ITERATIONS, *lock.lock());
+    }
+}
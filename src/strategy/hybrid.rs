@@ -10,49 +10,61 @@
 //! case, the reference is bumped and this secondary debt slot is released, so it is available for
 //! further loads.
 //!
-//! See the [crate::debt] module for the actual slot manipulation. Here we just wrap them into the
-//! strategy.
+//! The [`DefaultStrategy`][super::DefaultStrategy] is this strategy with the [`DefaultConfig`].
+//! Other [configurations][Config] can be plugged in, eg. to allow more fast slots per thread.
 
-use std::borrow::Borrow;
-use std::mem::{self, ManuallyDrop};
-use std::ops::Deref;
-use std::ptr;
-use std::sync::atomic::AtomicPtr;
-use std::sync::atomic::Ordering::*;
+use core::borrow::Borrow;
+use core::mem::{self, ManuallyDrop};
+use core::sync::atomic::Ordering::*;
 
 use super::sealed::{CaS, InnerStrategy, Protected};
-use crate::debt::{Debt, LocalNode};
+use crate::as_raw::AsRaw;
+use crate::debt::{Debt, Domain, LocalNode, DEBT_SLOT_CNT};
+use crate::nodes::ReaderHandle;
 use crate::ref_cnt::RefCnt;
+use crate::sync::{self, AtomicPtr};
+use crate::thin;
 
+#[doc(hidden)]
 pub struct HybridProtection<T: RefCnt> {
     debt: Option<&'static Debt>,
+    /// The word we either owe (if there's a debt) or own a reference to.
+    word: *const (),
+    /// A view of the value behind the word. Never dropped, the reference is released through the
+    /// word.
     ptr: ManuallyDrop<T>,
 }
 
+// The raw word is just a different form of the T.
+unsafe impl<T: RefCnt + Send> Send for HybridProtection<T> {}
+unsafe impl<T: RefCnt + Sync> Sync for HybridProtection<T> {}
+
 impl<T: RefCnt> HybridProtection<T> {
-    pub(super) unsafe fn new(ptr: *const T::Base, debt: Option<&'static Debt>) -> Self {
+    pub(super) unsafe fn new(word: *const (), debt: Option<&'static Debt>) -> Self {
         Self {
             debt,
-            ptr: ManuallyDrop::new(T::from_ptr(ptr)),
+            word,
+            ptr: thin::view(word),
         }
     }
 
     /// Try getting a dept into a fast slot.
     #[inline]
-    fn attempt(node: &LocalNode, storage: &AtomicPtr<T::Base>) -> Option<Self> {
+    fn attempt(node: &LocalNode, storage: &AtomicPtr<()>, fast_slots: usize) -> Option<Self> {
         // Relaxed is good enough here, see the Acquire below
         let ptr = storage.load(Relaxed);
         // Try to get a debt slot. If not possible, fail.
-        let debt = node.new_fast(ptr as usize)?;
+        let debt = node.new_fast(ptr as usize, fast_slots)?;
 
         // Acquire to get the data.
         //
         // SeqCst to make sure the storage vs. the debt are well ordered.
+        sync::loom_fence();
         let confirm = storage.load(SeqCst);
         if ptr == confirm {
             // Successfully got a debt
             Some(unsafe { Self::new(ptr, Some(debt)) })
-        } else if debt.pay::<T>(ptr) {
+        } else if debt.release(ptr) {
             // It changed in the meantime, we return the debt (that is on the outdated pointer,
             // possibly destroyed) and fail.
             None
@@ -64,13 +76,14 @@ impl<T: RefCnt> HybridProtection<T> {
     }
 
     /// Get a debt slot using the slower but always successful mechanism.
-    fn fallback(node: &LocalNode, storage: &AtomicPtr<T::Base>) -> Self {
+    fn fallback(node: &LocalNode, storage: &AtomicPtr<()>) -> Self {
         // First, we claim a debt slot and store the address of the atomic pointer there, so the
         // writer can optionally help us out with loading and protecting something.
         let gen = node.new_helping(storage as *const _ as usize);
         // We already synchronized the start of the sequence by SeqCst in the new_helping vs swap on
         // the pointer. We just need to make sure to bring the pointee in (this can be newer than
         // what we got in the Debt)
+        sync::loom_fence();
         let candidate = storage.load(Acquire);
 
         // Try to replace the debt with our candidate. If it works, we get the debt slot to use. If
@@ -78,24 +91,68 @@ impl<T: RefCnt> HybridProtection<T> {
         match node.confirm_helping(gen, candidate as usize) {
             Ok(debt) => {
                 // The fast path -> we got the debt confirmed alright.
-                Self::from_inner(unsafe { Self::new(candidate, Some(debt)).into_inner() })
+                let word = unsafe { Self::new(candidate, Some(debt)) }.into_word();
+                unsafe { Self::new(word, None) }
             }
             Err((unused_debt, replacement)) => {
                 // The debt is on the candidate we provided and it is unused, we so we just pay it
                 // back right away.
-                if !unused_debt.pay::<T>(candidate) {
-                    unsafe { T::dec(candidate) };
+                if !unused_debt.release(candidate) {
+                    unsafe { thin::dec::<T>(candidate) };
                 }
                 // We got a (possibly) different pointer out. But that one is already protected and
                 // the slot is paid back.
-                unsafe { Self::new(replacement as *mut _, None) }
+                unsafe { Self::new(replacement as *const (), None) }
             }
         }
     }
 
+    /// Loads and protects the value in the storage, using the given node of the `domain`.
+    ///
+    /// Up to `fast_slots` of the node's fast slots are tried before falling back.
     #[inline]
-    fn as_ptr(&self) -> *const T::Base {
-        T::as_ptr(self.ptr.deref())
+    pub(super) fn load(
+        node: &LocalNode,
+        domain: &Domain,
+        storage: &AtomicPtr<()>,
+        fast_slots: usize,
+    ) -> Self {
+        if !node.is_claimed() {
+            // No slots for the debts, we have to own the reference.
+            let word = domain.overflow().protect(|| {
+                // SeqCst ‒ must be ordered with the registration of the reader, see overflow.
+                let word = storage.load(SeqCst);
+                unsafe { thin::inc::<T>(word) };
+                word
+            });
+            return unsafe { Self::new(word, None) };
+        }
+        if let Some(protection) = Self::attempt(node, storage, fast_slots) {
+            metric!(fast_path);
+            return protection;
+        }
+        metric!(fallback);
+        Self::fallback(node, storage)
+    }
+
+    /// Turns it into a word with owned reference.
+    #[inline]
+    fn into_word(mut self) -> *const () {
+        // Drop any debt and release any lock held by the given guard and return a
+        // full-featured value that even can outlive the ArcSwap it originated from.
+        match self.debt.take() {
+            None => (), // We have a fully loaded ref-counted pointer.
+            Some(debt) => {
+                unsafe { thin::inc::<T>(self.word) };
+                if !debt.release(self.word) {
+                    unsafe { thin::dec::<T>(self.word) };
+                }
+            }
+        }
+
+        let word = self.word;
+        mem::forget(self);
+        word
     }
 }
 
@@ -108,48 +165,30 @@ impl<T: RefCnt> Drop for HybridProtection<T> {
             None => (),
             // If we owed something, just return the debt. We don't have a pointer owned, so
             // nothing to release.
-            Some(debt) => {
-                let ptr = T::as_ptr(&self.ptr);
-                if debt.pay::<T>(ptr) {
-                    return;
-                }
-                // But if the debt was already paid for us, we need to release the pointer, as we
-                // were effectively already in the Unprotected mode.
-            }
+            Some(debt) if debt.release(self.word) => return,
+            // But if the debt was already paid for us, we need to release the pointer, as we
+            // were effectively already in the Unprotected mode.
+            Some(_) => (),
         }
-        // Equivalent to T::dec(ptr)
-        unsafe { ManuallyDrop::drop(&mut self.ptr) };
+        unsafe { thin::dec::<T>(self.word) };
     }
 }
 
 impl<T: RefCnt> Protected<T> for HybridProtection<T> {
     #[inline]
     fn from_inner(ptr: T) -> Self {
-        Self {
-            debt: None,
-            ptr: ManuallyDrop::new(ptr),
-        }
+        unsafe { Self::new(thin::into_word(ptr), None) }
     }
 
     #[inline]
-    fn into_inner(mut self) -> T {
-        // Drop any debt and release any lock held by the given guard and return a
-        // full-featured value that even can outlive the ArcSwap it originated from.
-        match self.debt.take() {
-            None => (), // We have a fully loaded ref-counted pointer.
-            Some(debt) => {
-                let ptr = T::inc(&self.ptr);
-                if !debt.pay::<T>(ptr) {
-                    unsafe { T::dec(ptr) };
-                }
-            }
-        }
+    fn into_inner(self) -> T {
+        let word = self.into_word();
+        unsafe { thin::from_word(word) }
+    }
 
-        // The ptr::read & forget is something like a cheating move. We can't move it out, because
-        // we have a destructor and Rust doesn't allow us to do that.
-        let inner = unsafe { ptr::read(self.ptr.deref()) };
-        mem::forget(self);
-        inner
+    #[inline]
+    fn word(&self) -> *const () {
+        self.word
     }
 }
 
@@ -160,76 +199,156 @@ impl<T: RefCnt> Borrow<T> for HybridProtection<T> {
     }
 }
 
+/// Configuration of the [`HybridStrategy`].
+///
+/// All the items have defaults, so a configuration needs to specify only what it changes.
+///
+/// # Examples
+///
+/// A strategy for threads that hold many [`Guard`][crate::Guard]s at once:
+///
+/// ```rust
+/// use arc_swap::ArcSwapAny;
+/// use arc_swap::strategy::hybrid::{Config, HybridStrategy};
+///
+/// #[derive(Default)]
+/// struct ManySlots;
+///
+/// impl Config for ManySlots {
+///     const FAST_SLOTS: usize = 32;
+/// }
+///
+/// let shared = ArcSwapAny::<_, HybridStrategy<ManySlots>>::new(std::sync::Arc::new(42));
+/// let guards = (0..32).map(|_| shared.load()).collect::<Vec<_>>();
+/// assert_eq!(32, guards.len());
+/// ```
 pub trait Config {
-    // Mostly for testing, way to disable the fast slo
-    const USE_FAST: bool;
+    /// Whether to use the fast slots at all.
+    ///
+    /// Mostly for testing, a way to disable them.
+    const USE_FAST: bool = true;
+
+    /// How many fast slots a thread may use at once.
+    ///
+    /// Each [`Guard`][crate::Guard] holds one for its lifetime. Once they are used up, further
+    /// loads take the slower fallback path. Each node has 8 of them, more are allocated on demand
+    /// (and kept until the node is freed).
+    ///
+    /// The downside of more slots is that every writer (to any instance using the same nodes)
+    /// has to check all of them, in all the nodes. Therefore it is better to keep it small, unless
+    /// the threads really hold many guards at once (a small number of threads with many slots
+    /// is better than many threads with many slots).
+    const FAST_SLOTS: usize = DEBT_SLOT_CNT;
 }
 
+/// The configuration of the [`DefaultStrategy`][super::DefaultStrategy].
 #[derive(Clone, Default)]
 pub struct DefaultConfig;
 
-impl Config for DefaultConfig {
-    const USE_FAST: bool = true;
-}
+impl Config for DefaultConfig {}
 
+/// The strategy based on debts, with the given [configuration][Config].
+///
+/// See the [`DefaultStrategy`][super::DefaultStrategy] for its properties.
 #[derive(Clone, Default)]
 pub struct HybridStrategy<Cfg> {
     pub(crate) _config: Cfg,
 }
 
+impl<Cfg: Config> HybridStrategy<Cfg> {
+    /// The number of fast slots a load may try.
+    const FAST_SLOTS: usize = if Cfg::USE_FAST { Cfg::FAST_SLOTS } else { 0 };
+}
+
 impl<T, Cfg> InnerStrategy<T> for HybridStrategy<Cfg>
 where
     T: RefCnt,
     Cfg: Config,
 {
     type Protected = HybridProtection<T>;
-    unsafe fn load(&self, storage: &AtomicPtr<T::Base>) -> Self::Protected {
+    unsafe fn load(&self, storage: &AtomicPtr<()>) -> Self::Protected {
         LocalNode::with(|node| {
-            let fast = if Cfg::USE_FAST {
-                HybridProtection::attempt(node, storage)
-            } else {
-                None
-            };
-            fast.unwrap_or_else(|| HybridProtection::fallback(node, storage))
+            HybridProtection::load(node, Domain::global(), storage, Self::FAST_SLOTS)
         })
     }
-    unsafe fn wait_for_readers(&self, old: *const T::Base, storage: &AtomicPtr<T::Base>) {
-        // The pay_all may need to provide fresh replacement values if someone else is loading from
-        // this particular storage. We do so by the exact same way, by `load` ‒ it's OK, a writer
-        // does not hold a slot and the reader doesn't recurse back into writer, so we won't run
-        // out of slots.
-        let replacement = || self.load(storage).into_inner();
-        Debt::pay_all::<T, _>(old, storage as *const _ as usize, replacement);
+    unsafe fn load_with(&self, storage: &AtomicPtr<()>, handle: &ReaderHandle) -> Self::Protected {
+        handle
+            .0
+            .run(|node| HybridProtection::load(node, Domain::global(), storage, Self::FAST_SLOTS))
+    }
+    unsafe fn wait_for_readers(&self, old: *const (), storage: &AtomicPtr<()>) {
+        wait_for_readers::<T, _>(self, Domain::global(), old, storage);
     }
 }
 
 impl<T: RefCnt, Cfg: Config> CaS<T> for HybridStrategy<Cfg> {
-    unsafe fn compare_and_swap<C: crate::as_raw::AsRaw<T::Base>>(
+    unsafe fn compare_exchange<C: AsRaw<T::Base>>(
         &self,
-        storage: &AtomicPtr<T::Base>,
+        storage: &AtomicPtr<()>,
         current: C,
         new: T,
-    ) -> Self::Protected {
-        loop {
-            let old = <Self as InnerStrategy<T>>::load(self, storage);
-            // Observation of their inequality is enough to make a verdict
-            if old.as_ptr() != current.as_raw() {
-                return old;
-            }
-            // If they are still equal, put the new one in.
-            let new_raw = T::as_ptr(&new);
-            if storage
-                .compare_exchange_weak(current.as_raw(), new_raw, SeqCst, Relaxed)
-                .is_ok()
-            {
-                // We successfully put the new value in. The ref count went in there too.
-                T::into_ptr(new);
-                <Self as InnerStrategy<T>>::wait_for_readers(self, old.as_ptr(), storage);
-                // We just got one ref count out of the storage and we have one in old. We don't
-                // need two.
-                T::dec(old.as_ptr());
-                return old;
-            }
+        weak: bool,
+    ) -> Result<Self::Protected, (Self::Protected, T)> {
+        compare_exchange(self, storage, current, new, weak)
+    }
+}
+
+/// Pays the debts on the `old` word, in the given domain.
+///
+/// Shared by the strategies based on debts.
+pub(super) unsafe fn wait_for_readers<T, S>(
+    strategy: &S,
+    domain: &Domain,
+    old: *const (),
+    storage: &AtomicPtr<()>,
+) where
+    T: RefCnt,
+    S: InnerStrategy<T, Protected = HybridProtection<T>>,
+{
+    // The pay_all may need to provide fresh replacement values if someone else is loading from
+    // this particular storage. We do so by the exact same way, by `load` ‒ it's OK, a writer does
+    // not hold a slot and the reader doesn't recurse back into writer, so we won't run out of
+    // slots.
+    let replacement = || strategy.load(storage).into_word();
+    Debt::pay_all::<T, _>(domain, old, storage as *const _ as usize, replacement);
+}
+
+/// The compare and swap of the strategies based on debts.
+pub(super) unsafe fn compare_exchange<T, S, C>(
+    strategy: &S,
+    storage: &AtomicPtr<()>,
+    current: C,
+    new: T,
+    weak: bool,
+) -> Result<HybridProtection<T>, (HybridProtection<T>, T)>
+where
+    T: RefCnt,
+    S: InnerStrategy<T, Protected = HybridProtection<T>>,
+    C: AsRaw<T::Base>,
+{
+    let new = thin::into_word(new);
+    loop {
+        let old = strategy.load(storage);
+        // Observation of their inequality is enough to make a verdict
+        if thin::addr(T::as_ptr(&old.ptr)) != thin::addr(current.as_raw()) {
+            return Err((old, thin::from_word(new)));
+        }
+        // If they are still equal, put the new one in.
+        let swapped = if weak {
+            storage.compare_exchange_weak(old.word as *mut (), new, SeqCst, Relaxed)
+        } else {
+            storage.compare_exchange(old.word as *mut (), new, SeqCst, Relaxed)
+        };
+        if swapped.is_ok() {
+            // We successfully put the new value in. The ref count went in there too.
+            strategy.wait_for_readers(old.word, storage);
+            // We just got one ref count out of the storage and we have one in old. We don't
+            // need two.
+            thin::dec::<T>(old.word);
+            return Ok(old);
+        } else if weak {
+            // Either spurious failure or someone changed it in between, we don't retry.
+            return Err((old, thin::from_word(new)));
         }
     }
 }
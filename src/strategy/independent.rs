@@ -0,0 +1,60 @@
+//! A strategy with its own debt domain.
+//!
+//! It works the same way as the [hybrid][super::hybrid] one, but the debts live in a private list
+//! of nodes owned by the strategy (and therefore by the single instance). Each thread keeps a node
+//! for a few recently used instances, other operations claim a node just for their own duration.
+
+use super::hybrid::{self, HybridProtection};
+use super::sealed::{CaS, InnerStrategy};
+use crate::as_raw::AsRaw;
+use crate::debt::{Domain, LocalNode, DEBT_SLOT_CNT};
+use crate::ref_cnt::RefCnt;
+use crate::sync::AtomicPtr;
+
+/// Strategy for isolating instances.
+///
+/// It is similar to [`DefaultStrategy`][super::DefaultStrategy], but each instance owns its own
+/// set of debt slots instead of sharing the global ones with every other instance in the process
+/// (including these used by other libraries). Therefore:
+///
+/// * Writers check only the readers of this instance, so the cost of a write doesn't grow with
+///   the number of threads reading unrelated instances.
+/// * Holding a lot of [`Guard`][crate::Guard]s of other instances doesn't slow down loads of this
+///   one.
+///
+/// On the other hand, a thread keeps a node only for the few instances it used most recently.
+/// Loads of more instances, interleaved on the same thread, are slower, as they need to claim a
+/// node in the private list each time. The instance is also bigger in memory and the memory of the
+/// slots is not returned once it is dropped (it is reused by other independent instances).
+///
+/// The purpose of this strategy is meant for cases where a single instance is going to be
+/// "tortured" a lot, so it should not overflow to other instances, or for libraries that want to
+/// be isolated from whatever else uses arc-swap in the same process.
+#[derive(Default)]
+pub struct IndependentStrategy {
+    domain: Domain,
+}
+
+impl<T: RefCnt> InnerStrategy<T> for IndependentStrategy {
+    type Protected = HybridProtection<T>;
+    unsafe fn load(&self, storage: &AtomicPtr<()>) -> Self::Protected {
+        LocalNode::with_domain(&self.domain, |node| {
+            HybridProtection::load(node, &self.domain, storage, DEBT_SLOT_CNT)
+        })
+    }
+    unsafe fn wait_for_readers(&self, old: *const (), storage: &AtomicPtr<()>) {
+        hybrid::wait_for_readers::<T, _>(self, &self.domain, old, storage);
+    }
+}
+
+impl<T: RefCnt> CaS<T> for IndependentStrategy {
+    unsafe fn compare_exchange<C: AsRaw<T::Base>>(
+        &self,
+        storage: &AtomicPtr<()>,
+        current: C,
+        new: T,
+        weak: bool,
+    ) -> Result<Self::Protected, (Self::Protected, T)> {
+        hybrid::compare_exchange(self, storage, current, new, weak)
+    }
+}
@@ -17,6 +17,9 @@
 //! Currently, we have these strategies:
 //!
 //! * [`DefaultStrategy`] (this one is used implicitly)
+//! * [`HybridStrategy`] with a custom [`Config`][hybrid::Config] (the
+//!   default one with tweaked parameters)
+//! * [`IndependentStrategy`], with its own debt slots for each instance
 //! * [`RwLock<()>`][std::sync::RwLock]
 //!
 //! # Testing
@@ -36,12 +39,14 @@
 //! [`ArcSwap`]: crate::ArcSwap
 //! [`load`]: crate::ArcSwapAny::load
 
-use std::borrow::Borrow;
-use std::sync::atomic::AtomicPtr;
+use core::borrow::Borrow;
 
 use crate::ref_cnt::RefCnt;
+use crate::sync::AtomicPtr;
 
-pub(crate) mod hybrid;
+pub mod hybrid;
+mod independent;
+#[cfg(any(feature = "std", not(feature = "no-std")))]
 mod rw_lock;
 // Do not use from outside of the crate.
 #[cfg(feature = "internal-test-strategies")]
@@ -68,7 +73,8 @@ use self::hybrid::{DefaultConfig, HybridStrategy};
 /// Each thread has a limited number of fast slots (currently 8, but the exact number is not
 /// guaranteed). If it holds at most that many [`Guard`]s at once, acquiring them is fast. Once
 /// these slots are used up (by holding to these many [`Guard`]s), acquiring more of them will be
-/// slightly slower, but still wait-free.
+/// slightly slower, but still wait-free. A [`Config`][hybrid::Config] with more slots can be used
+/// for the cases when more are needed.
 ///
 /// If you expect to hold a lot of "handles" to the data around, or hold onto it for a long time,
 /// you may want to prefer the [`load_full`][crate::ArcSwapAny::load_full] method.
@@ -92,49 +98,50 @@ use self::hybrid::{DefaultConfig, HybridStrategy};
 /// [`Guard`]: crate::Guard
 pub type DefaultStrategy = HybridStrategy<DefaultConfig>;
 
-/// Strategy for isolating instances.
-///
-/// It is similar to [`DefaultStrategy`], however the spin lock is not sharded (therefore multiple
-/// concurrent threads might get bigger hit when multiple threads have to fall back). Nevertheless,
-/// each instance has a private spin lock, not influencing the other instances. That also makes
-/// them bigger in memory.
-///
-/// The hazard pointers are still shared between all instances.
-///
-/// The purpose of this strategy is meant for cases where a single instance is going to be
-/// "tortured" a lot, so it should not overflow to other instances.
-///
-/// This too may be changed for something else (but with at least as good guarantees, primarily
-/// that other instances won't get influenced by the "torture").
-// Testing if the DefaultStrategy is good enough to replace it fully and then deprecate.
-#[doc(hidden)]
-pub type IndependentStrategy = DefaultStrategy;
+pub use self::independent::IndependentStrategy;
 
 // TODO: When we are ready to un-seal, should these traits become unsafe?
 
 pub(crate) mod sealed {
     use super::*;
     use crate::as_raw::AsRaw;
+    use crate::nodes::ReaderHandle;
+
+    // Note: the storage and the pointers are the words from the crate::thin module, not the raw
+    // pointers of T.
 
     pub trait Protected<T>: Borrow<T> {
         fn into_inner(self) -> T;
         fn from_inner(ptr: T) -> Self;
+        // The word this protects
+        fn word(&self) -> *const ();
     }
 
     pub trait InnerStrategy<T: RefCnt> {
         // Drop „unlocks“
         type Protected: Protected<T>;
-        unsafe fn load(&self, storage: &AtomicPtr<T::Base>) -> Self::Protected;
-        unsafe fn wait_for_readers(&self, old: *const T::Base, storage: &AtomicPtr<T::Base>);
+        unsafe fn load(&self, storage: &AtomicPtr<()>) -> Self::Protected;
+        // The strategies not using the per-thread nodes just ignore the handle.
+        unsafe fn load_with(
+            &self,
+            storage: &AtomicPtr<()>,
+            _handle: &ReaderHandle,
+        ) -> Self::Protected {
+            self.load(storage)
+        }
+        unsafe fn wait_for_readers(&self, old: *const (), storage: &AtomicPtr<()>);
     }
 
     pub trait CaS<T: RefCnt>: InnerStrategy<T> {
-        unsafe fn compare_and_swap<C: AsRaw<T::Base>>(
+        // Returns the previous value on success, the current value and the rejected new one on
+        // failure. The weak variant is allowed to fail spuriously.
+        unsafe fn compare_exchange<C: AsRaw<T::Base>>(
             &self,
-            storage: &AtomicPtr<T::Base>,
+            storage: &AtomicPtr<()>,
             current: C,
             new: T,
-        ) -> Self::Protected;
+            weak: bool,
+        ) -> Result<Self::Protected, (Self::Protected, T)>;
     }
 }
 
@@ -0,0 +1,105 @@
+//! The synchronization primitives used by the lock-free parts.
+//!
+//! Normally, these are the ones from `core`. When compiled with `--cfg loom`, they are replaced by
+//! the ones from [loom](https://docs.rs/loom), which explores all the possible interleavings of
+//! the threads (and orderings of the memory accesses) in the model tests. The API is the common
+//! subset of both, with few helper functions where they differ.
+
+#[cfg(not(loom))]
+pub(crate) use core::cell::UnsafeCell;
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+
+#[cfg(loom)]
+pub(crate) use loom::cell::UnsafeCell;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+
+/// Tells the CPU (or the loom scheduler) we are spinning in a loop, waiting for someone else.
+#[inline]
+pub(crate) fn spin_loop_hint() {
+    #[cfg(not(loom))]
+    core::hint::spin_loop();
+    #[cfg(loom)]
+    loom::thread::yield_now();
+}
+
+/// A `SeqCst` fence, but only under loom.
+///
+/// Loom models the `SeqCst` loads and stores as `AcqRel` only, so it would find races in the places
+/// where we rely on a store being ordered before a later load (of another variable). It models the
+/// fences fully, though. These places therefore have this fence, to let loom know what the real
+/// `SeqCst` operations already guarantee.
+#[inline]
+pub(crate) fn loom_fence() {
+    #[cfg(loom)]
+    loom::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+/// Reads the atomic through a mutable reference (no synchronization needed).
+#[inline]
+pub(crate) fn load_mut<T>(atomic: &mut AtomicPtr<T>) -> *mut T {
+    #[cfg(not(loom))]
+    {
+        *atomic.get_mut()
+    }
+    #[cfg(loom)]
+    {
+        atomic.with_mut(|ptr| *ptr)
+    }
+}
+
+/// Writes the atomic through a mutable reference (no synchronization needed).
+#[inline]
+pub(crate) fn store_mut<T>(atomic: &mut AtomicPtr<T>, val: *mut T) {
+    #[cfg(not(loom))]
+    {
+        *atomic.get_mut() = val;
+    }
+    #[cfg(loom)]
+    {
+        atomic.with_mut(|ptr| *ptr = val);
+    }
+}
+
+/// Access to the inside of an [`UnsafeCell`].
+///
+/// Loom needs to track each access, so it hands out the pointers wrapped. Without loom, this is
+/// just the raw pointer.
+#[cfg(not(loom))]
+pub(crate) type MutPtr<T> = *mut T;
+#[cfg(loom)]
+pub(crate) type MutPtr<T> = loom::cell::MutPtr<T>;
+
+/// Starts an access to the inside of the cell.
+///
+/// The caller must make sure nobody else accesses the cell while the returned pointer is in use.
+#[inline]
+pub(crate) fn cell_ptr<T>(cell: &UnsafeCell<T>) -> MutPtr<T> {
+    #[cfg(not(loom))]
+    {
+        cell.get()
+    }
+    #[cfg(loom)]
+    {
+        cell.get_mut()
+    }
+}
+
+/// Dereferences the pointer from [`cell_ptr`].
+///
+/// # Safety
+///
+/// As with dereferencing any raw pointer.
+#[inline]
+#[allow(clippy::mut_from_ref)]
+pub(crate) unsafe fn deref_cell<T>(ptr: &MutPtr<T>) -> &mut T {
+    #[cfg(not(loom))]
+    {
+        &mut **ptr
+    }
+    #[cfg(loom)]
+    {
+        ptr.deref()
+    }
+}
@@ -0,0 +1,461 @@
+//! Squeezing the (possibly fat) pointers into a single word.
+//!
+//! The storage, the debts and the strategies all work with a single machine word ‒ the
+//! [`AtomicPtr`][std::sync::atomic::AtomicPtr] can't hold anything bigger and there's no
+//! double-word atomic on stable Rust. Pointers to sized types are already a single word and are
+//! stored directly, so nothing changes for them (the decision is done on compile-time constants
+//! and optimized out).
+//!
+//! Pointers to unsized types (`str`, `[T]`, `dyn Trait`) are fat and the [`RefCnt::into_ptr`]
+//! keeps only their address, so they can't be stored directly. The same goes for any smart
+//! pointer larger than a word. We move the whole smart pointer into a small heap cell with its own
+//! reference count and store the pointer to the cell instead. The cell is *the* reference counted
+//! thing from the point of view of the rest of the crate ‒ the debts are on it, the writers pay by
+//! bumping its count, etc.
+//!
+//! The count of the cell mirrors the count of the smart pointer inside ‒ each reference of the
+//! cell owns one reference of the inner pointer too. Therefore taking the value out of the cell is
+//! just a bitwise copy and releasing the cell's reference, there's no need to clone the inner
+//! pointer (and the cell doesn't have to be the last reference for that).
+//!
+//! Some notes:
+//!
+//! * The identity of a value (eg. what [`compare_and_swap`][crate::ArcSwapAny::compare_and_swap]
+//!   compares) is still the address of the pointee, not the word. The same `Arc` may be stored in
+//!   multiple cells.
+//! * Reading the cell is allowed only while the word is protected (there's a debt on it or a
+//!   reference owned).
+//! * The null pointer (eg. `None` of an `Option`) is stored as the null word, without a cell.
+//! * The dead cells are kept in a small per-thread cache and reused by further stores, so the
+//!   writers don't allocate in the usual case.
+
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+use core::mem::{self, ManuallyDrop};
+use core::ptr;
+use core::sync::atomic::Ordering::*;
+use core::sync::atomic::{self, AtomicUsize};
+
+use crate::ref_cnt::RefCnt;
+
+/// The cell holding an unsized smart pointer.
+///
+/// The `val` is a single reference as far as the layout goes, but it stands for as many
+/// references as the `refs` says.
+struct Indirect<T> {
+    refs: AtomicUsize,
+    val: ManuallyDrop<T>,
+}
+
+/// Is the smart pointer a single word, so we can store it directly?
+#[inline]
+pub(crate) fn is_thin<T>() -> bool {
+    mem::size_of::<T>() == mem::size_of::<*const ()>()
+}
+
+/// The address part of a (possibly fat) pointer.
+#[inline]
+pub(crate) fn addr<B: ?Sized>(ptr: *const B) -> *const () {
+    ptr as *const ()
+}
+
+#[inline]
+fn to_base<T: RefCnt>(word: *const ()) -> *const T::Base {
+    debug_assert!(is_thin::<T>() || word.is_null());
+    word as *const T::Base
+}
+
+/// Converts the value into a word, moving its reference into it.
+#[inline]
+pub(crate) fn into_word<T: RefCnt>(val: T) -> *mut () {
+    if is_thin::<T>() {
+        T::into_ptr(val) as *mut ()
+    } else if T::as_ptr(&val).is_null() {
+        // There's nothing to reference count, the from_ptr reconstructs it.
+        drop(val);
+        ptr::null_mut()
+    } else {
+        spare::alloc(Indirect {
+            refs: AtomicUsize::new(1),
+            val: ManuallyDrop::new(val),
+        }) as *mut ()
+    }
+}
+
+/// Converts a word back into a value, consuming one reference of the word.
+///
+/// # Safety
+///
+/// The word must come from [`into_word`] and the caller must own one reference of it.
+#[inline]
+pub(crate) unsafe fn from_word<T: RefCnt>(word: *const ()) -> T {
+    if is_thin::<T>() || word.is_null() {
+        T::from_ptr(to_base::<T>(word))
+    } else {
+        let cell = word as *mut Indirect<T>;
+        // We own one of the inner references, take it and leave the others in the cell. It must be
+        // read before releasing the cell, someone else may free it right after.
+        let val = ptr::read(&*(*cell).val);
+        release::<T>(cell);
+        val
+    }
+}
+
+/// Provides a temporary copy of the value behind the word, without any reference.
+///
+/// # Safety
+///
+/// The word must be protected for the whole lifetime of the result and it must never be dropped.
+#[inline]
+pub(crate) unsafe fn view<T: RefCnt>(word: *const ()) -> ManuallyDrop<T> {
+    if is_thin::<T>() || word.is_null() {
+        ManuallyDrop::new(T::from_ptr(to_base::<T>(word)))
+    } else {
+        let cell = word as *const Indirect<T>;
+        ManuallyDrop::new(ptr::read(&*(*cell).val))
+    }
+}
+
+/// The identity of the value behind the word, comparable to [`addr`] of [`RefCnt::as_ptr`].
+///
+/// # Safety
+///
+/// The word must be protected.
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+#[inline]
+pub(crate) unsafe fn identity<T: RefCnt>(word: *const ()) -> *const () {
+    if is_thin::<T>() || word.is_null() {
+        word
+    } else {
+        let cell = word as *const Indirect<T>;
+        addr(T::as_ptr(&(*cell).val))
+    }
+}
+
+/// Increments the reference count of the word.
+///
+/// # Safety
+///
+/// The word must be protected.
+#[inline]
+pub(crate) unsafe fn inc<T: RefCnt>(word: *const ()) {
+    if is_thin::<T>() {
+        T::inc(&view::<T>(word));
+    } else if !word.is_null() {
+        let cell = word as *const Indirect<T>;
+        // Relaxed is enough, just like with Arc ‒ we already have a protected word, so the count
+        // can't drop to 0 in the meantime.
+        (*cell).refs.fetch_add(1, Relaxed);
+        // Keep the inner count in sync.
+        mem::forget(T::clone(&(*cell).val));
+    }
+}
+
+/// Decrements the reference count of the word, possibly destroying the value.
+///
+/// # Safety
+///
+/// The caller must own one reference of the word.
+#[inline]
+pub(crate) unsafe fn dec<T: RefCnt>(word: *const ()) {
+    if is_thin::<T>() {
+        T::dec(to_base::<T>(word));
+    } else if !word.is_null() {
+        drop(from_word::<T>(word));
+    }
+}
+
+/// Releases one reference of the cell, freeing it if it was the last one.
+///
+/// The inner reference that belonged to it must have been already taken out.
+#[inline]
+unsafe fn release<T>(cell: *mut Indirect<T>) {
+    // The same dance as in Arc. Release our changes to whoever is the last one and the last one
+    // acquires all of them.
+    if (*cell).refs.fetch_sub(1, Release) == 1 {
+        atomic::fence(Acquire);
+        spare::free(cell);
+    }
+}
+
+/// Reuse of the dead cells.
+///
+/// Most of the fat pointers are two words, so most of the cells have the same layout and can be
+/// reused for any type. A few of them are kept per thread, the rest (and all the cells of unusual
+/// layout) go back to the allocator.
+#[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+mod spare {
+    use alloc::boxed::Box;
+    use core::alloc::Layout;
+    use core::cell::Cell;
+    use core::ptr;
+
+    use super::Indirect;
+
+    /// The layout of the cells we keep.
+    type Spare = Indirect<[usize; 2]>;
+
+    /// How many cells a thread keeps around.
+    const CAPACITY: usize = 8;
+
+    struct Spares {
+        cells: [Cell<*mut Spare>; CAPACITY],
+        len: Cell<usize>,
+    }
+
+    impl Drop for Spares {
+        fn drop(&mut self) {
+            for cell in &self.cells[..self.len.get()] {
+                // The cells are dead, the values are already gone. The ManuallyDrop doesn't drop
+                // the garbage in there.
+                drop(unsafe { Box::from_raw(cell.get()) });
+            }
+        }
+    }
+
+    // Only to initialize the array below, each use is a new Cell.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Cell<*mut Spare> = Cell::new(ptr::null_mut());
+
+    std::thread_local! {
+        static SPARES: Spares = const {
+            Spares {
+                cells: [EMPTY; CAPACITY],
+                len: Cell::new(0),
+            }
+        };
+    }
+
+    #[inline]
+    fn reusable<T>() -> bool {
+        Layout::new::<Indirect<T>>() == Layout::new::<Spare>()
+    }
+
+    /// Puts the cell onto the heap, reusing a dead one if possible.
+    #[inline]
+    pub(super) fn alloc<T>(cell: Indirect<T>) -> *mut Indirect<T> {
+        let spare = if reusable::<T>() {
+            SPARES
+                .try_with(|spares| {
+                    let len = spares.len.get();
+                    if len == 0 {
+                        None
+                    } else {
+                        spares.len.set(len - 1);
+                        Some(spares.cells[len - 1].get())
+                    }
+                })
+                .ok()
+                .flatten()
+        } else {
+            None
+        };
+        match spare {
+            Some(spare) => {
+                let spare = spare as *mut Indirect<T>;
+                // The same layout, so it fits.
+                unsafe { ptr::write(spare, cell) };
+                spare
+            }
+            None => Box::into_raw(Box::new(cell)),
+        }
+    }
+
+    /// Gets rid of a dead cell.
+    ///
+    /// # Safety
+    ///
+    /// The cell must come from [`alloc`] and nobody may access it any more. The value inside is
+    /// not dropped.
+    #[inline]
+    pub(super) unsafe fn free<T>(cell: *mut Indirect<T>) {
+        let kept = reusable::<T>()
+            && SPARES
+                .try_with(|spares| {
+                    let len = spares.len.get();
+                    if len == CAPACITY {
+                        false
+                    } else {
+                        spares.cells[len].set(cell as *mut Spare);
+                        spares.len.set(len + 1);
+                        true
+                    }
+                })
+                .unwrap_or(false);
+        if !kept {
+            drop(Box::from_raw(cell));
+        }
+    }
+}
+
+/// Without the thread locals, the cells always go to the allocator.
+#[cfg(not(all(any(feature = "std", not(feature = "no-std")), not(loom))))]
+mod spare {
+    use alloc::boxed::Box;
+
+    use super::Indirect;
+
+    #[inline]
+    pub(super) fn alloc<T>(cell: Indirect<T>) -> *mut Indirect<T> {
+        Box::into_raw(Box::new(cell))
+    }
+
+    #[inline]
+    pub(super) unsafe fn free<T>(cell: *mut Indirect<T>) {
+        drop(Box::from_raw(cell));
+    }
+}
+
+/// Marks a private cell holding the null value in an [`Owned`].
+const NULL_CELL: usize = 1;
+
+union Slot<T> {
+    val: ManuallyDrop<T>,
+    word: *const (),
+}
+
+/// An owned value, remembering the word it was stored as.
+///
+/// For the thin pointers, this is just the value (the word is the pointer itself). For the
+/// others, this is an owned reference of the cell and the value is accessed in there, so it is a
+/// single word too. The null word has no cell, so a private one is made to hold the value and
+/// marked in the lowest bit.
+pub(crate) struct Owned<T> {
+    slot: Slot<T>,
+}
+
+impl<T: RefCnt> Owned<T> {
+    /// Takes over a reference of the word.
+    ///
+    /// # Safety
+    ///
+    /// The caller must own one reference of the word.
+    pub(crate) unsafe fn from_word(word: *const ()) -> Self {
+        let slot = if is_thin::<T>() {
+            Slot {
+                val: ManuallyDrop::new(from_word(word)),
+            }
+        } else if word.is_null() {
+            Slot {
+                word: null_cell(T::from_ptr(to_base::<T>(word))),
+            }
+        } else {
+            Slot { word }
+        };
+        Owned { slot }
+    }
+
+    /// The word the value was stored as.
+    pub(crate) fn word(&self) -> *const () {
+        if is_thin::<T>() {
+            T::as_ptr(self.get()) as *const ()
+        } else {
+            let word = unsafe { self.slot.word };
+            if word as usize & NULL_CELL == 0 {
+                word
+            } else {
+                ptr::null()
+            }
+        }
+    }
+}
+
+impl<T> Owned<T> {
+    fn cell(&self) -> *mut Indirect<T> {
+        (unsafe { self.slot.word } as usize & !NULL_CELL) as *mut Indirect<T>
+    }
+
+    /// The value itself.
+    pub(crate) fn get(&self) -> &T {
+        if is_thin::<T>() {
+            unsafe { &self.slot.val }
+        } else {
+            // We own a reference of the cell, so it is alive.
+            unsafe { &(*self.cell()).val }
+        }
+    }
+}
+
+/// Puts the null value into a private cell, marked as such.
+fn null_cell<T>(val: T) -> *const () {
+    let cell = spare::alloc(Indirect {
+        refs: AtomicUsize::new(1),
+        val: ManuallyDrop::new(val),
+    });
+    (cell as usize | NULL_CELL) as *const ()
+}
+
+impl<T: Clone> Clone for Owned<T> {
+    fn clone(&self) -> Self {
+        let slot = if is_thin::<T>() {
+            Slot {
+                val: ManuallyDrop::new(T::clone(self.get())),
+            }
+        } else if unsafe { self.slot.word } as usize & NULL_CELL == 0 {
+            // The same as inc, we hold a reference so the count can't drop to 0.
+            unsafe { (*self.cell()).refs.fetch_add(1, Relaxed) };
+            mem::forget(T::clone(self.get()));
+            Slot {
+                word: unsafe { self.slot.word },
+            }
+        } else {
+            Slot {
+                word: null_cell(T::clone(self.get())),
+            }
+        };
+        Owned { slot }
+    }
+}
+
+impl<T> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if is_thin::<T>() {
+            unsafe { ManuallyDrop::drop(&mut self.slot.val) };
+        } else {
+            let cell = self.cell();
+            unsafe {
+                let val = ptr::read(&*(*cell).val);
+                release(cell);
+                drop(val);
+            }
+        }
+    }
+}
+
+impl<T: Debug> Debug for Owned<T> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        self.get().fmt(fmt)
+    }
+}
+
+// It is a T (or a shared reference of it in the cell) for all practical purposes.
+unsafe impl<T: Send> Send for Owned<T> {}
+unsafe impl<T: Sync> Sync for Owned<T> {}
+
+#[cfg(all(test, any(feature = "std", not(feature = "no-std")), not(loom)))]
+mod tests {
+    use alloc::sync::Arc;
+
+    use super::*;
+
+    /// Dead cells are reused for other values of the same layout and taking the value out doesn't
+    /// touch the inner count.
+    #[test]
+    fn cells_reused() {
+        let a: Arc<str> = Arc::from("hello");
+        let word = into_word(Arc::clone(&a));
+        unsafe { dec::<Arc<str>>(word) };
+        assert_eq!(1, Arc::strong_count(&a));
+
+        let b: Arc<[u8]> = Arc::from(vec![1, 2]);
+        let reused = into_word(Arc::clone(&b));
+        assert_eq!(word, reused);
+        unsafe { inc::<Arc<[u8]>>(reused) };
+        assert_eq!(3, Arc::strong_count(&b));
+        let taken = unsafe { from_word::<Arc<[u8]>>(reused) };
+        assert_eq!(3, Arc::strong_count(&b));
+        assert!(Arc::ptr_eq(&b, &taken));
+        drop(taken);
+        unsafe { dec::<Arc<[u8]>>(reused) };
+        assert_eq!(1, Arc::strong_count(&b));
+    }
+}
@@ -0,0 +1,104 @@
+//! Explicit control of the current thread's node.
+//!
+//! A thread gets its node (see the [`nodes`][crate::nodes] module) lazily, on the first load, and
+//! gives it up when it terminates. That may be inconvenient for thread pools:
+//!
+//! * The first load of a new worker may need to allocate the node.
+//! * A worker that sleeps for a long time keeps its node, so it can't be reused by another thread
+//!   and the writers still need to check it.
+//!
+//! The [`register`] and [`unregister`] allow doing it at well defined times, eg. when the worker
+//! starts and when it parks.
+//!
+//! These work with the node of the current thread, wherever it is kept (the thread local variable
+//! or a [`NodeProvider`][crate::nodes::NodeProvider]). Loading through a
+//! [`ReaderHandle`][crate::nodes::ReaderHandle] is not affected.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::sync::mpsc;
+//! use std::thread;
+//!
+//! use arc_swap::ArcSwap;
+//!
+//! let config = ArcSwap::from_pointee(42);
+//! let (sender, receiver) = mpsc::channel::<usize>();
+//!
+//! thread::scope(|s| {
+//!     s.spawn(|| {
+//!         arc_swap::thread::register();
+//!         for request in receiver {
+//!             assert_eq!(request, **config.load());
+//!         }
+//!         // Nothing more to do for now.
+//!         arc_swap::thread::unregister();
+//!     });
+//!     sender.send(42).unwrap();
+//!     drop(sender);
+//! });
+//! ```
+
+use crate::debt::LocalNode;
+
+/// Claims a node for the current thread, if it doesn't have one yet.
+///
+/// Returns `true` if the thread has a node now. It doesn't get one if the
+/// [limit][crate::nodes::set_limit] is reached or if there's no place to keep it (in the `no-std`
+/// mode without a [`NodeProvider`][crate::nodes::NodeProvider], or during the thread's shutdown).
+///
+/// With the `metrics` feature, this also creates the [counters][crate::metrics] of the thread, so
+/// the first load doesn't have to.
+pub fn register() -> bool {
+    #[cfg(feature = "metrics")]
+    crate::metrics::register();
+    LocalNode::register_thread()
+}
+
+/// Gives up the node of the current thread.
+///
+/// The node becomes available for other threads (after a short cooldown). The [`Guard`]s loaded
+/// by this thread stay valid. If the thread loads again, it gets a node again (like after
+/// [`register`]).
+///
+/// This doesn't free any unused nodes (neither does the termination of the thread), the writers do
+/// that later on. Use [`nodes::reclaim`][crate::nodes::reclaim] to free them right away.
+///
+/// [`Guard`]: crate::Guard
+pub fn unregister() {
+    LocalNode::unregister_thread()
+}
+
+#[cfg(all(test, any(feature = "std", not(feature = "no-std"))))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::diagnostics;
+    use crate::ArcSwap;
+
+    #[test]
+    fn register_unregister() {
+        std::thread::spawn(|| {
+            let shared = ArcSwap::from_pointee(1);
+            assert!(!diagnostics::thread_has_node());
+            assert!(register());
+            assert!(diagnostics::thread_has_node());
+            // Doesn't claim another one
+            assert!(register());
+
+            let guard = shared.load();
+            unregister();
+            assert!(!diagnostics::thread_has_node());
+            shared.store(Arc::new(2));
+            assert_eq!(1, **guard);
+            drop(guard);
+
+            // Gets a new one on a load
+            assert_eq!(2, **shared.load());
+            assert!(diagnostics::thread_has_node());
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+//! Updating several instances at once.
+//!
+//! Sometimes multiple [`ArcSwapAny`]s hold related data (eg. a routing table and its reverse
+//! index) and need to be replaced together. The [`Transaction`] collects the updates of several
+//! instances (possibly of different types) and then applies either all of them or none.
+//!
+//! # Consistency
+//!
+//! The individual instances are still changed one by one. Therefore, readers loading them one by
+//! one (with the plain [`load`][ArcSwapAny::load]) may still see a mix of old and new values,
+//! including values of a transaction that fails and gets rolled back. Only readers that use
+//! [`load_consistent`][crate::snapshot::load_consistent] are guaranteed to see either all the old
+//! or all the new values (these retry their loads if a transaction overlaps with them).
+//!
+//! Transactions sharing an instance are serialized with each other (each instance has a lock,
+//! though unrelated instances may share one). The [`commit`][Transaction::commit] first checks
+//! all the expected values and only if all of them match it replaces the values. Plain writes
+//! (eg. [`store`][ArcSwapAny::store]) to instances that are part of a transaction at the same
+//! time are not prevented. Such a write between the check and the replacement makes the
+//! transaction roll back the values it already replaced, or may even make that rollback fail. If
+//! all-or-nothing is important, all the writes to these instances should go through
+//! transactions.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use std::collections::HashMap;
+//! # use std::sync::Arc;
+//! use arc_swap::ArcSwap;
+//! use arc_swap::transaction::Transaction;
+//!
+//! let routes = ArcSwap::from_pointee(vec![(1, "a")]);
+//! let reverse = ArcSwap::from_pointee(HashMap::from([("a", 1)]));
+//!
+//! let old_routes = routes.load();
+//! let mut new_routes = Vec::clone(&old_routes);
+//! new_routes.push((2, "b"));
+//! let mut new_reverse = HashMap::clone(&reverse.load());
+//! new_reverse.insert("b", 2);
+//!
+//! let mut transaction = Transaction::new();
+//! transaction
+//!     .compare_and_swap(&routes, &old_routes, Arc::new(new_routes))
+//!     .swap(&reverse, Arc::new(new_reverse));
+//! transaction.commit().expect("Nobody else changed the routes");
+//! # drop(old_routes);
+//!
+//! assert_eq!(2, routes.load().len());
+//! assert_eq!(2, reverse.load().len());
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use core::mem;
+use core::ptr;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering::*;
+
+use crate::seq::Writing;
+use crate::strategy::CaS;
+use crate::{ArcSwapAny, Guard, RefCnt};
+
+const LOCK_CNT: usize = 32;
+
+// The lock is held while the values are replaced and the readers of the old ones are waited for.
+// That may take a while, so with std the waiting transactions sleep instead of spinning.
+#[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+type Lock = std::sync::Mutex<()>;
+#[cfg(not(all(any(feature = "std", not(feature = "no-std")), not(loom))))]
+type Lock = crate::spin::Mutex<()>;
+
+#[cfg(all(any(feature = "std", not(feature = "no-std")), not(loom)))]
+fn lock(lock: &Lock) -> impl Drop + '_ {
+    // Nothing is protected by the lock itself, a panic while holding it doesn't break anything.
+    lock.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(not(all(any(feature = "std", not(feature = "no-std")), not(loom))))]
+fn lock(lock: &Lock) -> impl Drop + '_ {
+    lock.lock()
+}
+
+/// Serializes the transactions, sharded by the address of the instances.
+#[derive(Default)]
+struct Locks([Lock; LOCK_CNT]);
+
+impl Locks {
+    fn index(addr: usize) -> usize {
+        // Instances next to each other (eg. in the same struct) should get different locks.
+        (addr / mem::size_of::<usize>()) % LOCK_CNT
+    }
+}
+
+/// The lazily-created locks.
+static LOCKS: AtomicPtr<Locks> = AtomicPtr::new(ptr::null_mut());
+
+fn locks() -> &'static Locks {
+    let current = LOCKS.load(Acquire);
+    if let Some(locks) = unsafe { current.as_ref() } {
+        return locks;
+    }
+    let new = Box::into_raw(Box::<Locks>::default());
+    match LOCKS.compare_exchange(ptr::null_mut(), new, AcqRel, Acquire) {
+        // It's leaked on purpose, it lives for the rest of the program.
+        Ok(_) => unsafe { &*new },
+        Err(other) => {
+            // Someone was faster, use theirs.
+            drop(unsafe { Box::from_raw(new) });
+            unsafe { &*other }
+        }
+    }
+}
+
+/// Unlocks the transactions, after a `fork`.
+///
+/// A vanished thread might have held some of the locks, so new ones are used (the old ones are
+/// leaked).
+#[cfg(unix)]
+pub(crate) fn after_fork_child() {
+    LOCKS.store(ptr::null_mut(), SeqCst);
+}
+
+/// One update of one instance.
+trait Operation {
+    /// Address of the storage.
+    fn addr(&self) -> usize;
+    /// Checks the instance holds the expected value (if any).
+    fn check(&self) -> bool;
+    /// Puts the new value in, or returns false if the instance doesn't hold the expected value.
+    fn apply(&mut self) -> bool;
+    /// Puts the previous value back.
+    fn rollback(&mut self);
+}
+
+struct Update<'a, T: RefCnt, S: CaS<T>> {
+    storage: &'a ArcSwapAny<T, S>,
+    /// The expected current value, if any.
+    ///
+    /// We hold onto it, so its address can't be reused by some other value in the meantime.
+    expected: Option<T>,
+    /// The value to put in.
+    new: Option<T>,
+    /// Copy of the new value after it went in, to be able to roll back.
+    installed: Option<T>,
+    /// The previous value after it was replaced.
+    old: Option<T>,
+}
+
+impl<T: RefCnt, S: CaS<T>> Operation for Update<'_, T, S> {
+    fn addr(&self) -> usize {
+        self.storage.addr()
+    }
+
+    fn check(&self) -> bool {
+        match self.expected {
+            None => true,
+            Some(ref expected) => crate::ptr_eq(&*self.storage.load(), expected),
+        }
+    }
+
+    fn apply(&mut self) -> bool {
+        let new = self.new.take().expect("Applied twice");
+        let installed = T::clone(&new);
+        let old = match self.expected {
+            None => self.storage.swap(new),
+            Some(ref expected) => match self.storage.compare_exchange(expected, new) {
+                Ok(old) => Guard::into_inner(old),
+                Err((_, new)) => {
+                    self.new = Some(new);
+                    return false;
+                }
+            },
+        };
+        self.installed = Some(installed);
+        self.old = Some(old);
+        true
+    }
+
+    fn rollback(&mut self) {
+        let installed = self.installed.take().expect("Rolled back without applying");
+        let old = self.old.take().expect("Rolled back without applying");
+        // If someone else wrote in the meantime, we leave their value in place.
+        let _ = self.storage.compare_exchange(&installed, old);
+    }
+}
+
+/// The transaction could not be applied, because one of the instances didn't hold the expected
+/// value.
+///
+/// Usually, the mismatch is found before anything is replaced and the instances are left
+/// untouched. Only if a plain write changes an instance between the check and the replacement,
+/// the updates before the failed one are rolled back, each to the value it replaced. Other
+/// transactions and readers using [`load_consistent`][crate::snapshot::load_consistent] can't
+/// observe the rolled back values. Plain [`load`][ArcSwapAny::load]s might have seen them in the
+/// meantime. If a plain write replaced one of the values in the meantime, that instance is not
+/// rolled back and keeps the value of that write.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Conflict {
+    index: usize,
+}
+
+impl Conflict {
+    /// The index of the update (in the order they were added) that failed.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl Display for Conflict {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        write!(
+            formatter,
+            "Transaction update #{} doesn't match the current value",
+            self.index
+        )
+    }
+}
+
+#[cfg(any(feature = "std", not(feature = "no-std")))]
+impl std::error::Error for Conflict {}
+
+/// A set of updates to several instances, applied all at once.
+///
+/// See the [module documentation](self).
+#[derive(Default)]
+pub struct Transaction<'a> {
+    updates: Vec<Box<dyn Operation + 'a>>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Creates an empty transaction.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an unconditional replacement of the value.
+    pub fn swap<T, S>(&mut self, storage: &'a ArcSwapAny<T, S>, new: T) -> &mut Self
+    where
+        T: RefCnt + 'a,
+        S: CaS<T> + 'a,
+    {
+        self.push(storage, None, new)
+    }
+
+    /// Adds a replacement of the value, conditioned on it being `current`.
+    ///
+    /// If the instance holds something else at the time of the [`commit`][Transaction::commit],
+    /// the whole transaction fails. As with
+    /// [`ArcSwapAny::compare_and_swap`], only the pointers are compared. The transaction keeps a
+    /// copy of `current` until it is done, so the comparison can't be fooled by another value
+    /// allocated at the same address in the meantime.
+    pub fn compare_and_swap<T, S>(
+        &mut self,
+        storage: &'a ArcSwapAny<T, S>,
+        current: &T,
+        new: T,
+    ) -> &mut Self
+    where
+        T: RefCnt + 'a,
+        S: CaS<T> + 'a,
+    {
+        self.push(storage, Some(T::clone(current)), new)
+    }
+
+    fn push<T, S>(
+        &mut self,
+        storage: &'a ArcSwapAny<T, S>,
+        expected: Option<T>,
+        new: T,
+    ) -> &mut Self
+    where
+        T: RefCnt + 'a,
+        S: CaS<T> + 'a,
+    {
+        self.updates.push(Box::new(Update {
+            storage,
+            expected,
+            new: Some(new),
+            installed: None,
+            old: None,
+        }));
+        self
+    }
+
+    /// Applies all the updates, or none of them.
+    ///
+    /// The previous values are released after all the updates are done.
+    ///
+    /// # Panics
+    ///
+    /// If the same instance is present multiple times in the transaction.
+    pub fn commit(mut self) -> Result<(), Conflict> {
+        let mut addrs = self
+            .updates
+            .iter()
+            .map(|update| update.addr())
+            .collect::<Vec<_>>();
+        addrs.sort_unstable();
+        let len = addrs.len();
+        addrs.dedup();
+        assert_eq!(len, addrs.len(), "Instance present in transaction twice");
+
+        let locks = locks();
+        let mut indices = addrs
+            .iter()
+            .map(|addr| Locks::index(*addr))
+            .collect::<Vec<_>>();
+        indices.sort_unstable();
+        indices.dedup();
+        // Always locked in the same order, so two transactions can't wait for each other.
+        let _locked = indices
+            .iter()
+            .map(|index| lock(&locks.0[*index]))
+            .collect::<Vec<_>>();
+        if let Some(index) = self.updates.iter().position(|update| !update.check()) {
+            return Err(Conflict { index });
+        }
+        // Make the consistent readers wait for the whole transaction, including a possible
+        // rollback.
+        let _writing = addrs
+            .iter()
+            .map(|addr| Writing::begin(*addr))
+            .collect::<Vec<_>>();
+        for index in 0..self.updates.len() {
+            if !self.updates[index].apply() {
+                for update in self.updates[..index].iter_mut().rev() {
+                    update.rollback();
+                }
+                return Err(Conflict { index });
+            }
+        }
+        Ok(())
+        // The old values get dropped with self, after we are done
+    }
+}
+
+impl Debug for Transaction<'_> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_struct("Transaction")
+            .field("updates", &self.updates.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::snapshot::load_consistent;
+    use crate::{ArcSwap, ArcSwapOption};
+
+    #[test]
+    fn all_or_nothing() {
+        let a = ArcSwap::from_pointee(0);
+        let b = ArcSwapOption::<String>::empty();
+        let orig_a = a.load_full();
+        let other = Some(Arc::new("other".to_owned()));
+
+        let mut transaction = Transaction::new();
+        transaction
+            .swap(&a, Arc::new(1))
+            .compare_and_swap(&b, &other, None);
+        assert_eq!(1, transaction.commit().unwrap_err().index());
+        assert!(Arc::ptr_eq(&orig_a, &a.load()));
+        assert!(b.load().is_none());
+
+        let mut transaction = Transaction::new();
+        transaction
+            .compare_and_swap(&a, &orig_a, Arc::new(2))
+            .compare_and_swap(&b, &None::<Arc<String>>, Some(Arc::new("x".to_owned())));
+        transaction.commit().unwrap();
+        assert_eq!(2, **a.load());
+        assert_eq!("x", **b.load().as_ref().unwrap());
+        // Only orig_a now, the transaction released the other one.
+        assert_eq!(1, Arc::strong_count(&orig_a));
+    }
+
+    /// A mismatch is found before anything is written.
+    #[test]
+    #[cfg(any(feature = "std", not(feature = "no-std")))]
+    fn checked_first() {
+        let a = Arc::new(ArcSwap::from_pointee(0));
+        let b = ArcSwap::from_pointee(0);
+        let writes = Arc::new(AtomicUsize::new(0));
+        let writes_cp = Arc::clone(&writes);
+        a.observe(move |_, _| {
+            writes_cp.fetch_add(1, Ordering::Relaxed);
+        });
+        let other = Arc::new(0);
+        let mut transaction = Transaction::new();
+        transaction
+            .swap(&a, Arc::new(1))
+            .compare_and_swap(&b, &other, Arc::new(1));
+        assert_eq!(1, transaction.commit().unwrap_err().index());
+        assert_eq!(0, writes.load(Ordering::Relaxed));
+        assert_eq!(0, **a.load());
+    }
+
+    /// The expected value is held, so a new value at the same address can't match it.
+    #[test]
+    fn no_aba() {
+        let a = ArcSwap::from_pointee(0);
+        let mut transaction = Transaction::new();
+        transaction.compare_and_swap(&a, &a.load_full(), Arc::new(1));
+        // The original is freed here if the transaction doesn't keep it.
+        a.store(Arc::new(2));
+        a.store(Arc::new(3));
+        assert_eq!(0, transaction.commit().unwrap_err().index());
+        assert_eq!(3, **a.load());
+    }
+
+    #[test]
+    #[should_panic(expected = "twice")]
+    fn duplicate() {
+        let a = ArcSwap::from_pointee(0);
+        let mut transaction = Transaction::new();
+        transaction.swap(&a, Arc::new(1)).swap(&a, Arc::new(2));
+        let _ = transaction.commit();
+    }
+
+    /// Readers doing consistent loads never see a mix.
+    #[test]
+    fn no_mix() {
+        const ITERATIONS: usize = 500;
+        let a = ArcSwap::from_pointee(0);
+        let b = ArcSwap::from_pointee(0);
+        crossbeam_utils::thread::scope(|scope| {
+            scope.spawn(|_| {
+                for i in 1..=ITERATIONS {
+                    let mut transaction = Transaction::new();
+                    transaction.swap(&a, Arc::new(i)).swap(&b, Arc::new(i));
+                    transaction.commit().unwrap();
+                }
+            });
+            for _ in 0..2 {
+                scope.spawn(|_| loop {
+                    let [x, y] = load_consistent(&[&a, &b]);
+                    assert_eq!(**x, **y);
+                    if **x == ITERATIONS {
+                        break;
+                    }
+                });
+            }
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! Support for the smart pointers from the [`triomphe`] crate.
+//!
+//! The [`triomphe::Arc`] has no weak count, and the [`ThinArc`] is a single word even though it
+//! points to a slice (so it doesn't need the cell of unsized values, see
+//! [`limitations`][crate::docs::limitations]).
+//!
+//! Note that `triomphe` has its own `arc-swap` feature providing the same. Only one of them can be
+//! enabled at a time.
+
+use core::ffi::c_void;
+use core::ops::Deref;
+
+use triomphe::{Arc, ThinArc};
+
+use crate::access::{Access, DirectDeref};
+use crate::{ArcSwapAny, RefCnt, Strategy};
+
+unsafe impl<T> RefCnt for Arc<T> {
+    type Base = T;
+    fn into_ptr(me: Arc<T>) -> *mut T {
+        Arc::into_raw(me) as *mut T
+    }
+    fn as_ptr(me: &Arc<T>) -> *mut T {
+        Arc::as_ptr(me) as *mut T
+    }
+    unsafe fn from_ptr(ptr: *const T) -> Arc<T> {
+        Arc::from_raw(ptr)
+    }
+}
+
+// The pointer is to the header and the length is stored in there, so we don't need the fat
+// pointer.
+unsafe impl<H, T> RefCnt for ThinArc<H, T> {
+    type Base = c_void;
+    fn into_ptr(me: Self) -> *mut c_void {
+        ThinArc::into_raw(me) as *mut c_void
+    }
+    fn as_ptr(me: &Self) -> *mut c_void {
+        ThinArc::as_ptr(me) as *mut c_void
+    }
+    unsafe fn from_ptr(ptr: *const c_void) -> Self {
+        ThinArc::from_raw(ptr)
+    }
+}
+
+impl<T, S: Strategy<Arc<T>>> Deref for DirectDeref<Arc<T>, S> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.0.deref().deref()
+    }
+}
+
+impl<T, S: Strategy<Arc<T>>> Access<T> for ArcSwapAny<Arc<T>, S> {
+    type Guard = DirectDeref<Arc<T>, S>;
+    fn load(&self) -> Self::Guard {
+        DirectDeref(self.load())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::Map;
+    use crate::cache::Cache;
+
+    #[test]
+    fn load_store() {
+        let shared: ArcSwapAny<_> = ArcSwapAny::new(Arc::new(1));
+        let first = shared.load_full();
+        shared.store(Arc::new(2));
+        assert_eq!(1, *first);
+        assert_eq!(2, **shared.load());
+
+        let prev = shared.compare_and_swap(&first, Arc::new(3));
+        assert_eq!(2, **prev);
+        let prev = shared.compare_and_swap(&prev, Arc::new(3));
+        assert_eq!(2, **prev);
+        assert_eq!(3, **shared.load());
+        drop(prev);
+        assert!(first.is_unique());
+    }
+
+    #[test]
+    fn access_and_cache() {
+        struct Cfg {
+            value: usize,
+        }
+
+        let shared: ArcSwapAny<_> = ArcSwapAny::new(Arc::new(Cfg { value: 1 }));
+        let mapped = shared.map(|cfg: &Cfg| &cfg.value);
+        let mut cache = Cache::new(&shared);
+        assert_eq!(1, *Access::<usize>::load(&mapped));
+        assert_eq!(1, cache.load().value);
+
+        shared.store(Arc::new(Cfg { value: 2 }));
+        assert_eq!(2, *Access::<usize>::load(&mapped));
+        assert_eq!(2, cache.load().value);
+    }
+
+    #[test]
+    fn thin_arc() {
+        let first = ThinArc::from_header_and_slice("first", &[1, 2, 3]);
+        let shared: ArcSwapAny<_> = ArcSwapAny::new(first.clone());
+        let loaded = shared.load();
+        assert_eq!("first", loaded.header.header);
+        assert_eq!([1, 2, 3], loaded.slice);
+
+        let second = ThinArc::from_header_and_slice("second", &[4]);
+        let prev = shared.swap(second.clone());
+        assert_eq!(first.as_ptr(), prev.as_ptr());
+        let prev = shared.compare_and_swap(&first, first.clone());
+        assert_eq!(second.as_ptr(), prev.as_ptr());
+        assert_eq!([4], shared.load().slice);
+    }
+
+    #[test]
+    fn thin_arc_map() {
+        type Thin = ThinArc<&'static str, usize>;
+        let shared: ArcSwapAny<_> = ArcSwapAny::new(Thin::from_header_and_slice("first", &[1, 2]));
+        let header = shared.map(|thin: &Thin| &thin.header.header);
+        let last = Map::new(&shared, |thin: &Thin| thin.slice.last().unwrap());
+        assert_eq!("first", *header.load());
+        assert_eq!(2, *last.load());
+
+        shared.store(Thin::from_header_and_slice("second", &[3]));
+        assert_eq!("second", *header.load());
+        assert_eq!(3, *last.load());
+    }
+}
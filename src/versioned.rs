@@ -0,0 +1,352 @@
+//! Storage that knows which revision of the value it holds.
+//!
+//! See [`VersionedArcSwap`].
+
+use alloc::sync::Arc;
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+use core::ops::Deref;
+
+use crate::{ArcSwapAny, Guard, RefCnt};
+
+/// The value together with its version, as stored inside.
+struct Entry<T> {
+    version: u64,
+    value: T,
+}
+
+impl<T> Entry<T> {
+    fn new(version: u64, value: T) -> Arc<Self> {
+        Arc::new(Self { version, value })
+    }
+
+    /// Extracts the value back from an entry we just created and that got rejected.
+    fn into_value(entry: Arc<Self>) -> T {
+        match Arc::try_unwrap(entry) {
+            Ok(entry) => entry.value,
+            Err(_) => unreachable!("Rejected entry is not shared with anyone"),
+        }
+    }
+}
+
+impl<T: Clone> Entry<T> {
+    /// Extracts the value from an entry, moving it out if nobody else holds the entry.
+    fn take_value(entry: Arc<Self>) -> T {
+        match Arc::try_unwrap(entry) {
+            Ok(entry) => entry.value,
+            Err(shared) => T::clone(&shared.value),
+        }
+    }
+}
+
+/// An [`ArcSwapAny`] with a generation counter.
+///
+/// Every successful write bumps the version of the held value, even if the same pointer is stored
+/// again. That allows to tell which revision of the value one holds and to detect changes where
+/// pointer identity is not enough (the ABA problem).
+///
+/// The version and value are kept together, so the [`load`] always gets a matching pair.
+///
+/// This is more expensive than the plain [`ArcSwapAny`] ‒ each write allocates and the reads go
+/// through another level of indirection.
+///
+/// The version never wraps around. Once it reaches `u64::MAX`, the writes that would need to bump
+/// it further fail and hand the value back.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::sync::Arc;
+/// use arc_swap::versioned::VersionedArcSwap;
+///
+/// let shared = VersionedArcSwap::new(Arc::new(1));
+/// let config = Arc::new(2);
+/// assert_eq!(1, shared.store(Arc::clone(&config)).unwrap());
+/// assert_eq!(2, shared.store(Arc::clone(&config)).unwrap());
+///
+/// let current = shared.load();
+/// assert_eq!(2, current.version());
+/// assert_eq!(2, **current);
+///
+/// // Some lagging replicator with an old version is refused.
+/// assert!(shared.store_if_newer(1, Arc::new(3)).is_err());
+/// assert!(shared.store_if_newer(10, Arc::new(3)).is_ok());
+/// assert_eq!(10, shared.version());
+/// ```
+///
+/// [`load`]: VersionedArcSwap::load
+pub struct VersionedArcSwap<T: RefCnt> {
+    inner: ArcSwapAny<Arc<Entry<T>>>,
+}
+
+/// A loaded value of [`VersionedArcSwap`], together with its version.
+///
+/// It dereferences to the held value.
+pub struct VersionedGuard<T: RefCnt> {
+    inner: Guard<Arc<Entry<T>>>,
+}
+
+impl<T: RefCnt> VersionedGuard<T> {
+    fn new(inner: Guard<Arc<Entry<T>>>) -> Self {
+        Self { inner }
+    }
+
+    /// The version of the held value.
+    pub fn version(&self) -> u64 {
+        self.inner.version
+    }
+
+    /// Converts it into the held value.
+    // Associated function on purpose, because of deref
+    #[allow(clippy::wrong_self_convention)]
+    pub fn into_inner(guard: Self) -> T {
+        Entry::take_value(Guard::into_inner(guard.inner))
+    }
+}
+
+impl<T: RefCnt> Deref for VersionedGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner.value
+    }
+}
+
+impl<T: RefCnt + Debug> Debug for VersionedGuard<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_struct("VersionedGuard")
+            .field("version", &self.version())
+            .field("value", &self.inner.value)
+            .finish()
+    }
+}
+
+impl<T: RefCnt> From<T> for VersionedArcSwap<T> {
+    fn from(val: T) -> Self {
+        Self::new(val)
+    }
+}
+
+impl<T: RefCnt + Default> Default for VersionedArcSwap<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: RefCnt + Debug> Debug for VersionedArcSwap<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_tuple("VersionedArcSwap")
+            .field(&self.load())
+            .finish()
+    }
+}
+
+impl<T: RefCnt> VersionedArcSwap<T> {
+    /// Creates a new storage, with the version 0.
+    pub fn new(val: T) -> Self {
+        Self::with_version(0, val)
+    }
+
+    /// Creates a new storage, starting at the given version.
+    pub fn with_version(version: u64, val: T) -> Self {
+        Self {
+            inner: ArcSwapAny::new(Entry::new(version, val)),
+        }
+    }
+
+    /// Extracts the value inside.
+    pub fn into_inner(self) -> T {
+        Entry::take_value(self.inner.into_inner())
+    }
+
+    /// Loads the value together with its version.
+    pub fn load(&self) -> VersionedGuard<T> {
+        VersionedGuard::new(self.inner.load())
+    }
+
+    /// Loads the value and its version, as an owned value.
+    pub fn load_full(&self) -> (u64, T) {
+        let entry = self.inner.load();
+        (entry.version, T::clone(&entry.value))
+    }
+
+    /// The current version.
+    pub fn version(&self) -> u64 {
+        self.inner.load().version
+    }
+
+    /// Stores a new value, bumping the version by one.
+    ///
+    /// Returns the version of the newly stored value. If the current version is already
+    /// `u64::MAX`, the value is handed back instead.
+    pub fn store(&self, val: T) -> Result<u64, T> {
+        let mut cur = self.inner.load();
+        let mut new = Entry::new(cur.version, val);
+        loop {
+            let version = match cur.version.checked_add(1) {
+                Some(version) => version,
+                None => return Err(Entry::into_value(new)),
+            };
+            // Nobody else has seen the entry yet.
+            Arc::get_mut(&mut new)
+                .expect("Our new entry is not shared with anyone")
+                .version = version;
+            match self.inner.compare_exchange(&*cur, new) {
+                Ok(_) => return Ok(version),
+                Err((actual, rejected)) => {
+                    cur = actual;
+                    new = rejected;
+                }
+            }
+        }
+    }
+
+    /// Stores the value with the given version, if it is newer than the current one.
+    ///
+    /// This is meant for taking over values from another, versioned, source. If the `version` is
+    /// not greater than the current version, the value is handed back.
+    ///
+    /// Note that storing `u64::MAX` makes all the further writes except this one fail.
+    pub fn store_if_newer(&self, version: u64, val: T) -> Result<(), T> {
+        let mut val = val;
+        loop {
+            let cur = self.inner.load();
+            if cur.version >= version {
+                return Err(val);
+            }
+            match self.inner.compare_exchange(&*cur, Entry::new(version, val)) {
+                Ok(_) => return Ok(()),
+                // Someone else changed it in the meantime, check again.
+                Err((_, rejected)) => val = Entry::into_value(rejected),
+            }
+        }
+    }
+
+    /// Stores the `new` value, if the current version is `expected_version`.
+    ///
+    /// On success, the previous value is returned (and the new one has version
+    /// `expected_version + 1`). On failure, the current value and the rejected new one are
+    /// returned. This always fails if the `expected_version` is `u64::MAX`, as the version can't
+    /// be bumped any more.
+    ///
+    /// Unlike [`ArcSwapAny::compare_exchange`], this is not fooled by the same pointer being
+    /// stored again in the meantime.
+    #[allow(clippy::type_complexity)]
+    pub fn compare_and_swap_version(
+        &self,
+        expected_version: u64,
+        new: T,
+    ) -> Result<VersionedGuard<T>, (VersionedGuard<T>, T)> {
+        let mut new = new;
+        loop {
+            let cur = self.inner.load();
+            let version = match expected_version.checked_add(1) {
+                Some(version) if cur.version == expected_version => version,
+                _ => return Err((VersionedGuard::new(cur), new)),
+            };
+            // We hold the entry in cur, so it can't be freed and its address reused by another
+            // entry. Therefore comparing the pointer is enough here.
+            match self.inner.compare_exchange(&*cur, Entry::new(version, new)) {
+                Ok(prev) => return Ok(VersionedGuard::new(prev)),
+                // The version changed in the meantime, the next round returns it.
+                Err((_, rejected)) => new = Entry::into_value(rejected),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_pointer_new_version() {
+        let val = Arc::new(42);
+        let shared = VersionedArcSwap::new(Arc::clone(&val));
+        let first = shared.load();
+        assert_eq!(0, first.version());
+        assert_eq!(1, shared.store(Arc::clone(&val)).unwrap());
+        // The same pointer, but the CaS on the old version must fail.
+        let (cur, rejected) = shared.compare_and_swap_version(0, Arc::new(0)).unwrap_err();
+        assert_eq!(1, cur.version());
+        assert!(Arc::ptr_eq(&val, &cur));
+        assert_eq!(0, *rejected);
+        drop(cur);
+
+        let prev = shared
+            .compare_and_swap_version(1, Arc::clone(&val))
+            .unwrap();
+        assert_eq!(1, prev.version());
+        assert_eq!(2, shared.version());
+        drop((first, prev));
+        // One in val, one in shared
+        assert_eq!(2, Arc::strong_count(&val));
+    }
+
+    #[test]
+    fn store_if_newer() {
+        let shared = VersionedArcSwap::with_version(5, Arc::new(0));
+        assert_eq!(1, *shared.store_if_newer(5, Arc::new(1)).unwrap_err());
+        assert_eq!(2, *shared.store_if_newer(3, Arc::new(2)).unwrap_err());
+        shared.store_if_newer(7, Arc::new(3)).unwrap();
+        assert_eq!((7, Arc::new(3)), shared.load_full());
+        assert_eq!(8, shared.store(Arc::new(4)).unwrap());
+        assert_eq!(4, *shared.into_inner());
+    }
+
+    #[test]
+    fn into_inner_moves() {
+        let val = Arc::new(0);
+        let shared = VersionedArcSwap::new(Arc::clone(&val));
+        assert!(Arc::ptr_eq(&val, &shared.into_inner()));
+        assert_eq!(1, Arc::strong_count(&val));
+
+        let shared = VersionedArcSwap::new(Arc::clone(&val));
+        let guard = shared.load();
+        // Still shared with the storage, so this one falls back to a clone
+        assert!(Arc::ptr_eq(&val, &VersionedGuard::into_inner(guard)));
+        let guard = shared.load();
+        drop(shared);
+        assert!(Arc::ptr_eq(&val, &VersionedGuard::into_inner(guard)));
+        assert_eq!(1, Arc::strong_count(&val));
+    }
+
+    #[test]
+    fn version_overflow() {
+        let shared = VersionedArcSwap::with_version(u64::MAX - 1, Arc::new(0));
+        assert_eq!(u64::MAX, shared.store(Arc::new(1)).unwrap());
+        assert_eq!(2, *shared.store(Arc::new(2)).unwrap_err());
+        let (cur, rejected) = shared
+            .compare_and_swap_version(u64::MAX, Arc::new(3))
+            .unwrap_err();
+        assert_eq!(u64::MAX, cur.version());
+        assert_eq!(3, *rejected);
+        assert_eq!(
+            4,
+            *shared.store_if_newer(u64::MAX, Arc::new(4)).unwrap_err()
+        );
+        assert_eq!((u64::MAX, Arc::new(1)), shared.load_full());
+    }
+
+    #[test]
+    fn concurrent_stores() {
+        const THREADS: usize = 8;
+        const ITERATIONS: usize = 100;
+        let shared = VersionedArcSwap::new(Arc::new(0));
+        crossbeam_utils::thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|_| {
+                    let mut last = 0;
+                    for i in 0..ITERATIONS {
+                        let version = shared.store(Arc::new(i)).unwrap();
+                        assert!(version > last);
+                        last = version;
+                        assert!(shared.load().version() >= version);
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert_eq!((THREADS * ITERATIONS) as u64, shared.version());
+    }
+}
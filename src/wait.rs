@@ -0,0 +1,223 @@
+//! Blocking wait for a change.
+//!
+//! The waiting threads are kept in a global "parking lot", split into buckets by the address of
+//! the storage they wait on. Each bucket counts its waiting threads in `waiting` and lists them
+//! (with the address they wait on) under a mutex. Writers look at the counter of the bucket of
+//! their instance only and, if anyone waits there, unpark the threads waiting on the same address.
+//! As long as nobody waits on an instance sharing the bucket, the cost for the writer is just the
+//! fence and a load of the counter. The woken up threads check their own condition and go to
+//! sleep again if it's not satisfied yet.
+//!
+//! The parking lot is created by the first waiter. Until then, the writers only do a relaxed
+//! load of the pointer to it and don't even issue the fence. This means a writer may miss the
+//! very first waiters for a short while (there's no fence on its side that would order its write
+//! before the load of the pointer). Therefore the waiters poll the storage (by parking with a
+//! short timeout) for a while after the parking lot was created.
+//!
+//! The ordering argument: the writer first changes the storage and then (after the SeqCst fence)
+//! reads the counter. The waiter first registers itself in the list, increments the counter
+//! (SeqCst) and then (after another fence) reads the storage. Therefore, either the writer sees
+//! the waiter and unparks it, or the waiter sees the new value. The unpark is not lost even if it
+//! comes between checking the condition and parking, the park then returns right away.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::Ordering::*;
+use std::sync::atomic::{self, AtomicPtr, AtomicUsize};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+const BUCKET_CNT: usize = 16;
+
+/// One waiting thread.
+struct Waiter {
+    /// Address of the storage it waits on.
+    addr: usize,
+    id: usize,
+    thread: Thread,
+}
+
+#[derive(Default)]
+struct Waiters {
+    next_id: usize,
+    list: Vec<Waiter>,
+}
+
+#[derive(Default)]
+#[repr(align(64))]
+struct Bucket {
+    /// Number of threads in the list.
+    waiting: AtomicUsize,
+    waiters: Mutex<Waiters>,
+}
+
+impl Bucket {
+    fn lock(&self) -> MutexGuard<'_, Waiters> {
+        // We don't panic while holding it, but be robust anyway.
+        self.waiters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// For how long after the creation of the parking lot the waiters poll.
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// How often the waiters poll during the [`SETTLE`] time.
+const POLL: Duration = Duration::from_millis(1);
+
+struct Parking {
+    buckets: [Bucket; BUCKET_CNT],
+    created: Instant,
+}
+
+impl Default for Parking {
+    fn default() -> Self {
+        Self {
+            buckets: Default::default(),
+            created: Instant::now(),
+        }
+    }
+}
+
+impl Parking {
+    fn bucket(&self, addr: usize) -> &Bucket {
+        // Instances next to each other (eg. in the same struct) should land in different buckets.
+        &self.buckets[(addr / mem::size_of::<usize>()) % BUCKET_CNT]
+    }
+}
+
+/// The lazily-created global parking lot.
+static PARKING: AtomicPtr<Parking> = AtomicPtr::new(ptr::null_mut());
+
+fn parking() -> &'static Parking {
+    let current = PARKING.load(Acquire);
+    if let Some(parking) = unsafe { current.as_ref() } {
+        return parking;
+    }
+    let new = Box::into_raw(Box::<Parking>::default());
+    match PARKING.compare_exchange(ptr::null_mut(), new, AcqRel, Acquire) {
+        // It's leaked on purpose, it lives for the rest of the program.
+        Ok(_) => unsafe { &*new },
+        Err(other) => {
+            // Someone was faster, use theirs.
+            drop(unsafe { Box::from_raw(new) });
+            unsafe { &*other }
+        }
+    }
+}
+
+/// Wakes up the threads waiting on the storage at `addr` after a change.
+#[inline]
+pub(crate) fn notify(addr: usize) {
+    // Nobody ever waited anywhere -> the parking lot doesn't even exist and we are done. Relaxed
+    // is fine, the waiters poll for a while after creating it in case we miss it.
+    if PARKING.load(Relaxed).is_null() {
+        return;
+    }
+    notify_parked(addr);
+}
+
+#[inline(never)]
+fn notify_parked(addr: usize) {
+    atomic::fence(SeqCst);
+    // Acquire to see the initialized buckets.
+    let parking = unsafe { &*PARKING.load(Acquire) };
+    let bucket = parking.bucket(addr);
+    if bucket.waiting.load(SeqCst) > 0 {
+        notify_slow(bucket, addr);
+    }
+}
+
+#[cold]
+fn notify_slow(bucket: &Bucket, addr: usize) {
+    let waiters = bucket.lock();
+    waiters
+        .list
+        .iter()
+        .filter(|waiter| waiter.addr == addr)
+        .for_each(|waiter| waiter.thread.unpark());
+}
+
+/// Unregisters the waiting thread, even on panic.
+struct Registration {
+    bucket: &'static Bucket,
+    id: usize,
+}
+
+impl Registration {
+    fn new(parking: &'static Parking, addr: usize) -> Self {
+        let bucket = parking.bucket(addr);
+        let id = {
+            let mut waiters = bucket.lock();
+            let id = waiters.next_id;
+            waiters.next_id = waiters.next_id.wrapping_add(1);
+            waiters.list.push(Waiter {
+                addr,
+                id,
+                thread: thread::current(),
+            });
+            id
+        };
+        // Only after it is in the list, so a writer seeing the counter also finds it there.
+        bucket.waiting.fetch_add(1, SeqCst);
+        Registration { bucket, id }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.bucket.waiting.fetch_sub(1, SeqCst);
+        self.bucket
+            .lock()
+            .list
+            .retain(|waiter| waiter.id != self.id);
+    }
+}
+
+/// Forgets the waiting threads that no longer exist (after a `fork`).
+///
+/// One of them might have held a lock of the parking lot, so a new one is used (the old one is
+/// leaked).
+#[cfg(unix)]
+pub(crate) fn after_fork_child() {
+    PARKING.store(ptr::null_mut(), SeqCst);
+}
+
+/// Waits until `check` returns `Some`, or until the timeout passes (in which case `None` is
+/// returned).
+///
+/// The `check` is called once right away and then each time there was some change to the storage
+/// at `addr` (possibly also at other times).
+pub(crate) fn wait<R, F>(addr: usize, mut check: F, timeout: Duration) -> Option<R>
+where
+    F: FnMut() -> Option<R>,
+{
+    // No deadline on overflow ‒ it is as good as forever.
+    let deadline = Instant::now().checked_add(timeout);
+    let parking = parking();
+    let _registration = Registration::new(parking, addr);
+    atomic::fence(SeqCst);
+    loop {
+        // The check is called without any lock, it may want to do some writes itself.
+        if let Some(result) = check() {
+            return Some(result);
+        }
+        let now = Instant::now();
+        // Don't get stuck in here with a busy writer that never satisfies the check.
+        if deadline.map_or(false, |deadline| now >= deadline) {
+            return None;
+        }
+        // The writers might not see the parking lot yet, don't rely on them waking us up.
+        let settling = now.duration_since(parking.created) < SETTLE;
+        let park = match deadline {
+            Some(deadline) if settling => Some(POLL.min(deadline - now)),
+            Some(deadline) => Some(deadline - now),
+            None if settling => Some(POLL),
+            None => None,
+        };
+        match park {
+            Some(park) => thread::park_timeout(park),
+            None => thread::park(),
+        }
+    }
+}
@@ -1,6 +1,6 @@
-use std::ptr;
-use std::rc::Weak as RcWeak;
-use std::sync::Weak;
+use alloc::rc::Weak as RcWeak;
+use alloc::sync::Weak;
+use core::ptr;
 
 use crate::RefCnt;
 
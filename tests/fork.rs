@@ -0,0 +1,94 @@
+//! The child process after `fork` can use the instances and reuses the nodes of the threads that
+//! didn't make it into the child.
+#![cfg(target_os = "linux")]
+
+use std::panic;
+use std::sync::mpsc;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+
+use arc_swap::{diagnostics, nodes, ArcSwap};
+
+extern "C" {
+    fn fork() -> i32;
+    fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+    fn _exit(status: i32) -> !;
+}
+
+const THREADS: usize = 4;
+// More than the fast slots, so the helping ones are used too.
+const GUARDS: usize = 10;
+
+fn child(shared: &ArcSwap<usize>) {
+    let count = nodes::count();
+    // With the hook, the fork itself already did the reset.
+    if !cfg!(feature = "fork-hook") {
+        assert!(diagnostics::report().used() >= THREADS);
+        unsafe { arc_swap::after_fork_child() };
+    }
+
+    // Only this thread (possibly) keeps its node.
+    let own = diagnostics::thread_has_node() as usize;
+    assert_eq!(own, diagnostics::report().used());
+    assert_eq!(count, nodes::count());
+
+    let guard = shared.load();
+    shared.store(Arc::new(2));
+    assert_eq!(1, **guard);
+    assert_eq!(2, **shared.load());
+
+    // New threads get the nodes left behind (this thread took one of them by now).
+    let barrier = Barrier::new(THREADS - 1);
+    thread::scope(|s| {
+        for _ in 0..THREADS - 1 {
+            s.spawn(|| {
+                let guards = (0..GUARDS).map(|_| shared.load()).collect::<Vec<_>>();
+                barrier.wait();
+                assert!(guards.iter().all(|g| ***g == 2));
+            });
+        }
+    });
+    assert_eq!(count, nodes::count());
+
+    shared.rcu(|v| **v + 1);
+    assert_eq!(3, **shared.load());
+}
+
+#[test]
+fn use_after_fork() {
+    let shared = ArcSwap::from_pointee(1);
+    let (release, released) = mpsc::channel::<()>();
+    let released = Arc::new(Mutex::new(released));
+    let ready = Arc::new(Barrier::new(THREADS + 1));
+
+    thread::scope(|s| {
+        // Threads holding their nodes (and some debts in them) at the time of the fork.
+        for _ in 0..THREADS {
+            let released = Arc::clone(&released);
+            let ready = Arc::clone(&ready);
+            let shared = &shared;
+            s.spawn(move || {
+                let guards = (0..GUARDS).map(|_| shared.load()).collect::<Vec<_>>();
+                ready.wait();
+                let _ = released.lock().unwrap().recv();
+                drop(guards);
+            });
+        }
+        ready.wait();
+
+        match unsafe { fork() } {
+            -1 => panic!("Fork failed"),
+            0 => {
+                let result = panic::catch_unwind(panic::AssertUnwindSafe(|| child(&shared)));
+                unsafe { _exit(result.is_err() as i32) };
+            }
+            pid => {
+                let mut status = 0;
+                let waited = unsafe { waitpid(pid, &mut status, 0) };
+                drop(release);
+                assert_eq!(pid, waited);
+                assert_eq!(0, status, "The child failed");
+            }
+        }
+    });
+}
@@ -0,0 +1,205 @@
+//! Model checking of the lock-free algorithms.
+//!
+//! Unlike the stress tests, which hope to hit a race condition by chance, these go through the
+//! possible interleavings of the threads (and reorderings of the memory accesses) exhaustively,
+//! up to some number of preemptions. They need the crate to be built with loom:
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom
+//! ```
+//!
+//! The `LOOM_MAX_PREEMPTIONS` environment variable can raise the bound (at the cost of a lot
+//! longer run time).
+#![cfg(loom)]
+
+use std::sync::{Arc, Weak};
+
+use arc_swap::strategy::{CaS, DefaultStrategy, IndependentStrategy};
+use arc_swap::{nodes, ArcSwapAny, Guard};
+use loom::model::Builder;
+use loom::thread;
+
+/// Number of guards a thread can hold before the loads fall back to the helping slot.
+///
+/// Keep in sync with the debt::fast module.
+const FAST_SLOTS: usize = 8;
+
+fn model<F: Fn() + Sync + Send + 'static>(f: F) {
+    let mut builder = Builder::new();
+    if builder.preemption_bound.is_none() {
+        builder.preemption_bound = Some(2);
+    }
+    builder.check(f);
+}
+
+/// A value that checks it's still alive when looked at.
+///
+/// This is not bulletproof (reading freed memory is UB after all), but it likely catches a guard
+/// pointing to an already dropped value.
+struct Value {
+    num: usize,
+    alive: bool,
+}
+
+impl Value {
+    fn new(num: usize) -> Arc<Self> {
+        Arc::new(Value { num, alive: true })
+    }
+
+    fn check(&self) -> usize {
+        assert!(self.alive);
+        self.num
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        self.alive = false;
+    }
+}
+
+type Shared<S> = ArcSwapAny<Arc<Value>, S>;
+
+fn store_load<S: CaS<Arc<Value>> + Default + Send + Sync + 'static>() {
+    model(|| {
+        let first = Value::new(1);
+        let first_weak = Arc::downgrade(&first);
+        let shared = Arc::new(Shared::<S>::new(first));
+
+        let writer = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.store(Value::new(2)))
+        };
+
+        let guard = shared.load();
+        let num = guard.check();
+        assert!(num == 1 || num == 2);
+        drop(guard);
+
+        writer.join().unwrap();
+        assert_eq!(2, shared.load().check());
+        // Nobody holds the old one any more (no debt left unpaid).
+        assert_eq!(0, Weak::strong_count(&first_weak));
+    });
+}
+
+#[test]
+fn store_load_default() {
+    store_load::<DefaultStrategy>();
+}
+
+#[test]
+fn store_load_domain() {
+    store_load::<IndependentStrategy>();
+}
+
+/// A guard outlives the replacement of the value.
+#[test]
+fn guard_outlives_store() {
+    model(|| {
+        let first = Value::new(1);
+        let first_weak = Arc::downgrade(&first);
+        let shared = Arc::new(Shared::<DefaultStrategy>::new(first));
+
+        let reader = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let guard = shared.load();
+                thread::yield_now();
+                guard.check()
+            })
+        };
+
+        let old = shared.swap(Value::new(2));
+        assert_eq!(1, old.check());
+        drop(old);
+
+        let seen = reader.join().unwrap();
+        assert!(seen == 1 || seen == 2);
+        assert_eq!(0, Weak::strong_count(&first_weak));
+    });
+}
+
+#[test]
+fn compare_and_swap_race() {
+    model(|| {
+        let first = Value::new(0);
+        let shared = Arc::new(Shared::<DefaultStrategy>::new(Arc::clone(&first)));
+
+        let threads = (1..=2)
+            .map(|num| {
+                let shared = Arc::clone(&shared);
+                let first = Arc::clone(&first);
+                thread::spawn(move || {
+                    let prev = shared.compare_and_swap(&first, Value::new(num));
+                    Arc::ptr_eq(&*prev, &first)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let successes = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .filter(|&succeeded| succeeded)
+            .count();
+        assert_eq!(1, successes);
+        let current = shared.load().check();
+        assert!(current == 1 || current == 2);
+        assert_eq!(1, Arc::strong_count(&first));
+    });
+}
+
+/// The reader runs out of the fast slots, so it goes through the helping slot, possibly being
+/// helped by the concurrent writer.
+#[test]
+fn helping() {
+    model(|| {
+        let first = Value::new(1);
+        let first_weak = Arc::downgrade(&first);
+        let shared = Arc::new(Shared::<DefaultStrategy>::new(first));
+
+        let reader = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let held = (0..FAST_SLOTS)
+                    .map(|_| shared.load())
+                    .collect::<Vec<Guard<_>>>();
+                let guard = shared.load();
+                let num = guard.check();
+                assert!(held.iter().all(|held| held.check() <= num));
+                num
+            })
+        };
+
+        shared.store(Value::new(2));
+
+        let seen = reader.join().unwrap();
+        assert!(seen == 1 || seen == 2);
+        assert_eq!(2, shared.load().check());
+        assert_eq!(0, Weak::strong_count(&first_weak));
+    });
+}
+
+/// Freeing the unused nodes while someone is walking the list.
+#[test]
+fn reclaim() {
+    model(|| {
+        let first = Value::new(1);
+        let first_weak = Arc::downgrade(&first);
+        let shared = Arc::new(Shared::<DefaultStrategy>::new(first));
+
+        // Leaves its node behind.
+        {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.load().check()).join().unwrap();
+        }
+
+        let reclaimer = thread::spawn(nodes::reclaim);
+
+        shared.store(Value::new(2));
+        assert_eq!(2, shared.load().check());
+
+        reclaimer.join().unwrap();
+        assert_eq!(0, Weak::strong_count(&first_weak));
+    });
+}
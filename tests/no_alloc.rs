@@ -0,0 +1,93 @@
+//! Checks the loads don't allocate once the nodes are preallocated.
+//!
+//! This needs its own global allocator, therefore its own test binary. There's just one test in
+//! here, as it changes global settings.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use arc_swap::{nodes, ArcSwap, Cache};
+
+/// Counts the allocations made by each thread.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+const NODES: usize = 4;
+// More threads than nodes, some of them have to use the fallback.
+const THREADS: usize = 8;
+// More than the fast slots of a node.
+const GUARDS: usize = 20;
+
+#[test]
+fn loads_without_allocations() {
+    let shared = Arc::new(ArcSwap::from_pointee(42));
+    arc_swap::preallocate(NODES);
+    nodes::set_allocating(false);
+    let count = nodes::count();
+    assert!(count >= NODES);
+
+    // Each thread holds its guards until all of them loaded, so the nodes are all taken.
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let threads = (0..THREADS)
+        .map(|_| {
+            let shared = Arc::clone(&shared);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                #[cfg(feature = "metrics")]
+                arc_swap::metrics::thread();
+                let mut guards = Vec::with_capacity(GUARDS);
+
+                let before = allocations();
+                let mut cache = Cache::new(&*shared);
+                for _ in 0..GUARDS {
+                    guards.push(shared.load());
+                }
+                let full = shared.load_full();
+                let cached = **cache.load();
+                let after = allocations();
+
+                barrier.wait();
+                assert_eq!(before, after);
+                assert!(guards.iter().all(|g| ***g == 42));
+                assert_eq!(42, *full);
+                assert_eq!(42, cached);
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // No nodes were added and the preallocated ones are kept even after the threads terminated.
+    assert_eq!(count, nodes::count());
+
+    // The writers still work with the readers holding the guards.
+    let guard = shared.load();
+    shared.store(Arc::new(43));
+    assert_eq!(42, **guard);
+    assert_eq!(43, **shared.load());
+    nodes::set_allocating(true);
+}
@@ -7,8 +7,10 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
 use adaptive_barrier::{Barrier, PanicMode};
+use arc_swap::snapshot::load_consistent;
 use arc_swap::strategy::{CaS, DefaultStrategy, IndependentStrategy, Strategy};
-use arc_swap::ArcSwapAny;
+use arc_swap::transaction::Transaction;
+use arc_swap::{nodes, ArcSwap, ArcSwapAny};
 use crossbeam_utils::thread;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
@@ -247,6 +249,68 @@ where
     assert_eq!(2, Arc::strong_count(&v));
 }
 
+/// Loads several instances at once, while a writer keeps updating them one by one (with plain
+/// writes and single-update transactions).
+///
+/// The writer always updates them in the same order, so a consistent snapshot may see at most the
+/// first few already updated, but never a newer value after an older one.
+fn load_consistent_parallel<S>(iters: usize)
+where
+    S: Default + CaS<Arc<usize>> + Send + Sync,
+{
+    let _lock = lock();
+    #[cfg(not(miri))]
+    let cpus = num_cpus::get();
+    #[cfg(miri)]
+    let cpus = 2;
+    let shared = (0..3)
+        .map(|_| ArcSwapAny::<_, S>::from(Arc::new(0)))
+        .collect::<Vec<_>>();
+    thread::scope(|scope| {
+        scope.spawn(|_| {
+            for i in 1..=iters {
+                for instance in &shared {
+                    // All kinds of writes are detected by the readers.
+                    match i % 3 {
+                        0 => instance.store(Arc::new(i)),
+                        1 => drop(instance.rcu(|_| i)),
+                        _ => {
+                            let mut transaction = Transaction::new();
+                            transaction.swap(instance, Arc::new(i));
+                            transaction.commit().unwrap();
+                        }
+                    }
+                }
+            }
+        });
+        for _ in 0..cpus {
+            scope.spawn(|_| {
+                let mut last = 0;
+                while last < iters {
+                    // Load them in the opposite order than the writer writes them, so the plain
+                    // loads would likely get torn.
+                    let (c, b, a) = load_consistent((&shared[2], &shared[1], &shared[0]));
+                    assert!(
+                        **a >= **b && **b >= **c && **a - **c <= 1,
+                        "Torn snapshot {} {} {}",
+                        a,
+                        b,
+                        c,
+                    );
+                    assert!(**c >= last);
+                    last = **c;
+                }
+            });
+        }
+    })
+    .unwrap();
+    for instance in &shared {
+        let v = instance.load_full();
+        assert_eq!(iters, *v);
+        assert_eq!(2, Arc::strong_count(&v));
+    }
+}
+
 #[cfg(not(miri))]
 const ITER_SMALL: usize = 100;
 #[cfg(not(miri))]
@@ -297,6 +361,17 @@ macro_rules! t {
             fn load_parallel_large() {
                 load_parallel::<Strategy>(100_000);
             }
+
+            #[test]
+            fn load_consistent_parallel_small() {
+                load_consistent_parallel::<Strategy>(ITER_MID);
+            }
+
+            #[test]
+            #[ignore]
+            fn load_consistent_parallel_large() {
+                load_consistent_parallel::<Strategy>(100_000);
+            }
         }
     };
 }
@@ -308,3 +383,99 @@ t!(
     full_slots,
     arc_swap::strategy::test_strategies::FillFastSlots
 );
+
+/// The nodes of terminated threads get freed, so the writers don't walk them forever.
+#[test]
+fn reclaim_nodes() {
+    let _lock = lock();
+    const THREADS: usize = 16;
+    let shared = ArcSwap::from_pointee(0);
+    let barr = Barrier::new(PanicMode::Poison);
+    let mut peak = 0;
+    thread::scope(|scope| {
+        for _ in 0..THREADS {
+            let mut barr = barr.clone();
+            let shared = &shared;
+            scope.spawn(move |_| {
+                let _guard = shared.load();
+                // Everyone holds a node now
+                barr.wait();
+                barr.wait();
+            });
+        }
+        let mut barr = barr;
+        barr.wait();
+        peak = nodes::count();
+        barr.wait();
+    })
+    .unwrap();
+    assert!(peak >= THREADS);
+    nodes::reclaim();
+    assert!(nodes::count() <= peak - THREADS);
+    // Still works
+    shared.store(Arc::new(1));
+    assert_eq!(1, **shared.load());
+}
+
+/// The terminating threads leave their nodes be, the writers free them later on.
+#[test]
+fn writers_reclaim_nodes() {
+    let _lock = lock();
+    const THREADS: usize = 16;
+    let shared = ArcSwap::from_pointee(0);
+    let barr = Barrier::new(PanicMode::Poison);
+    let mut peak = 0;
+    thread::scope(|scope| {
+        for _ in 0..THREADS {
+            let mut barr = barr.clone();
+            let shared = &shared;
+            scope.spawn(move |_| {
+                let _guard = shared.load();
+                barr.wait();
+                barr.wait();
+            });
+        }
+        let mut barr = barr;
+        barr.wait();
+        peak = nodes::count();
+        barr.wait();
+    })
+    .unwrap();
+    assert_eq!(peak, nodes::count());
+    // The first one unlinks them, the second one frees them.
+    shared.store(Arc::new(1));
+    shared.store(Arc::new(2));
+    // A few are kept in spare and one is now used by this thread.
+    assert!(nodes::count() <= peak - THREADS + 5);
+    assert_eq!(2, **shared.load());
+}
+
+/// With limited number of nodes, the threads without one still work correctly.
+#[test]
+fn limited_nodes() {
+    let _lock = lock();
+    #[cfg(not(miri))]
+    let (threads, iters) = (8, ITER_MID);
+    #[cfg(miri)]
+    let (threads, iters) = (3, ITER_SMALL);
+    // Make sure there are no spare nodes to claim, so most of the threads go without one.
+    nodes::reclaim();
+    nodes::set_limit(Some(1));
+    let shared = ArcSwap::from_pointee(0);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|_| {
+                for _ in 0..iters {
+                    let before = shared.load();
+                    shared.rcu(|v| **v + 1);
+                    assert!(**shared.load() > **before);
+                }
+            });
+        }
+    })
+    .unwrap();
+    nodes::set_limit(None);
+    let v = shared.load_full();
+    assert_eq!(threads * iters, *v);
+    assert_eq!(2, Arc::strong_count(&v));
+}